@@ -0,0 +1,149 @@
+//! Binary size breakdown of `cargo bloat --message-format json`, as unit results
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{input, UnitResult};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Bloat {
+    file_size: u64,
+    text_section_size: u64,
+    /// Without `--crates`
+    #[serde(default)]
+    functions: Vec<Entry>,
+    /// With `--crates`
+    #[serde(default)]
+    crates: Vec<Entry>,
+}
+#[derive(Deserialize)]
+struct Entry {
+    /// Crate of a function
+    #[serde(rename = "crate")]
+    krate: Option<String>,
+    name: String,
+    size: u64,
+}
+
+/// Harbormaster limits the names to 255 bytes
+const MAX_NAME: usize = 255;
+
+fn human(bytes: u64) -> String {
+    match bytes {
+        0..=1023 => format!("{} B", bytes),
+        1024..=1048575 => format!("{:.1} KiB", bytes as f64 / 1024.0),
+        _ => format!("{:.1} MiB", bytes as f64 / 1048576.0),
+    }
+}
+
+/// Parse the output, with a result for the total size and for each function or crate listed
+/// (see `cargo bloat -n`). The sizes are in the details.
+pub(crate) fn parse(path: &Path) -> anyhow::Result<Vec<UnitResult>> {
+    let bloat: Bloat = serde_json::from_str(&input::read_to_string(path)?)?;
+    let unit = |name: String, namespace: &str, details: String| UnitResult {
+        name,
+        result: "pass".into(),
+        namespace: Some(namespace.into()),
+        engine: Some("cargo-bloat".into()),
+        details: Some(details),
+        ..Default::default()
+    };
+    let mut units = vec![unit(
+        "binary size".into(),
+        "cargo-bloat",
+        format!(
+            "File: {} ({} bytes)\n.text section: {} ({} bytes)",
+            human(bloat.file_size),
+            bloat.file_size,
+            human(bloat.text_section_size),
+            bloat.text_section_size
+        ),
+    )];
+    let share = |size: u64| 100.0 * size as f64 / bloat.text_section_size.max(1) as f64;
+    for (namespace, entries) in [
+        ("cargo-bloat::functions", bloat.functions),
+        ("cargo-bloat::crates", bloat.crates),
+    ] {
+        for entry in entries {
+            let mut name = match &entry.krate {
+                Some(krate) if !entry.name.starts_with(krate.as_str()) => {
+                    format!("{} ({})", entry.name, krate)
+                }
+                _ => entry.name,
+            };
+            if name.len() > MAX_NAME {
+                let mut end = MAX_NAME - 3;
+                while !name.is_char_boundary(end) {
+                    end -= 1;
+                }
+                name.truncate(end);
+                name.push_str("...");
+            }
+            units.push(unit(
+                name,
+                namespace,
+                format!(
+                    "{} ({} bytes, {:.2}% of .text)",
+                    human(entry.size),
+                    entry.size,
+                    share(entry.size)
+                ),
+            ));
+        }
+    }
+    Ok(units)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    #[test]
+    fn sizes_in_the_details() {
+        let long = "x".repeat(300);
+        let json = serde_json::json!({
+            "file-size": 3145728,
+            "text-section-size": 2048,
+            "functions": [
+                {"crate": "std", "name": "std::io::Write::write_fmt", "size": 512},
+                {"crate": "regex", "name": "<T as core::fmt::Debug>::fmt", "size": 100},
+                {"name": long, "size": 10}
+            ]
+        });
+        let units = parse(&fixture("bloat.json", &json.to_string())).unwrap();
+        let names: Vec<_> = units.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(
+            names[..3],
+            [
+                "binary size",
+                "std::io::Write::write_fmt",
+                "<T as core::fmt::Debug>::fmt (regex)"
+            ]
+        );
+        assert_eq!(
+            units[0].details.as_deref(),
+            Some("File: 3.0 MiB (3145728 bytes)\n.text section: 2.0 KiB (2048 bytes)")
+        );
+        assert_eq!(
+            units[1].namespace.as_deref(),
+            Some("cargo-bloat::functions")
+        );
+        assert_eq!(
+            units[1].details.as_deref(),
+            Some("512 B (512 bytes, 25.00% of .text)")
+        );
+        assert_eq!(units[3].name.len(), MAX_NAME);
+        assert!(units[3].name.ends_with("..."));
+        assert!(units
+            .iter()
+            .all(|u| u.result == "pass" && u.duration_s.is_none()));
+
+        let json = r#"{"file-size": 10, "text-section-size": 0,
+            "crates": [{"name": "serde", "size": 4}]}"#;
+        let units = parse(&fixture("bloat-crates.json", json)).unwrap();
+        assert_eq!(units[1].name, "serde");
+        assert_eq!(units[1].namespace.as_deref(), Some("cargo-bloat::crates"));
+    }
+}
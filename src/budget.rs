@@ -0,0 +1,165 @@
+//! Budgets of warnings (`--max-warnings`), failing the build when they are exceeded
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use log::*;
+
+use crate::{paths, LintResult};
+
+/// Maximum number of warnings, in total, of a lint code or in a package
+#[derive(Debug, Clone)]
+pub(crate) struct Budget {
+    scope: Scope,
+    max: usize,
+}
+#[derive(Debug, Clone)]
+enum Scope {
+    All,
+    Code(String),
+    Package(String),
+}
+impl FromStr for Budget {
+    type Err = anyhow::Error;
+
+    /// `N`, `CODE=N` or `package:NAME=N`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (scope, max) = match s.rsplit_once('=') {
+            None => (Scope::All, s),
+            Some((scope, max)) => match scope.strip_prefix("package:") {
+                Some(package) => (Scope::Package(package.into()), max),
+                None => (Scope::Code(scope.into()), max),
+            },
+        };
+        Ok(Self {
+            scope,
+            max: max
+                .parse()
+                .with_context(|| format!("Expected N, CODE=N or package:NAME=N, got {}", s))?,
+        })
+    }
+}
+
+/// Descriptions of the exceeded budgets, with the packages and their directories relative to
+/// the repository root
+fn exceeded(
+    budgets: &[Budget],
+    lints: &[LintResult],
+    packages: &[(String, PathBuf)],
+) -> Vec<String> {
+    let warnings: Vec<_> = lints
+        .iter()
+        .filter(|l| l.severity.eq_ignore_ascii_case("warning"))
+        .collect();
+    budgets
+        .iter()
+        .filter_map(|budget| {
+            let (count, scope) = match &budget.scope {
+                Scope::All => (warnings.len(), String::new()),
+                Scope::Code(code) => (
+                    warnings.iter().filter(|l| &l.code == code).count(),
+                    format!(" of {}", code),
+                ),
+                Scope::Package(name) => (
+                    warnings
+                        .iter()
+                        .filter(|l| paths::package_of(packages, &l.path) == Some(name))
+                        .count(),
+                    format!(" in package {}", name),
+                ),
+            };
+            (count > budget.max).then(|| {
+                format!(
+                    "{} warnings{}, over the budget of {}",
+                    count, scope, budget.max
+                )
+            })
+        })
+        .collect()
+}
+
+/// Lint summarizing the exceeded budgets, if any. As an error, it fails the build with
+/// `--status auto`. The packages are those of the workspace containing `dir`.
+pub(crate) fn check(
+    dir: &Path,
+    budgets: &[Budget],
+    lints: &[LintResult],
+) -> anyhow::Result<Option<LintResult>> {
+    if budgets.is_empty() {
+        return Ok(None);
+    }
+    let workspace = paths::metadata(dir, None).and_then(|metadata| paths::package_dirs(&metadata));
+    let (packages, manifest) = match workspace {
+        Ok(workspace) => workspace,
+        Err(e) if budgets.iter().any(|b| matches!(b.scope, Scope::Package(_))) => {
+            return Err(e.context("Failed to find the packages of the workspace"));
+        }
+        Err(e) => {
+            debug!("Failed to find the workspace manifest: {:#}", e);
+            (vec![], "Cargo.toml".into())
+        }
+    };
+    let exceeded = exceeded(budgets, lints, &packages);
+    if exceeded.is_empty() {
+        return Ok(None);
+    }
+    for budget in &exceeded {
+        warn!("Lint budget exceeded: {}", budget);
+    }
+    Ok(Some(LintResult {
+        name: "cargo-harbormaster".into(),
+        code: "lint-budget".into(),
+        severity: "error".into(),
+        path: manifest.to_string_lossy().into(),
+        line: None,
+        position: None,
+        description: Some(format!(
+            "The warning budgets (--max-warnings) were exceeded:\n\n{}",
+            exceeded
+                .iter()
+                .map(|b| format!("- {}", b))
+                .collect::<Vec<_>>()
+                .join("\n")
+        )),
+    }))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::lint;
+
+    #[test]
+    fn exceeded_budgets() {
+        let mut lints = vec![
+            lint("warning", "crates/foo/src/lib.rs", Some(1)),
+            lint("warning", "crates/foo/src/lib.rs", Some(2)),
+            lint("Warning", "crates/foo-cli/src/main.rs", Some(1)),
+            lint("error", "crates/foo/src/lib.rs", Some(3)),
+        ];
+        lints[2].code = "clippy::unwrap_used".into();
+        let packages = [
+            ("foo".to_string(), PathBuf::from("crates/foo")),
+            ("foo-cli".to_string(), PathBuf::from("crates/foo-cli")),
+        ];
+        let budgets: Vec<Budget> = [
+            "3",
+            "2",
+            "clippy::unwrap_used=0",
+            "package:foo=2",
+            "package:foo-cli=0",
+        ]
+        .iter()
+        .map(|b| b.parse().unwrap())
+        .collect();
+        assert_eq!(
+            exceeded(&budgets, &lints, &packages),
+            [
+                "3 warnings, over the budget of 2",
+                "1 warnings of clippy::unwrap_used, over the budget of 0",
+                "1 warnings in package foo-cli, over the budget of 0",
+            ]
+        );
+        assert!("clippy::unwrap_used=x".parse::<Budget>().is_err());
+    }
+}
@@ -0,0 +1,82 @@
+//! Record of the results already sent for a build target, to only send new results
+use std::collections::HashSet;
+use std::path::{Path, PathBuf};
+
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+
+/// SHA-256 hashes of the serialized results already sent, stable across toolchains
+#[derive(Default, Serialize, Deserialize)]
+pub(crate) struct Sent {
+    hashes: HashSet<String>,
+}
+impl Sent {
+    fn path(dir: &Path, build: &str) -> PathBuf {
+        dir.join(format!("{}.json", build))
+    }
+    pub fn load(dir: &Path, build: &str) -> anyhow::Result<Self> {
+        let path = Self::path(dir, build);
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        match serde_json::from_slice::<Self>(&std::fs::read(&path)?) {
+            Ok(sent) => {
+                debug!("{} results already sent to {}", sent.hashes.len(), build);
+                Ok(sent)
+            }
+            // e.g. written by a previous version, with other hashes
+            Err(e) => {
+                warn!("Ignoring the invalid cache {:?}: {}", path, e);
+                Ok(Self::default())
+            }
+        }
+    }
+    pub fn save(&self, dir: &Path, build: &str) -> anyhow::Result<()> {
+        std::fs::create_dir_all(dir)?;
+        std::fs::write(Self::path(dir, build), serde_json::to_vec(self)?)?;
+        Ok(())
+    }
+    /// Items that were not sent yet, without their duplicates
+    pub fn unsent<T: Serialize + Clone>(&self, items: &[T]) -> Vec<T> {
+        let mut seen = HashSet::new();
+        items
+            .iter()
+            .filter(|item| {
+                let hash = hash(item);
+                !self.hashes.contains(&hash) && seen.insert(hash)
+            })
+            .cloned()
+            .collect()
+    }
+    /// Record items as sent, once acknowledged by the server
+    pub fn mark<T: Serialize>(&mut self, items: &[T]) {
+        self.hashes.extend(items.iter().map(hash));
+    }
+}
+
+fn hash(item: &impl Serialize) -> String {
+    format!(
+        "{:x}",
+        sha2::Sha256::digest(serde_json::to_vec(item).unwrap())
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn stable_hashes() {
+        assert_eq!(
+            hash(&"a"),
+            "ac8d8342bbb2362d13f0a559a3621bb407011368895164b628a54f7fc33fc43c"
+        );
+        let mut sent = Sent::default();
+        assert_eq!(sent.unsent(&["a", "b", "a"]), ["a", "b"]);
+        // Until marked as sent
+        assert_eq!(sent.unsent(&["a", "b"]), ["a", "b"]);
+        sent.mark(&["a", "b"]);
+        assert_eq!(sent.unsent(&["a", "c"]), ["c"]);
+    }
+}
@@ -0,0 +1,695 @@
+//! Minimal Conduit API client
+//! See <https://secure.phabricator.com/book/phabricator/article/conduit/>
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
+
+use anyhow::Context;
+use clap::ValueEnum;
+use log::*;
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+/// Conduit server implementation
+#[derive(Debug, Copy, Clone, Default, PartialEq, Eq, ValueEnum)]
+pub(crate) enum Flavor {
+    /// Detected from the login page of the server
+    #[default]
+    Auto,
+    Phabricator,
+    /// Community fork of Phabricator, see <https://we.phorge.it/>
+    Phorge,
+}
+impl Flavor {
+    /// Flavor named by the login page of a server ("Login to Phorge"), Phabricator otherwise
+    fn detect(page: &str) -> Self {
+        if page.contains("Phorge") {
+            Flavor::Phorge
+        } else {
+            Flavor::Phabricator
+        }
+    }
+    /// Documentation of the Conduit API
+    fn conduit_docs(self) -> &'static str {
+        match self {
+            Flavor::Phorge => "https://we.phorge.it/book/phorge/article/conduit/",
+            Flavor::Auto | Flavor::Phabricator => {
+                "https://secure.phabricator.com/book/phabricator/article/conduit/"
+            }
+        }
+    }
+}
+impl std::fmt::Display for Flavor {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Flavor::Auto => "Conduit server",
+            Flavor::Phabricator => "Phabricator",
+            Flavor::Phorge => "Phorge",
+        })
+    }
+}
+
+pub(crate) struct Client {
+    uri: String,
+    token: String,
+    /// Requested with --flavor
+    flavor: Flavor,
+    /// Resolved flavor, detected once with `Flavor::Auto`
+    detected: tokio::sync::OnceCell<Flavor>,
+    http: reqwest::Client,
+    /// Minimum interval between requests
+    interval: Duration,
+    /// Earliest time of the next request
+    next: tokio::sync::Mutex<tokio::time::Instant>,
+    /// Checked once, before the first message
+    capabilities: tokio::sync::OnceCell<Capabilities>,
+}
+
+/// Number of retries of rate-limited requests
+const RATE_LIMIT_RETRIES: u32 = 5;
+
+#[derive(Deserialize)]
+struct Response {
+    /// `null` for methods without a result, e.g. harbormaster.sendmessage
+    #[serde(default)]
+    result: serde_json::Value,
+    error_code: Option<String>,
+    error_info: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "camelCase")]
+pub(crate) struct User {
+    pub phid: String,
+    pub user_name: String,
+}
+
+/// TLS client certificate, from a PEM certificate and key, or from a PKCS#12 archive
+pub(crate) fn identity(
+    cert: &Path,
+    key: Option<&Path>,
+    password: Option<&str>,
+) -> anyhow::Result<reqwest::Identity> {
+    let read =
+        |path: &Path| std::fs::read(path).with_context(|| format!("Failed to read {:?}", path));
+    let data = read(cert)?;
+    Ok(match key {
+        Some(key) => reqwest::Identity::from_pkcs8_pem(&data, &read(key)?)
+            .context("Failed to load the PEM client certificate and key")?,
+        None if data.starts_with(b"-----BEGIN") => {
+            anyhow::bail!("--client-key is required with a PEM client certificate")
+        }
+        None => reqwest::Identity::from_pkcs12_der(&data, password.unwrap_or_default())
+            .context("Failed to load the PKCS#12 client certificate")?,
+    })
+}
+
+/// Error returned in the Conduit envelope
+#[derive(Debug)]
+pub(crate) struct Error {
+    pub flavor: Flavor,
+    pub method: String,
+    pub code: String,
+    pub info: String,
+}
+impl Error {
+    /// Suggestion on how to fix the error
+    fn hint(&self) -> Option<String> {
+        let info = self.info.to_lowercase();
+        Some(match self.code.as_str() {
+            "ERR-INVALID-AUTH" | "ERR-INVALID-SESSION" => format!(
+                "check the API token (--token or PHAB_TOKEN), which can be created under Settings > Conduit API Tokens, see {}",
+                self.flavor.conduit_docs()
+            ),
+            "ERR-CONDUIT-CALL" => {
+                "the method does not exist, check --conduit-uri and that Harbormaster is installed"
+                    .into()
+            }
+            "ERR-RATE-LIMIT" => "too many requests, retry later or lower --concurrency".into(),
+            _ if info.contains("phid") || info.contains("build target") => {
+                "check the build target PHID, which is passed as ${target.phid} in the build plan"
+                    .into()
+            }
+            _ => return None,
+        })
+    }
+}
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} {} failed: {} ({})",
+            self.flavor, self.method, self.info, self.code
+        )?;
+        if let Some(hint) = self.hint() {
+            write!(f, "; {}", hint)?;
+        }
+        Ok(())
+    }
+}
+impl std::error::Error for Error {}
+
+/// Result of `conduit.getcapabilities`
+#[derive(Debug, Default, Deserialize)]
+pub(crate) struct Capabilities {
+    #[serde(default)]
+    pub authentication: Vec<String>,
+    #[serde(default)]
+    pub input: Vec<String>,
+    #[serde(default)]
+    pub output: Vec<String>,
+}
+
+/// Additional HTTP header of the Conduit requests (`Name: value`), e.g. the credentials of a
+/// reverse proxy in front of the server
+#[derive(Debug, Clone)]
+pub(crate) struct Header {
+    pub name: reqwest::header::HeaderName,
+    pub value: reqwest::header::HeaderValue,
+}
+impl FromStr for Header {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (name, value) = s
+            .split_once(':')
+            .ok_or_else(|| anyhow::anyhow!("Expected 'Name: value', got {}", s))?;
+        let name = name.trim();
+        let mut value = reqwest::header::HeaderValue::from_str(value.trim())
+            .with_context(|| format!("Invalid value of the {} header", name))?;
+        // Not shown in the debug output
+        value.set_sensitive(is_secret_header(name));
+        Ok(Self {
+            name: name
+                .parse()
+                .with_context(|| format!("Invalid header name {:?}", name))?,
+            value,
+        })
+    }
+}
+
+/// Whether a header, by its name, carries credentials (`Authorization`, `X-Api-Key`, `Cookie`...)
+pub(crate) fn is_secret_header(name: &str) -> bool {
+    let name = name.to_ascii_lowercase();
+    ["auth", "token", "key", "secret", "password", "cookie"]
+        .iter()
+        .any(|word| name.contains(word))
+}
+
+/// HTTP client of the Conduit requests and of the downloads of the inputs, using the proxies
+/// (`HTTPS_PROXY`...) and CA certificates of the system, and sending the given headers with each
+/// request
+pub(crate) fn http_client(
+    timeout: Duration,
+    identity: Option<reqwest::Identity>,
+    headers: &[Header],
+) -> anyhow::Result<reqwest::Client> {
+    let mut http = reqwest::Client::builder().timeout(timeout);
+    if let Some(identity) = identity {
+        http = http.identity(identity);
+    }
+    if !headers.is_empty() {
+        let mut map = reqwest::header::HeaderMap::new();
+        for header in headers {
+            map.append(header.name.clone(), header.value.clone());
+        }
+        http = http.default_headers(map);
+    }
+    Ok(http.build()?)
+}
+
+impl Client {
+    /// The connection is reused across calls. `timeout` applies to each call.
+    pub fn new(
+        uri: &str,
+        token: &str,
+        timeout: Duration,
+        flavor: Flavor,
+        identity: Option<reqwest::Identity>,
+        headers: &[Header],
+        max_rps: Option<f64>,
+    ) -> anyhow::Result<Self> {
+        let interval = match max_rps {
+            Some(rps) => {
+                anyhow::ensure!(rps > 0.0, "The requests per second must be positive");
+                Duration::from_secs_f64(1.0 / rps)
+            }
+            None => Duration::ZERO,
+        };
+        Ok(Self {
+            uri: uri.trim_end_matches('/').into(),
+            token: token.into(),
+            flavor,
+            detected: Default::default(),
+            http: http_client(timeout, identity, headers)?,
+            interval,
+            next: tokio::sync::Mutex::new(tokio::time::Instant::now()),
+            capabilities: Default::default(),
+        })
+    }
+    /// Flavor of the server, detected from its login page with `--flavor auto`, falling back to
+    /// Phabricator. The server is only queried once.
+    pub async fn flavor(&self) -> Flavor {
+        if self.flavor != Flavor::Auto {
+            return self.flavor;
+        }
+        *self
+            .detected
+            .get_or_init(|| async {
+                let url = format!("{}/auth/start/", self.uri);
+                let page = async { self.http.get(&url).send().await?.text().await };
+                match page.await {
+                    Ok(page) => {
+                        let flavor = Flavor::detect(&page);
+                        debug!("Detected {} at {}", flavor, self.uri);
+                        flavor
+                    }
+                    Err(e) => {
+                        debug!("Failed to detect the flavor of {}: {}", self.uri, e);
+                        Flavor::Phabricator
+                    }
+                }
+            })
+            .await
+    }
+    /// Wait for the request budget, and reserve the next slot
+    async fn throttle(&self) {
+        let mut next = self.next.lock().await;
+        let at = (*next).max(tokio::time::Instant::now());
+        *next = at + self.interval;
+        drop(next);
+        tokio::time::sleep_until(at).await;
+    }
+    /// Delay all the requests after being rate limited
+    async fn back_off(&self, method: &str, wait: Duration) {
+        warn!("{} was rate limited, retrying in {:?}", method, wait);
+        let mut next = self.next.lock().await;
+        *next = (*next).max(tokio::time::Instant::now() + wait);
+    }
+    /// Call a Conduit method. `params` must serialize into a JSON object; the token is added to it.
+    pub async fn call<T: DeserializeOwned>(
+        &self,
+        method: &str,
+        params: &impl serde::Serialize,
+    ) -> anyhow::Result<T> {
+        let mut params = serde_json::to_value(params)?;
+        params
+            .as_object_mut()
+            .context("Conduit parameters must be an object")?
+            .insert(
+                "__conduit__".into(),
+                serde_json::json!({ "token": self.token }),
+            );
+        let url = format!("{}/api/{}", self.uri, method);
+        let params = serde_json::to_string(&params)?;
+        let mut attempt = 0;
+        let resp: Response = loop {
+            self.throttle().await;
+            debug!("Calling {}", url);
+            let resp = self
+                .http
+                .post(&url)
+                .form(&[
+                    ("params", params.as_str()),
+                    ("output", "json"),
+                    ("__conduit__", "1"),
+                ])
+                .send()
+                .await?;
+            let status = resp.error_for_status_ref().map(|_| ());
+            // Exponential backoff, unless the server tells how long to wait
+            let wait = resp
+                .headers()
+                .get(reqwest::header::RETRY_AFTER)
+                .and_then(|v| v.to_str().ok()?.parse().ok())
+                .map_or(Duration::from_secs(1 << attempt), Duration::from_secs);
+            let limited = resp.status() == reqwest::StatusCode::TOO_MANY_REQUESTS;
+            let body = resp.bytes().await?;
+            // Errors are usually returned in the envelope, possibly with an HTTP error status
+            let resp = serde_json::from_slice::<Response>(&body);
+            let limited = limited
+                || matches!(&resp, Ok(r) if r.error_code.as_deref() == Some("ERR-RATE-LIMIT"));
+            if limited && attempt < RATE_LIMIT_RETRIES {
+                self.back_off(method, wait).await;
+                attempt += 1;
+                continue;
+            }
+            match resp {
+                Ok(resp) => break resp,
+                Err(e) => {
+                    status?;
+                    return Err(e)
+                        .with_context(|| format!("Failed to decode the response of {}", method));
+                }
+            }
+        };
+        if let Some(code) = resp.error_code {
+            return Err(Error {
+                flavor: self.flavor().await,
+                method: method.into(),
+                code,
+                info: resp.error_info.unwrap_or_default(),
+            }
+            .into());
+        }
+        serde_json::from_value(resp.result)
+            .with_context(|| format!("Unexpected result of {}", method))
+    }
+    /// Upload a file and attach it to a build target as an artifact, returning the file PHID
+    pub async fn upload_artifact(
+        &self,
+        build: &str,
+        key: &str,
+        name: &str,
+        data: &[u8],
+    ) -> anyhow::Result<String> {
+        use base64::Engine;
+        let file: String = self
+            .call(
+                "file.upload",
+                &serde_json::json!({
+                    "name": name,
+                    "data_base64": base64::engine::general_purpose::STANDARD.encode(data),
+                }),
+            )
+            .await?;
+        self.call::<serde_json::Value>(
+            "harbormaster.createartifact",
+            &serde_json::json!({
+                "buildTargetPHID": build,
+                "artifactKey": key,
+                "artifactType": "file",
+                "artifactData": { "filePHID": file },
+            }),
+        )
+        .await?;
+        info!("Attached {} ({}) to {}", name, file, build);
+        Ok(file)
+    }
+    /// URL of an uploaded file, from its PHID
+    pub async fn file_url(&self, phid: &str) -> anyhow::Result<String> {
+        #[derive(Deserialize)]
+        struct File {
+            id: u64,
+        }
+        #[derive(Deserialize)]
+        struct Files {
+            data: Vec<File>,
+        }
+        let files: Files = self
+            .call(
+                "file.search",
+                &serde_json::json!({ "constraints": { "phids": [phid] } }),
+            )
+            .await?;
+        let file = files
+            .data
+            .first()
+            .with_context(|| format!("File {} not found", phid))?;
+        Ok(format!("{}/F{}", self.uri, file.id))
+    }
+    /// Check that the server supports token authentication and the form-encoded requests with
+    /// JSON responses used by this client. Unadvertised capabilities are assumed to be supported.
+    /// The server is only queried once.
+    pub async fn capabilities(&self) -> anyhow::Result<&Capabilities> {
+        self.capabilities
+            .get_or_try_init(|| async {
+                let caps: Capabilities = self
+                    .call("conduit.getcapabilities", &serde_json::json!({}))
+                    .await?;
+                let flavor = self.flavor().await;
+                debug!("{} capabilities: {:?}", flavor, caps);
+                let supports = |list: &[String], value: &str| {
+                    list.is_empty() || list.iter().any(|v| v == value)
+                };
+                anyhow::ensure!(
+                    supports(&caps.authentication, "token"),
+                    "{} at {} does not support token authentication ({})",
+                    flavor,
+                    self.uri,
+                    caps.authentication.join(", ")
+                );
+                if !supports(&caps.input, "urlencoded") || !supports(&caps.output, "json") {
+                    warn!(
+                        "{} at {} does not advertise url-encoded inputs and JSON outputs",
+                        flavor, self.uri
+                    );
+                }
+                Ok(caps)
+            })
+            .await
+    }
+    /// Check connectivity, capabilities and the validity of the token.
+    pub async fn check(&self) -> anyhow::Result<User> {
+        let host: serde_json::Value = self.call("conduit.ping", &serde_json::json!({})).await?;
+        info!("Reached {} at {}: {}", self.flavor().await, self.uri, host);
+        self.capabilities().await?;
+        let user: User = self.call("user.whoami", &serde_json::json!({})).await?;
+        info!("Authenticated as {} ({})", user.user_name, user.phid);
+        Ok(user)
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+    use hyper::{Body, Request, StatusCode};
+
+    /// Conduit server on a local port, answering each call with the HTTP status and JSON
+    /// response returned by `respond`, given the method and its parameters (a string response is
+    /// sent as is). Rate-limited requests are retried immediately. Returns its URI.
+    pub(crate) fn server(
+        respond: impl Fn(&str, serde_json::Value) -> (StatusCode, serde_json::Value)
+            + Send
+            + Sync
+            + 'static,
+    ) -> String {
+        let respond = std::sync::Arc::new(respond);
+        let make_service = hyper::service::make_service_fn(move |_| {
+            let respond = respond.clone();
+            async move {
+                Ok::<_, hyper::Error>(hyper::service::service_fn(move |request: Request<Body>| {
+                    let respond = respond.clone();
+                    async move {
+                        let method = request.uri().path().trim_start_matches("/api/").to_string();
+                        let body = hyper::body::to_bytes(request.into_body()).await?;
+                        let form = format!("http://form/?{}", String::from_utf8_lossy(&body));
+                        let params = reqwest::Url::parse(&form)
+                            .unwrap()
+                            .query_pairs()
+                            .find(|(k, _)| k == "params")
+                            .map_or(serde_json::Value::Null, |(_, v)| {
+                                serde_json::from_str(&v).unwrap()
+                            });
+                        let (status, response) = respond(&method, params);
+                        let builder = hyper::Response::builder()
+                            .status(status)
+                            .header("Retry-After", "0");
+                        let body = match response {
+                            serde_json::Value::String(text) => Body::from(text),
+                            response => Body::from(response.to_string()),
+                        };
+                        Ok::<_, hyper::Error>(builder.body(body).unwrap())
+                    }
+                }))
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let uri = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+        uri
+    }
+
+    /// Error envelope
+    pub(crate) fn error(code: &str, info: &str) -> (StatusCode, serde_json::Value) {
+        let response =
+            serde_json::json!({ "result": null, "error_code": code, "error_info": info });
+        (StatusCode::OK, response)
+    }
+
+    /// Successful response
+    pub(crate) fn ok(result: serde_json::Value) -> (StatusCode, serde_json::Value) {
+        let response =
+            serde_json::json!({ "result": result, "error_code": null, "error_info": null });
+        (StatusCode::OK, response)
+    }
+
+    pub(crate) fn client(uri: &str) -> Client {
+        let timeout = Duration::from_secs(5);
+        Client::new(
+            uri,
+            "api-token",
+            timeout,
+            Flavor::Phabricator,
+            None,
+            &[],
+            None,
+        )
+        .unwrap()
+    }
+
+    #[tokio::test]
+    async fn null_results() {
+        let uri = server(|method, params| {
+            assert_eq!(params["__conduit__"]["token"], "api-token");
+            match method {
+                "user.whoami" => ok(serde_json::json!({"phid": "PHID-USER-1", "userName": "ci"})),
+                _ => ok(serde_json::Value::Null),
+            }
+        });
+        let client = client(&uri);
+        let result: serde_json::Value = client
+            .call("harbormaster.sendmessage", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert!(result.is_null());
+        let user: User = client
+            .call("user.whoami", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(user.user_name, "ci");
+        // A result is expected
+        let error = client
+            .call::<User>("conduit.ping", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(error.to_string(), "Unexpected result of conduit.ping");
+    }
+
+    #[test]
+    fn flavors() {
+        let page = |platform: &str| format!("<title>Login</title><h1>Login to {}</h1>", platform);
+        assert_eq!(Flavor::detect(&page("Phorge")), Flavor::Phorge);
+        assert_eq!(Flavor::detect(&page("Phabricator")), Flavor::Phabricator);
+        assert_eq!(Flavor::detect(""), Flavor::Phabricator);
+
+        let error = |flavor| Error {
+            flavor,
+            method: "user.whoami".into(),
+            code: "ERR-INVALID-AUTH".into(),
+            info: "API token \"api-x\" has the wrong length.".into(),
+        };
+        let phorge = error(Flavor::Phorge).to_string();
+        assert!(phorge.starts_with("Phorge user.whoami failed"));
+        assert!(phorge.ends_with("see https://we.phorge.it/book/phorge/article/conduit/"));
+        let phabricator = error(Flavor::Phabricator).to_string();
+        assert!(phabricator.starts_with("Phabricator user.whoami failed"));
+        assert!(phabricator.contains("secure.phabricator.com"));
+    }
+
+    #[tokio::test]
+    async fn hints_and_exit_codes() {
+        let uri = server(|method, _| match method {
+            "user.whoami" => error("ERR-INVALID-AUTH", "API token \"x\" has the wrong length."),
+            "harbormaster.sendmessage" => error(
+                "ERR-CONDUIT-CORE",
+                "No build target exists with PHID \"PHID-HMBT-x\".",
+            ),
+            "conduit.ping" => error("ERR-CONDUIT-CORE", "Database connection lost"),
+            "proxy.login" => (StatusCode::UNAUTHORIZED, "<html>Login</html>".into()),
+            "proxy.gateway" => (StatusCode::BAD_GATEWAY, "<html>Bad Gateway</html>".into()),
+            _ => error("ERR-CONDUIT-CALL", "Conduit method does not exist."),
+        });
+        let client = client(&uri);
+        let call = |method: &'static str| {
+            let client = &client;
+            async move {
+                client
+                    .call::<serde_json::Value>(method, &serde_json::json!({}))
+                    .await
+                    .unwrap_err()
+            }
+        };
+        let auth = call("user.whoami").await;
+        assert!(auth
+            .to_string()
+            .contains("(ERR-INVALID-AUTH); check the API token"));
+        assert_eq!(crate::exit_code(&auth), 3);
+        let target = call("harbormaster.sendmessage").await;
+        assert!(target.to_string().contains("; check the build target PHID"));
+        assert_eq!(crate::exit_code(&target), 4);
+        let method = call("harbormaster.unknown").await;
+        assert!(method.to_string().contains("; the method does not exist"));
+        assert_eq!(crate::exit_code(&method), 4);
+        let other = call("conduit.ping").await;
+        assert_eq!(
+            other.to_string(),
+            "Phabricator conduit.ping failed: Database connection lost (ERR-CONDUIT-CORE)"
+        );
+        assert_eq!(crate::exit_code(&other), 4);
+        // Errors of a proxy in front of the server, without an envelope
+        assert_eq!(crate::exit_code(&call("proxy.login").await), 3);
+        assert_eq!(crate::exit_code(&call("proxy.gateway").await), 5);
+        let unreachable = super::tests::client("http://127.0.0.1:1")
+            .call::<serde_json::Value>("conduit.ping", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(crate::exit_code(&unreachable), 5);
+    }
+
+    #[tokio::test]
+    async fn retries_rate_limited_requests() {
+        let calls = std::sync::Arc::new(std::sync::atomic::AtomicU32::new(0));
+        let uri = server({
+            let calls = calls.clone();
+            move |method, _| {
+                let n = calls.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+                match method {
+                    // In the envelope, then with the HTTP status
+                    "conduit.ping" if n == 0 => error("ERR-RATE-LIMIT", "Slow down"),
+                    "conduit.ping" if n == 1 => (StatusCode::TOO_MANY_REQUESTS, "busy".into()),
+                    "conduit.ping" => ok("phab.example.com".into()),
+                    _ => error("ERR-RATE-LIMIT", "Slow down"),
+                }
+            }
+        });
+        let client = client(&uri);
+        let host: String = client
+            .call("conduit.ping", &serde_json::json!({}))
+            .await
+            .unwrap();
+        assert_eq!(host, "phab.example.com");
+        assert_eq!(calls.load(std::sync::atomic::Ordering::SeqCst), 3);
+
+        // Until giving up
+        calls.store(0, std::sync::atomic::Ordering::SeqCst);
+        let error = client
+            .call::<serde_json::Value>("user.whoami", &serde_json::json!({}))
+            .await
+            .unwrap_err();
+        assert_eq!(
+            calls.load(std::sync::atomic::Ordering::SeqCst),
+            RATE_LIMIT_RETRIES + 1
+        );
+        assert!(error.to_string().contains("; too many requests"));
+        assert_eq!(crate::exit_code(&error), 5);
+    }
+
+    #[tokio::test]
+    async fn limits_the_request_rate() {
+        let uri = server(|_, _| ok(serde_json::Value::Null));
+        let timeout = Duration::from_secs(5);
+        let new = |max_rps| {
+            Client::new(
+                &uri,
+                "api-token",
+                timeout,
+                Flavor::Phabricator,
+                None,
+                &[],
+                max_rps,
+            )
+        };
+        assert!(new(Some(0.0)).is_err());
+        let client = new(Some(50.0)).unwrap();
+        let start = std::time::Instant::now();
+        let params = serde_json::json!({});
+        futures::future::try_join_all(
+            (0..6).map(|_| client.call::<serde_json::Value>("harbormaster.sendmessage", &params)),
+        )
+        .await
+        .unwrap();
+        // 5 intervals of 20ms between the 6 requests
+        assert!(start.elapsed() >= Duration::from_millis(100));
+    }
+}
@@ -0,0 +1,167 @@
+//! Configuration file (harbormaster.toml)
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+
+use crate::Inputs;
+
+#[derive(Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Config {
+    /// Inputs for each workspace of the repository
+    #[serde(default, rename = "workspace")]
+    pub workspaces: Vec<Inputs>,
+    /// Parsers loaded as WASM plugins
+    #[serde(default, rename = "parser")]
+    pub parsers: Vec<crate::parser::ParserConfig>,
+    /// Builds run by the server mode
+    pub serve: Option<crate::serve::ServeConfig>,
+    /// Regular expressions of the secrets to redact, in addition to `--redact`
+    #[serde(default)]
+    pub redact: Vec<String>,
+    /// Additional outputs, written along with the one of the flags: path ('-' for stdout) of
+    /// each format
+    #[serde(default)]
+    pub outputs: BTreeMap<crate::Format, PathBuf>,
+    /// Additional HTTP headers of the Conduit requests, by name, overridden by `--header`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+    /// Conduit URI, overridden by `--conduit-uri` and `PHAB_URI`
+    pub conduit_uri: Option<String>,
+    /// API token, overridden by `--token` and `PHAB_TOKEN`
+    pub token: Option<String>,
+}
+impl Config {
+    /// Load the configuration, expanding the environment variables in the values
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data =
+            std::fs::read_to_string(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let mut table: toml::Table =
+            toml::from_str(&data).with_context(|| format!("Failed to parse {:?}", path))?;
+        // The commands are run by a shell, which expands the variables itself, including the
+        // ones set for each build (e.g. $HARBORMASTER_TARGET)
+        let serve = table.get_mut("serve").and_then(toml::Value::as_table_mut);
+        let commands = serve.and_then(|serve| serve.remove("commands"));
+        for (key, value) in table.iter_mut() {
+            expand(value, key, &|name| std::env::var(name).ok())
+                .with_context(|| format!("Failed to parse {:?}", path))?;
+        }
+        if let (Some(commands), Some(toml::Value::Table(serve))) =
+            (commands, table.get_mut("serve"))
+        {
+            serve.insert("commands".into(), commands);
+        }
+        toml::Value::Table(table)
+            .try_into()
+            .with_context(|| format!("Failed to parse {:?}", path))
+    }
+}
+
+/// Expand the environment variables in the strings of a value, at the dotted path `key`
+fn expand(
+    value: &mut toml::Value,
+    key: &str,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> anyhow::Result<()> {
+    match value {
+        toml::Value::String(s) => {
+            *s = interpolate(s, env).with_context(|| format!("In {}", key))?
+        }
+        toml::Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                expand(value, &format!("{}[{}]", key, i), env)?;
+            }
+        }
+        toml::Value::Table(table) => {
+            for (k, value) in table.iter_mut() {
+                expand(value, &format!("{}.{}", key, k), env)?;
+            }
+        }
+        _ => {}
+    }
+    Ok(())
+}
+
+/// Replace `${VAR}` by the value of the variable, and `${VAR:-default}` by the default when it
+/// is unset or empty. `$${` is a literal `${`.
+fn interpolate(value: &str, env: &dyn Fn(&str) -> Option<String>) -> anyhow::Result<String> {
+    let mut out = String::with_capacity(value.len());
+    let mut rest = value;
+    while let Some(start) = rest.find('$') {
+        out.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(after) = rest.strip_prefix("$${") {
+            out.push_str("${");
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("${") {
+            let end = after
+                .find('}')
+                .with_context(|| format!("Unterminated variable in {:?}", value))?;
+            let (name, default) = match after[..end].split_once(":-") {
+                Some((name, default)) => (name, Some(default)),
+                None => (&after[..end], None),
+            };
+            let var = env(name).filter(|v| !v.is_empty() || default.is_none());
+            match (var, default) {
+                (Some(var), _) => out.push_str(&var),
+                (None, Some(default)) => out.push_str(default),
+                (None, None) => anyhow::bail!("The environment variable {} is not set", name),
+            }
+            rest = &after[end + 1..];
+        } else {
+            out.push('$');
+            rest = &rest[1..];
+        }
+    }
+    out.push_str(rest);
+    Ok(out)
+}
+
+/// Deserialize either a single value or an array of values
+pub(crate) fn one_or_many<'de, D, T>(deserializer: D) -> Result<Vec<T>, D::Error>
+where
+    D: Deserializer<'de>,
+    T: Deserialize<'de>,
+{
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum OneOrMany<T> {
+        One(T),
+        Many(Vec<T>),
+    }
+    Ok(match OneOrMany::deserialize(deserializer)? {
+        OneOrMany::One(value) => vec![value],
+        OneOrMany::Many(values) => values,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn interpolates_variables() {
+        let env = |name: &str| match name {
+            "PHAB_URI" => Some("https://phab.example.com".to_string()),
+            "EMPTY" => Some(String::new()),
+            _ => None,
+        };
+        let interpolate = |value| interpolate(value, &env);
+        assert_eq!(
+            interpolate("${PHAB_URI}/api").unwrap(),
+            "https://phab.example.com/api"
+        );
+        assert_eq!(
+            interpolate("${RUNNER_DIR:-/tmp}/out, ${EMPTY:-x}, ${EMPTY}.").unwrap(),
+            "/tmp/out, x, ."
+        );
+        assert_eq!(
+            interpolate("^ab$, $$, $${HOME}").unwrap(),
+            "^ab$, $$, ${HOME}"
+        );
+        assert!(interpolate("${UNSET}").is_err());
+        assert!(interpolate("${PHAB_URI").is_err());
+    }
+}
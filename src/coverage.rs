@@ -0,0 +1,211 @@
+//! Line coverage of LCOV reports (`cargo llvm-cov --lcov`, `cargo tarpaulin --out Lcov`...), in
+//! the per-file format of Harbormaster, and its unit results
+use std::collections::BTreeMap;
+use std::io::BufRead;
+use std::path::Path;
+
+use anyhow::Context;
+use log::*;
+
+use crate::paths::PathResolver;
+use crate::{input, UnitResult};
+
+/// Execution counts of the lines of each source file of an LCOV report, summed across records
+fn parse_lcov(reader: impl BufRead) -> anyhow::Result<BTreeMap<String, BTreeMap<usize, u64>>> {
+    let mut files = BTreeMap::<String, BTreeMap<usize, u64>>::new();
+    let mut current = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim();
+        if let Some(path) = line.strip_prefix("SF:") {
+            current = Some(files.entry(path.into()).or_default());
+        } else if let Some(data) = line.strip_prefix("DA:") {
+            // DA:<line>,<count>[,<checksum>]
+            let parsed = data.split_once(',').and_then(|(number, rest)| {
+                let count = rest.split(',').next()?;
+                Some((number.parse::<usize>().ok()?, count.parse::<u64>().ok()?))
+            });
+            let (number, count) =
+                parsed.with_context(|| format!("Invalid line {}: {}", i + 1, line))?;
+            let lines = current
+                .as_mut()
+                .with_context(|| format!("Line {} is outside of a record", i + 1))?;
+            let total = lines.entry(number).or_default();
+            *total = total.saturating_add(count);
+        } else if line == "end_of_record" {
+            current = None;
+        }
+    }
+    Ok(files)
+}
+
+/// Harbormaster coverage string of the execution counts by line number: a character per line,
+/// `C` for the executed lines, `U` for the other instrumented lines and `N` for the others
+fn to_harbormaster(lines: &BTreeMap<usize, u64>) -> String {
+    let len = lines.keys().next_back().copied().unwrap_or_default();
+    (1..=len)
+        .map(|number| match lines.get(&number) {
+            None => 'N',
+            Some(0) => 'U',
+            Some(_) => 'C',
+        })
+        .collect()
+}
+
+/// Add the coverage of a file, merging it with the existing coverage of the file (e.g. from
+/// another test run): a line is covered if it is covered by either
+pub(crate) fn add(coverage: &mut BTreeMap<String, String>, path: String, lines: &str) {
+    let Some(existing) = coverage.get_mut(&path) else {
+        coverage.insert(path, lines.into());
+        return;
+    };
+    let rank = |c: char| match c {
+        'C' => 2,
+        'U' => 1,
+        _ => 0,
+    };
+    let (mut a, mut b) = (existing.chars(), lines.chars());
+    *existing = std::iter::from_fn(|| match (a.next(), b.next()) {
+        (Some(x), Some(y)) => Some(if rank(y) > rank(x) { y } else { x }),
+        (x, y) => x.or(y),
+    })
+    .collect();
+}
+
+/// Coverage of each file of an LCOV report, relative to the repository root. Absolute paths are
+/// resolved against `build_root`, the workspace root when the coverage was measured. The files
+/// outside of the workspace sources (dependencies, generated code) are ignored.
+pub(crate) fn parse(
+    path: &Path,
+    resolver: &PathResolver,
+    build_root: &Path,
+) -> anyhow::Result<BTreeMap<String, String>> {
+    let mut coverage = BTreeMap::new();
+    let mut external = 0;
+    for (file, lines) in parse_lcov(input::open(path)?)? {
+        let file = Path::new(&file);
+        if resolver.is_external(file, Some(build_root)) {
+            external += 1;
+            continue;
+        }
+        let file = resolver.resolve(file, Some(build_root));
+        add(
+            &mut coverage,
+            file.to_string_lossy().into(),
+            &to_harbormaster(&lines),
+        );
+    }
+    if external > 0 {
+        debug!(
+            "Ignoring the coverage of {} files outside of the workspace in {:?}",
+            external, path
+        );
+    }
+    Ok(coverage)
+}
+
+/// Unit results carrying the coverage, one per package (named after it in the namespace) given
+/// the package of each file. The files outside of the packages are attached to a
+/// `cargo-harbormaster` unit.
+pub(crate) fn units(
+    coverage: &BTreeMap<String, String>,
+    packages: &BTreeMap<String, String>,
+) -> Vec<UnitResult> {
+    let mut grouped = BTreeMap::<Option<&str>, BTreeMap<&str, &str>>::new();
+    for (path, lines) in coverage {
+        grouped
+            .entry(packages.get(path).map(String::as_str))
+            .or_default()
+            .insert(path, lines);
+    }
+    grouped
+        .into_iter()
+        .map(|(package, files)| {
+            let covered: usize = files.values().map(|l| l.matches('C').count()).sum();
+            let uncovered: usize = files.values().map(|l| l.matches('U').count()).sum();
+            let percent = match covered + uncovered {
+                0 => 100.0,
+                total => 100.0 * covered as f64 / total as f64,
+            };
+            UnitResult {
+                name: "coverage".into(),
+                result: "pass".into(),
+                namespace: Some(package.unwrap_or("cargo-harbormaster").into()),
+                engine: Some("cargo-harbormaster".into()),
+                details: Some(format!(
+                    "{} of {} lines covered ({:.1}%)",
+                    covered,
+                    covered + uncovered,
+                    percent
+                )),
+                coverage: Some(
+                    files
+                        .into_iter()
+                        .map(|(path, lines)| (path.into(), lines.into()))
+                        .collect(),
+                ),
+                ..Default::default()
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lcov_to_harbormaster() {
+        let lcov = "TN:\nSF:/ws/src/lib.rs\nDA:2,1\nDA:3,0\nDA:5,4,abc\nend_of_record\n\
+                    SF:/ws/src/main.rs\nDA:1,0\nend_of_record\nSF:/ws/src/lib.rs\nDA:3,2\n\
+                    DA:4,0\nend_of_record\n";
+        let files = parse_lcov(lcov.as_bytes()).unwrap();
+        assert_eq!(to_harbormaster(&files["/ws/src/lib.rs"]), "NCCUC");
+        assert_eq!(to_harbormaster(&files["/ws/src/main.rs"]), "U");
+        assert!(parse_lcov("DA:1,1\n".as_bytes()).is_err());
+        assert!(parse_lcov("SF:a.rs\nDA:x,1\n".as_bytes()).is_err());
+
+        let mut coverage = BTreeMap::new();
+        add(&mut coverage, "src/lib.rs".into(), "NCUU");
+        add(&mut coverage, "src/lib.rs".into(), "NUCNUC");
+        assert_eq!(coverage["src/lib.rs"], "NCCUUC");
+    }
+
+    #[test]
+    fn units_by_package() {
+        let coverage: BTreeMap<String, String> = [
+            ("crates/foo/src/lib.rs", "NCU"),
+            ("crates/foo/src/main.rs", "C"),
+            ("crates/bar/src/lib.rs", "UU"),
+            ("build.rs", "N"),
+        ]
+        .into_iter()
+        .map(|(p, l)| (p.into(), l.into()))
+        .collect();
+        let packages: BTreeMap<String, String> = [
+            ("crates/foo/src/lib.rs", "foo"),
+            ("crates/foo/src/main.rs", "foo"),
+            ("crates/bar/src/lib.rs", "bar"),
+        ]
+        .into_iter()
+        .map(|(p, n)| (p.into(), n.into()))
+        .collect();
+        let units = units(&coverage, &packages);
+        let namespaces: Vec<_> = units.iter().map(|u| u.namespace.as_deref()).collect();
+        assert_eq!(
+            namespaces,
+            [Some("cargo-harbormaster"), Some("bar"), Some("foo")]
+        );
+        assert_eq!(
+            units[2].details.as_deref(),
+            Some("2 of 3 lines covered (66.7%)")
+        );
+        let foo = units[2].coverage.as_ref().unwrap();
+        assert_eq!(foo.len(), 2);
+        assert_eq!(foo["crates/foo/src/lib.rs"], "NCU");
+        assert_eq!(
+            units[1].details.as_deref(),
+            Some("0 of 2 lines covered (0.0%)")
+        );
+    }
+}
@@ -0,0 +1,216 @@
+//! Lines changed by a diff, to only report the lints on the changed code
+use std::collections::HashMap;
+use std::ops::RangeInclusive;
+use std::path::Path;
+use std::process::Command;
+
+use anyhow::Context;
+use log::*;
+
+use crate::LintResult;
+
+/// Changed line ranges of each file, relative to the repository root
+#[derive(Debug, Default)]
+pub(crate) struct Changes {
+    files: HashMap<String, Vec<RangeInclusive<usize>>>,
+}
+impl Changes {
+    /// Changes between `base` and the working tree of the repository containing `dir`, or the
+    /// staged changes without a base
+    pub fn load(dir: &Path, base: Option<&str>) -> anyhow::Result<Self> {
+        let mut cmd = Command::new("git");
+        cmd.current_dir(dir);
+        cmd.args([
+            "diff",
+            "-U0",
+            "--no-color",
+            "--no-ext-diff",
+            "--no-relative",
+            // Regardless of diff.noprefix and diff.mnemonicPrefix
+            "--src-prefix=a/",
+            "--dst-prefix=b/",
+        ]);
+        match base {
+            Some(base) => cmd.args(["--end-of-options", base, "--"]),
+            None => cmd.arg("--cached"),
+        };
+        let output = cmd.output().context("Failed to run git")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "git diff failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        let changes = Self::parse(&String::from_utf8_lossy(&output.stdout));
+        info!(
+            "{} files changed since {}",
+            changes.files.len(),
+            base.unwrap_or("the index")
+        );
+        Ok(changes)
+    }
+    /// Parse a `git diff -U0` output
+    fn parse(diff: &str) -> Self {
+        let mut changes = Self::default();
+        let mut current = None;
+        for line in diff.lines() {
+            // Renames without changes have no `+++` line
+            if let Some(path) = line.strip_prefix("rename to ") {
+                changes.files.entry(path.into()).or_default();
+            } else if let Some(path) = line.strip_prefix("+++ ") {
+                // Deleted files are `/dev/null`
+                current = path.strip_prefix("b/").map(String::from);
+                if let Some(path) = &current {
+                    changes.files.entry(path.clone()).or_default();
+                }
+            } else if let (Some(hunk), Some(path)) = (line.strip_prefix("@@ "), &current) {
+                // @@ -start[,count] +start[,count] @@
+                let Some(added) = hunk.split(' ').find_map(|r| r.strip_prefix('+')) else {
+                    continue;
+                };
+                let (start, count) = match added.split_once(',') {
+                    Some((start, count)) => (start.parse(), count.parse()),
+                    None => (added.parse(), Ok(1)),
+                };
+                // Pure deletions do not add lines
+                if let (Ok(start), Ok(count @ 1..)) = (start, count) {
+                    changes
+                        .files
+                        .get_mut(path)
+                        .unwrap()
+                        .push(start..=start + count - 1);
+                }
+            }
+        }
+        changes
+    }
+    /// Whether a file, relative to the repository root, is changed by the diff
+    pub fn touches(&self, path: &str) -> bool {
+        self.files.contains_key(path)
+    }
+    /// Whether a lint is on a changed line, or in a changed file for lints without a line
+    pub fn contains(&self, lint: &LintResult) -> bool {
+        let Some(ranges) = self.files.get(&lint.path) else {
+            return false;
+        };
+        match lint.line {
+            Some(line) => ranges.iter().any(|r| r.contains(&line)),
+            None => true,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::lint;
+
+    fn ranges(changes: &Changes, path: &str) -> Vec<RangeInclusive<usize>> {
+        changes.files[path].clone()
+    }
+
+    #[test]
+    fn parse() {
+        let changes = Changes::parse(
+            "diff --git a/src/lib.rs b/src/lib.rs
+index 1111111..2222222 100644
+--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -3 +3 @@ fn main() {
+-    let x = 1;
++    let x = 2;
+@@ -10,0 +11,3 @@ fn other() {
++fn a() {}
++fn b() {}
++fn c() {}
+@@ -20,2 +23,0 @@ fn d() {}
+-fn e() {}
+-fn f() {}
+diff --git a/src/new.rs b/src/new.rs
+new file mode 100644
+index 0000000..3333333
+--- /dev/null
++++ b/src/new.rs
+@@ -0,0 +1,2 @@
++fn new() {}
++fn newer() {}
+diff --git a/src/old.rs b/src/old.rs
+deleted file mode 100644
+index 4444444..0000000
+--- a/src/old.rs
++++ /dev/null
+@@ -1,2 +0,0 @@
+-fn old() {}
+-fn older() {}
+diff --git a/src/a.rs b/src/b.rs
+similarity index 100%
+rename from src/a.rs
+rename to src/b.rs
+diff --git a/src/c.rs b/src/d.rs
+similarity index 90%
+rename from src/c.rs
+rename to src/d.rs
+index 5555555..6666666 100644
+--- a/src/c.rs
++++ b/src/d.rs
+@@ -5 +5 @@
+-fn c() {}
++fn d() {}
+",
+        );
+        // Continuation lines are not counted, and deletions do not add lines
+        assert_eq!(ranges(&changes, "src/lib.rs"), [3..=3, 11..=13]);
+        assert_eq!(ranges(&changes, "src/new.rs"), [1..=2]);
+        assert!(!changes.touches("src/old.rs"));
+        assert!(changes.touches("src/b.rs"));
+        assert!(ranges(&changes, "src/b.rs").is_empty());
+        assert!(!changes.touches("src/a.rs"));
+        assert_eq!(ranges(&changes, "src/d.rs"), [5..=5]);
+        assert!(!changes.touches("src/c.rs"));
+
+        assert!(changes.contains(&lint("error", "src/lib.rs", Some(12))));
+        assert!(!changes.contains(&lint("error", "src/lib.rs", Some(4))));
+        assert!(!changes.contains(&lint("error", "src/lib.rs", Some(21))));
+        assert!(changes.contains(&lint("error", "src/lib.rs", None)));
+        // The lints of a renamed file are only kept on its changed lines
+        assert!(changes.contains(&lint("error", "src/b.rs", None)));
+        assert!(!changes.contains(&lint("error", "src/b.rs", Some(1))));
+        assert!(!changes.contains(&lint("error", "src/main.rs", None)));
+    }
+
+    #[test]
+    fn load() {
+        let dir = std::env::temp_dir().join(format!("harbormaster-{}-diff", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        let git = |args: &[&str]| {
+            let output = Command::new("git")
+                .args(["-c", "user.name=ci", "-c", "user.email=ci@example.com"])
+                .args(args)
+                .current_dir(&dir)
+                .output()
+                .unwrap();
+            assert!(output.status.success(), "{:?}", output);
+        };
+        git(&["init", "-q"]);
+        // Configuration changing the prefixes of the paths
+        git(&["config", "diff.noprefix", "true"]);
+        git(&["config", "diff.mnemonicPrefix", "true"]);
+        std::fs::write(dir.join("src/lib.rs"), "fn a() {}\nfn b() {}\n").unwrap();
+        git(&["add", "."]);
+        git(&["commit", "-q", "-m", "Initial"]);
+        std::fs::write(dir.join("src/lib.rs"), "fn a() {}\nfn c() {}\n").unwrap();
+        std::fs::write(dir.join("src/new.rs"), "fn new() {}\n").unwrap();
+        git(&["add", "."]);
+
+        let staged = Changes::load(&dir.join("src"), None).unwrap();
+        assert_eq!(ranges(&staged, "src/lib.rs"), [2..=2]);
+        assert_eq!(ranges(&staged, "src/new.rs"), [1..=1]);
+        let changes = Changes::load(&dir, Some("HEAD")).unwrap();
+        assert_eq!(changes.files, staged.files);
+        // Not an option
+        let output = dir.join("output");
+        let base = format!("--output={}", output.display());
+        assert!(Changes::load(&dir, Some(&base)).is_err());
+        assert!(!output.exists());
+    }
+}
@@ -0,0 +1,198 @@
+//! Usage of `unsafe` reported by `cargo geiger --output-format Json`
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+use crate::paths::PathResolver;
+use crate::{input, LintResult};
+
+#[derive(Deserialize)]
+struct Report {
+    packages: Vec<Entry>,
+}
+#[derive(Deserialize)]
+struct Entry {
+    package: Package,
+    unsafety: Unsafety,
+}
+#[derive(Deserialize)]
+struct Package {
+    id: PackageId,
+}
+#[derive(Deserialize)]
+struct PackageId {
+    name: String,
+    version: String,
+    /// e.g. `{"Path": "file:///..."}` or `{"Registry": {...}}`
+    source: serde_json::Value,
+}
+#[derive(Deserialize)]
+struct Unsafety {
+    used: Counters,
+}
+#[derive(Deserialize)]
+struct Counters {
+    functions: Count,
+    exprs: Count,
+    item_impls: Count,
+    item_traits: Count,
+    methods: Count,
+}
+#[derive(Deserialize)]
+struct Count {
+    unsafe_: u64,
+}
+impl Counters {
+    fn total(&self) -> u64 {
+        self.counts().iter().map(|(_, c)| c).sum()
+    }
+    fn counts(&self) -> [(&'static str, u64); 5] {
+        [
+            ("functions", self.functions.unsafe_),
+            ("expressions", self.exprs.unsafe_),
+            ("impls", self.item_impls.unsafe_),
+            ("traits", self.item_traits.unsafe_),
+            ("methods", self.methods.unsafe_),
+        ]
+    }
+}
+
+/// Directory of a local package
+fn local_dir(id: &PackageId) -> Option<PathBuf> {
+    let url = id.source.get("Path")?.as_str()?;
+    Some(url.strip_prefix("file://").unwrap_or(url).into())
+}
+
+fn read(path: &Path) -> anyhow::Result<Report> {
+    Ok(serde_json::from_str(&input::read_to_string(path)?)?)
+}
+
+/// Parse the report, with an advice for each crate using `unsafe`, or only for the crates using
+/// more of it than in the baseline report. The lints of local crates are anchored at their
+/// `Cargo.toml`, and the ones of dependencies at the `Cargo.toml` of the workspace.
+pub(crate) fn parse(
+    path: &Path,
+    baseline: Option<&Path>,
+    resolver: &PathResolver,
+) -> anyhow::Result<Vec<LintResult>> {
+    let report = read(path)?;
+    let baseline: Option<HashMap<String, u64>> = baseline
+        .map(|path| {
+            anyhow::Ok(
+                read(path)?
+                    .packages
+                    .into_iter()
+                    .map(|e| (e.package.id.name, e.unsafety.used.total()))
+                    .collect(),
+            )
+        })
+        .transpose()?;
+    let mut lints = vec![];
+    for entry in report.packages {
+        let used = &entry.unsafety.used;
+        let total = used.total();
+        let id = &entry.package.id;
+        let before = baseline.as_ref().map(|b| b.get(&id.name).copied());
+        if total == 0 || before.is_some_and(|before| before.is_some_and(|b| total <= b)) {
+            continue;
+        }
+        let counts = used
+            .counts()
+            .iter()
+            .filter(|(_, c)| *c > 0)
+            .map(|(kind, c)| format!("{} {}", c, kind))
+            .collect::<Vec<_>>()
+            .join(", ");
+        let mut description = format!("{} {} uses unsafe code: {}", id.name, id.version, counts);
+        match before {
+            Some(Some(before)) => description += &format!(" ({} in the baseline)", before),
+            Some(None) => description += " (not in the baseline)",
+            None => {}
+        }
+        let manifest = match local_dir(id) {
+            Some(dir) => resolver.resolve(&dir.join("Cargo.toml"), Some(&resolver.cwd)),
+            None => resolver.resolve(Path::new("Cargo.toml"), None),
+        };
+        lints.push(LintResult {
+            name: "cargo-geiger".into(),
+            code: "unsafe".into(),
+            severity: "advice".into(),
+            path: manifest.to_string_lossy().to_string(),
+            line: None,
+            position: None,
+            description: Some(description),
+        });
+    }
+    Ok(lints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    fn entry(name: &str, source: serde_json::Value, exprs: u64, methods: u64) -> serde_json::Value {
+        let count = |n: u64| serde_json::json!({"safe": 1, "unsafe_": n});
+        serde_json::json!({
+            "package": {"id": {"name": name, "version": "1.0.0", "source": source}},
+            "unsafety": {"used": {
+                "functions": count(0),
+                "exprs": count(exprs),
+                "item_impls": count(0),
+                "item_traits": count(0),
+                "methods": count(methods),
+            }}
+        })
+    }
+
+    #[test]
+    fn unsafe_usage_against_the_baseline() {
+        let local = serde_json::json!({"Path": "file:///ws/ffi"});
+        let registry = serde_json::json!({"Registry": {"name": "crates-io"}});
+        let report = serde_json::json!({"packages": [
+            entry("ffi", local.clone(), 3, 1),
+            entry("libc", registry.clone(), 20, 0),
+            entry("safe", local.clone(), 0, 0),
+        ]});
+        let report = fixture("geiger.json", &report.to_string());
+        let resolver = PathResolver {
+            workspace: "".into(),
+            maps: vec![],
+            cwd: "/ws".into(),
+        };
+
+        let lints = parse(&report, None, &resolver).unwrap();
+        assert_eq!(lints.len(), 2);
+        assert!(lints
+            .iter()
+            .all(|l| l.code == "unsafe" && l.severity == "advice"));
+        assert_eq!(lints[0].path, "ffi/Cargo.toml");
+        assert_eq!(
+            lints[0].description.as_deref(),
+            Some("ffi 1.0.0 uses unsafe code: 3 expressions, 1 methods")
+        );
+        assert_eq!(lints[1].path, "Cargo.toml");
+
+        // Only the crates using more unsafe code than in the baseline, or new ones
+        let baseline = serde_json::json!({"packages": [entry("ffi", local, 1, 1)]});
+        let baseline = fixture("geiger-baseline.json", &baseline.to_string());
+        let lints = parse(&report, Some(&baseline), &resolver).unwrap();
+        let descriptions: Vec<_> = lints
+            .iter()
+            .filter_map(|l| l.description.as_deref())
+            .collect();
+        assert_eq!(
+            descriptions,
+            [
+                "ffi 1.0.0 uses unsafe code: 3 expressions, 1 methods (2 in the baseline)",
+                "libc 1.0.0 uses unsafe code: 20 expressions (not in the baseline)",
+            ]
+        );
+        let baseline = serde_json::json!({"packages": [entry("ffi", registry.clone(), 3, 1), entry("libc", registry, 30, 0)]});
+        let baseline = fixture("geiger-same.json", &baseline.to_string());
+        assert!(parse(&report, Some(&baseline), &resolver)
+            .unwrap()
+            .is_empty());
+    }
+}
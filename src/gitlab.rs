@@ -0,0 +1,109 @@
+//! GitLab Code Quality report, displayed in the merge request widgets
+//! See <https://docs.gitlab.com/ee/ci/testing/code_quality.html#implement-a-custom-tool>
+use std::io::Write;
+
+use serde::Serialize;
+use sha2::Digest;
+
+use crate::report::{Report, Sink};
+use crate::LintResult;
+
+/// Sink writing the lints as a Code Quality report. The tests are not reported, as GitLab reads
+/// them from JUnit reports.
+pub(crate) struct Gitlab;
+
+#[derive(Serialize)]
+struct Issue<'a> {
+    description: &'a str,
+    check_name: &'a str,
+    /// Identifies the issue across pipelines, to display the new and fixed ones
+    fingerprint: String,
+    severity: &'static str,
+    location: Location<'a>,
+}
+#[derive(Serialize)]
+struct Location<'a> {
+    path: &'a str,
+    lines: Lines,
+}
+#[derive(Serialize)]
+struct Lines {
+    begin: usize,
+}
+
+impl<'a> Issue<'a> {
+    fn new(lint: &'a LintResult) -> Self {
+        let mut hasher = sha2::Sha256::new();
+        for field in [
+            &lint.name,
+            &lint.code,
+            &lint.path,
+            lint.description.as_deref().unwrap_or_default(),
+        ] {
+            hasher.update(field.as_bytes());
+            hasher.update([0]);
+        }
+        hasher.update(lint.line.unwrap_or_default().to_le_bytes());
+        Self {
+            description: lint.description.as_deref().unwrap_or(&lint.code),
+            check_name: &lint.code,
+            fingerprint: hasher
+                .finalize()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect(),
+            severity: match lint.severity.as_str() {
+                "error" => "major",
+                "warning" => "minor",
+                _ => "info",
+            },
+            location: Location {
+                path: &lint.path,
+                lines: Lines {
+                    begin: lint.line.unwrap_or(1),
+                },
+            },
+        }
+    }
+}
+
+impl Sink for Gitlab {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        let issues: Vec<_> = report.lints.iter().map(Issue::new).collect();
+        serde_json::to_writer_pretty(out, &issues)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{lint, report, written};
+
+    #[test]
+    fn issues() {
+        let issues: serde_json::Value = serde_json::from_str(&written(&Gitlab, &report())).unwrap();
+        let issues = issues.as_array().unwrap();
+        assert_eq!(issues.len(), 2);
+        assert_eq!(issues[0]["check_name"], "clippy::len_zero");
+        assert_eq!(issues[0]["severity"], "major");
+        assert_eq!(issues[0]["location"]["path"], "src/lib.rs");
+        assert_eq!(issues[0]["location"]["lines"]["begin"], 3);
+        assert_eq!(issues[1]["severity"], "info");
+        assert_eq!(issues[1]["location"]["lines"]["begin"], 1);
+    }
+
+    #[test]
+    fn stable_fingerprints() {
+        let a = lint("warning", "src/lib.rs", Some(3));
+        assert_eq!(
+            Issue::new(&a).fingerprint,
+            Issue::new(&a.clone()).fingerprint
+        );
+        assert_eq!(Issue::new(&a).fingerprint.len(), 64);
+        let moved = lint("warning", "src/lib.rs", Some(4));
+        assert_ne!(Issue::new(&a).fingerprint, Issue::new(&moved).fingerprint);
+        let renamed = lint("warning", "src/main.rs", Some(3));
+        assert_ne!(Issue::new(&a).fingerprint, Issue::new(&renamed).fingerprint);
+    }
+}
@@ -0,0 +1,560 @@
+//! Results of the previous runs, recorded in a SQLite database (`--history-db`) for trend analysis
+use std::io::Write;
+use std::path::Path;
+use std::time::SystemTime;
+
+use anyhow::Context;
+use itertools::Itertools;
+use log::*;
+use rusqlite::{params, Connection};
+
+use crate::report::Report;
+use crate::UnitResult;
+
+const SCHEMA: &str = "
+CREATE TABLE IF NOT EXISTS runs (
+    id INTEGER PRIMARY KEY,
+    time INTEGER NOT NULL,
+    build TEXT
+);
+CREATE TABLE IF NOT EXISTS tests (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    namespace TEXT NOT NULL,
+    name TEXT NOT NULL,
+    result TEXT NOT NULL,
+    duration REAL
+);
+CREATE INDEX IF NOT EXISTS tests_name ON tests (namespace, name);
+CREATE TABLE IF NOT EXISTS lints (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    name TEXT NOT NULL,
+    severity TEXT NOT NULL,
+    count INTEGER NOT NULL
+);
+CREATE TABLE IF NOT EXISTS coverage (
+    run INTEGER NOT NULL REFERENCES runs(id) ON DELETE CASCADE,
+    path TEXT NOT NULL,
+    covered INTEGER NOT NULL,
+    uncovered INTEGER NOT NULL
+);
+";
+
+/// Query of the `history` subcommand
+#[derive(Debug, Default, Clone, Copy, clap::ValueEnum)]
+enum Query {
+    /// Summary of each run
+    #[default]
+    Runs,
+    /// Durations and failures of each test, the ones slowing down the most first
+    Tests,
+    /// Lint counts of each run, by severity
+    Lints,
+    /// Line coverage of each run
+    Coverage,
+}
+
+#[derive(clap::Args)]
+pub(crate) struct HistoryArgs {
+    #[clap(value_enum, default_value_t)]
+    query: Query,
+    /// Number of runs to show, or to compute the test averages over
+    #[clap(long, default_value_t = 10)]
+    last: usize,
+    /// Only show the tests whose namespace or name contains this
+    #[clap(long)]
+    filter: Option<String>,
+}
+
+/// Covered and uncovered lines of a Harbormaster coverage string (e.g. `NCCU`)
+fn coverage_counts(coverage: &str) -> (i64, i64) {
+    let covered = coverage.bytes().filter(|c| *c == b'C').count();
+    let uncovered = coverage.bytes().filter(|c| *c == b'U').count();
+    (covered as i64, uncovered as i64)
+}
+
+fn percent(covered: i64, uncovered: i64) -> f64 {
+    100.0 * covered as f64 / (covered + uncovered).max(1) as f64
+}
+
+/// Relative change, e.g. `+12%`
+fn change(value: f64, average: f64) -> String {
+    if average == 0.0 {
+        return "n/a".into();
+    }
+    format!("{:+.0}%", 100.0 * (value - average) / average)
+}
+
+pub(crate) struct History {
+    conn: Connection,
+}
+impl History {
+    pub fn open(path: &Path) -> anyhow::Result<Self> {
+        let conn = Connection::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+        conn.execute_batch("PRAGMA foreign_keys = ON;")?;
+        conn.execute_batch(SCHEMA)?;
+        Ok(Self { conn })
+    }
+
+    /// Record the results of a run. A previous run of the same build (e.g. a retried submission)
+    /// is replaced.
+    pub fn record(&mut self, report: &Report, build: Option<&str>) -> anyhow::Result<()> {
+        let time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let tx = self.conn.transaction()?;
+        if let Some(build) = build {
+            tx.execute("DELETE FROM runs WHERE build = ?1", [build])?;
+        }
+        tx.execute(
+            "INSERT INTO runs (time, build) VALUES (?1, ?2)",
+            params![time, build],
+        )?;
+        let run = tx.last_insert_rowid();
+        {
+            let mut insert = tx.prepare(
+                "INSERT INTO tests (run, namespace, name, result, duration)
+                 VALUES (?1, ?2, ?3, ?4, ?5)",
+            )?;
+            // Not the results added by cargo-harbormaster, e.g. the trends
+            for unit in report
+                .units
+                .iter()
+                .filter(|u| u.engine.as_deref() != Some("cargo-harbormaster"))
+            {
+                insert.execute(params![
+                    run,
+                    unit.namespace.as_deref().unwrap_or_default(),
+                    unit.name,
+                    unit.result,
+                    unit.duration_s
+                ])?;
+            }
+            let mut insert = tx.prepare(
+                "INSERT INTO lints (run, name, severity, count) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            let counts = report
+                .lints
+                .iter()
+                .counts_by(|l| (l.name.as_str(), l.severity.to_lowercase()));
+            for ((name, severity), count) in counts {
+                insert.execute(params![run, name, severity, count])?;
+            }
+            let mut insert = tx.prepare(
+                "INSERT INTO coverage (run, path, covered, uncovered) VALUES (?1, ?2, ?3, ?4)",
+            )?;
+            for (path, coverage) in &report.coverage {
+                let (covered, uncovered) = coverage_counts(coverage);
+                insert.execute(params![run, path, covered, uncovered])?;
+            }
+        }
+        tx.commit()?;
+        info!("Recorded the results in the history as run {}", run);
+        Ok(())
+    }
+
+    /// Annotate the tests with the change of their duration versus their average over the last
+    /// `window` runs, and add a result with the changes of the lint count and coverage. To be
+    /// called before recording the run.
+    pub fn annotate(&self, report: &mut Report, window: usize) -> anyhow::Result<()> {
+        let mut average = self.conn.prepare(
+            "SELECT AVG(duration), COUNT(*) FROM (
+                SELECT duration FROM tests
+                WHERE namespace = ?1 AND name = ?2 AND duration IS NOT NULL
+                ORDER BY run DESC LIMIT ?3
+            )",
+        )?;
+        for unit in &mut report.units {
+            let Some(duration) = unit.duration_s else {
+                continue;
+            };
+            let (avg, runs): (Option<f64>, usize) = average.query_row(
+                params![
+                    unit.namespace.as_deref().unwrap_or_default(),
+                    unit.name,
+                    window
+                ],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )?;
+            if let Some(avg) = avg {
+                let note = format!(
+                    "Duration: {:.3}s, {} versus the average of {:.3}s over the last {} runs",
+                    duration,
+                    change(duration as f64, avg),
+                    avg,
+                    runs
+                );
+                unit.details = Some(match unit.details.take() {
+                    Some(details) => format!("{}\n\n{}", details, note),
+                    None => note,
+                });
+            }
+        }
+        let runs: Vec<i64> = self
+            .conn
+            .prepare("SELECT id FROM runs ORDER BY id DESC LIMIT ?1")?
+            .query_map([window], |row| row.get(0))?
+            .collect::<Result<_, _>>()?;
+        if runs.is_empty() {
+            return Ok(());
+        }
+        let ids = runs.iter().join(",");
+        let mut details = vec![];
+        let lints: f64 = self.conn.query_row(
+            &format!(
+                "SELECT COALESCE(SUM(count), 0) * 1.0 / ?1 FROM lints WHERE run IN ({})",
+                ids
+            ),
+            [runs.len()],
+            |row| row.get(0),
+        )?;
+        details.push(format!(
+            "Lints: {}, {} versus the average of {:.1}",
+            report.lints.len(),
+            change(report.lints.len() as f64, lints),
+            lints
+        ));
+        if !report.coverage.is_empty() {
+            let (covered, uncovered) = report
+                .coverage
+                .values()
+                .map(|c| coverage_counts(c))
+                .fold((0, 0), |a, b| (a.0 + b.0, a.1 + b.1));
+            let current = percent(covered, uncovered);
+            let previous: Option<f64> = self.conn.query_row(
+                &format!(
+                    "SELECT AVG(100.0 * c / MAX(c + u, 1)) FROM (
+                        SELECT SUM(covered) AS c, SUM(uncovered) AS u FROM coverage
+                        WHERE run IN ({}) GROUP BY run
+                    )",
+                    ids
+                ),
+                [],
+                |row| row.get(0),
+            )?;
+            details.push(match previous {
+                Some(previous) => format!(
+                    "Coverage: {:.1}%, {:+.1} points versus the average of {:.1}%",
+                    current,
+                    current - previous,
+                    previous
+                ),
+                None => format!("Coverage: {:.1}%", current),
+            });
+        }
+        report.units.push(UnitResult {
+            name: "Trends".into(),
+            result: "pass".into(),
+            namespace: Some("cargo-harbormaster".into()),
+            engine: Some("cargo-harbormaster".into()),
+            details: Some(format!(
+                "Over the last {} runs:\n{}",
+                runs.len(),
+                details.join("\n")
+            )),
+            ..Default::default()
+        });
+        Ok(())
+    }
+
+    /// Print the trends for the `history` subcommand
+    pub fn query(&self, args: &HistoryArgs, out: &mut dyn Write) -> anyhow::Result<()> {
+        match args.query {
+            Query::Runs => {
+                let mut statement = self.conn.prepare(
+                    "SELECT r.id, datetime(r.time, 'unixepoch'), COALESCE(r.build, ''),
+                        (SELECT COUNT(*) FROM tests WHERE run = r.id),
+                        (SELECT COUNT(*) FROM tests WHERE run = r.id
+                            AND result NOT IN ('pass', 'skip', 'unsound')),
+                        (SELECT COALESCE(SUM(count), 0) FROM lints WHERE run = r.id),
+                        (SELECT 100.0 * SUM(covered) / MAX(SUM(covered) + SUM(uncovered), 1)
+                            FROM coverage WHERE run = r.id)
+                    FROM runs r ORDER BY r.id DESC LIMIT ?1",
+                )?;
+                writeln!(
+                    out,
+                    "{:>5}  {:19}  {:30}  {:>7}  {:>7}  {:>6}  {:>8}",
+                    "RUN", "TIME", "BUILD", "RESULTS", "FAILED", "LINTS", "COVERAGE"
+                )?;
+                let mut rows = statement.query([args.last])?;
+                while let Some(row) = rows.next()? {
+                    let coverage: Option<f64> = row.get(6)?;
+                    writeln!(
+                        out,
+                        "{:>5}  {:19}  {:30}  {:>7}  {:>7}  {:>6}  {:>8}",
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        row.get::<_, String>(2)?,
+                        row.get::<_, i64>(3)?,
+                        row.get::<_, i64>(4)?,
+                        row.get::<_, i64>(5)?,
+                        coverage.map_or(String::new(), |c| format!("{:.1}%", c)),
+                    )?;
+                }
+            }
+            Query::Tests => {
+                let mut statement = self.conn.prepare(
+                    "SELECT t.namespace, t.name, COUNT(*),
+                        SUM(t.result NOT IN ('pass', 'skip', 'unsound')),
+                        AVG(t.duration),
+                        (SELECT duration FROM tests WHERE namespace = t.namespace
+                            AND name = t.name ORDER BY run DESC LIMIT 1)
+                    FROM tests t
+                    WHERE t.run IN (SELECT id FROM runs ORDER BY id DESC LIMIT ?1)
+                        AND instr(t.namespace || ' ' || t.name, ?2) > 0
+                    GROUP BY t.namespace, t.name",
+                )?;
+                let mut tests = vec![];
+                let mut rows = statement.query(params![
+                    args.last,
+                    args.filter.as_deref().unwrap_or_default()
+                ])?;
+                while let Some(row) = rows.next()? {
+                    let namespace: String = row.get(0)?;
+                    let name: String = row.get(1)?;
+                    let average: Option<f64> = row.get(4)?;
+                    let last: Option<f64> = row.get(5)?;
+                    tests.push((
+                        format!("{} {}", namespace, name).trim().to_string(),
+                        row.get::<_, i64>(2)?,
+                        row.get::<_, i64>(3)?,
+                        average,
+                        last,
+                    ));
+                }
+                // Tests slowing down the most first
+                let ratio = |t: &(String, i64, i64, Option<f64>, Option<f64>)| match (t.3, t.4) {
+                    (Some(average), Some(last)) if average > 0.0 => last / average,
+                    _ => 0.0,
+                };
+                tests.sort_by(|a, b| ratio(b).total_cmp(&ratio(a)).then(a.0.cmp(&b.0)));
+                writeln!(
+                    out,
+                    "{:>5}  {:>8}  {:>9}  {:>9}  {:>6}  TEST",
+                    "RUNS", "FAILURES", "AVERAGE", "LAST", "CHANGE"
+                )?;
+                let seconds = |d: Option<f64>| d.map_or(String::new(), |d| format!("{:.3}s", d));
+                for (name, runs, failures, average, last) in tests {
+                    writeln!(
+                        out,
+                        "{:>5}  {:>8}  {:>9}  {:>9}  {:>6}  {}",
+                        runs,
+                        failures,
+                        seconds(average),
+                        seconds(last),
+                        match (last, average) {
+                            (Some(last), Some(average)) => change(last, average),
+                            _ => String::new(),
+                        },
+                        name
+                    )?;
+                }
+            }
+            Query::Lints => {
+                let mut statement = self.conn.prepare(
+                    "SELECT r.id, datetime(r.time, 'unixepoch'), COALESCE(l.severity, ''),
+                        COALESCE(SUM(l.count), 0)
+                    FROM runs r LEFT JOIN lints l ON l.run = r.id
+                    WHERE r.id IN (SELECT id FROM runs ORDER BY id DESC LIMIT ?1)
+                    GROUP BY r.id, l.severity ORDER BY r.id DESC, l.severity",
+                )?;
+                let rows = statement
+                    .query_map([args.last], |row| {
+                        Ok((
+                            row.get::<_, i64>(0)?,
+                            row.get::<_, String>(1)?,
+                            row.get::<_, String>(2)?,
+                            row.get::<_, i64>(3)?,
+                        ))
+                    })?
+                    .collect::<Result<Vec<_>, _>>()?;
+                writeln!(
+                    out,
+                    "{:>5}  {:19}  {:>6}  BY SEVERITY",
+                    "RUN", "TIME", "LINTS"
+                )?;
+                for ((run, time), counts) in &rows.into_iter().group_by(|r| (r.0, r.1.clone())) {
+                    let counts: Vec<_> = counts.filter(|c| !c.2.is_empty()).collect();
+                    writeln!(
+                        out,
+                        "{:>5}  {:19}  {:>6}  {}",
+                        run,
+                        time,
+                        counts.iter().map(|c| c.3).sum::<i64>(),
+                        counts
+                            .iter()
+                            .map(|c| format!("{}: {}", c.2, c.3))
+                            .join(", ")
+                    )?;
+                }
+            }
+            Query::Coverage => {
+                let mut statement = self.conn.prepare(
+                    "SELECT r.id, datetime(r.time, 'unixepoch'), SUM(c.covered),
+                        SUM(c.uncovered), COUNT(c.path)
+                    FROM runs r JOIN coverage c ON c.run = r.id
+                    GROUP BY r.id ORDER BY r.id DESC LIMIT ?1",
+                )?;
+                writeln!(
+                    out,
+                    "{:>5}  {:19}  {:>8}  {:>9}  {:>6}",
+                    "RUN", "TIME", "COVERAGE", "LINES", "FILES"
+                )?;
+                let mut rows = statement.query([args.last])?;
+                while let Some(row) = rows.next()? {
+                    let covered: i64 = row.get(2)?;
+                    let uncovered: i64 = row.get(3)?;
+                    writeln!(
+                        out,
+                        "{:>5}  {:19}  {:>7.1}%  {:>9}  {:>6}",
+                        row.get::<_, i64>(0)?,
+                        row.get::<_, String>(1)?,
+                        percent(covered, uncovered),
+                        format!("{}/{}", covered, covered + uncovered),
+                        row.get::<_, i64>(4)?,
+                    )?;
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{report, unit};
+
+    fn history() -> History {
+        History::open(Path::new(":memory:")).unwrap()
+    }
+
+    fn count(history: &History, table: &str) -> i64 {
+        let query = format!("SELECT COUNT(*) FROM {}", table);
+        history
+            .conn
+            .query_row(&query, [], |row| row.get(0))
+            .unwrap()
+    }
+
+    fn query(history: &History, query: Query, filter: Option<&str>) -> String {
+        let args = HistoryArgs {
+            query,
+            last: 10,
+            filter: filter.map(Into::into),
+        };
+        let mut out = vec![];
+        history.query(&args, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn record() {
+        let mut history = history();
+        let mut report = report();
+        report.coverage.insert("src/lib.rs".into(), "NCCU".into());
+        history.record(&report, Some("PHID-HMBT-1")).unwrap();
+        assert_eq!(count(&history, "runs"), 1);
+        assert_eq!(count(&history, "tests"), 2);
+        assert_eq!(count(&history, "lints"), 2);
+        assert_eq!(count(&history, "coverage"), 1);
+        let (covered, uncovered): (i64, i64) = history
+            .conn
+            .query_row("SELECT covered, uncovered FROM coverage", [], |row| {
+                Ok((row.get(0)?, row.get(1)?))
+            })
+            .unwrap();
+        assert_eq!((covered, uncovered), (2, 1));
+
+        // A retried submission of the same build replaces the run, with its results
+        history.record(&report, Some("PHID-HMBT-1")).unwrap();
+        assert_eq!(count(&history, "runs"), 1);
+        assert_eq!(count(&history, "tests"), 2);
+        history.record(&report, Some("PHID-HMBT-2")).unwrap();
+        history.record(&report, None).unwrap();
+        history.record(&report, None).unwrap();
+        assert_eq!(count(&history, "runs"), 4);
+        assert_eq!(count(&history, "tests"), 8);
+
+        // The results added by cargo-harbormaster are not recorded
+        history.annotate(&mut report, 10).unwrap();
+        assert_eq!(report.units.last().unwrap().name, "Trends");
+        history.record(&report, None).unwrap();
+        assert_eq!(count(&history, "tests"), 10);
+        let runs = query(&history, Query::Runs, None);
+        assert_eq!(runs.lines().count(), 6);
+        assert!(runs
+            .lines()
+            .nth(1)
+            .unwrap()
+            .ends_with("2        1       2     66.7%"));
+    }
+
+    #[test]
+    fn trends() {
+        let mut history = history();
+        // Nothing to compare to
+        let mut report = report();
+        history.annotate(&mut report, 10).unwrap();
+        assert_eq!(report.units.len(), 2);
+        assert!(report.units[0].details.is_none());
+
+        for (duration, coverage) in [(0.5, "CU"), (1.0, "CC")] {
+            let mut report = crate::report::tests::report();
+            report.units[0].duration_s = Some(duration);
+            report.lints.pop();
+            report.coverage.insert("src/lib.rs".into(), coverage.into());
+            history.record(&report, None).unwrap();
+        }
+        let mut report = crate::report::tests::report();
+        report.units[0].duration_s = Some(1.5);
+        report.coverage.insert("src/lib.rs".into(), "CCCU".into());
+        history.annotate(&mut report, 10).unwrap();
+        assert_eq!(
+            report.units[0].details.as_deref(),
+            Some("Duration: 1.500s, +100% versus the average of 0.750s over the last 2 runs")
+        );
+        let trends = report.units.last().unwrap();
+        assert_eq!(trends.name, "Trends");
+        assert_eq!(trends.result, "pass");
+        assert_eq!(
+            trends.details.as_deref(),
+            Some(
+                "Over the last 2 runs:\n\
+                 Lints: 2, +100% versus the average of 1.0\n\
+                 Coverage: 75.0%, +0.0 points versus the average of 75.0%"
+            )
+        );
+
+        // Only the last runs are averaged
+        let mut report = crate::report::tests::report();
+        report.units[0].duration_s = Some(1.5);
+        history.annotate(&mut report, 1).unwrap();
+        assert_eq!(
+            report.units[0].details.as_deref(),
+            Some("Duration: 1.500s, +50% versus the average of 1.000s over the last 1 runs")
+        );
+    }
+
+    #[test]
+    fn flaky() {
+        let mut history = history();
+        for (result, duration) in [("pass", 1.0), ("fail", 1.0), ("pass", 2.0)] {
+            let mut flaky = unit("tests::flaky", result);
+            flaky.duration_s = Some(duration);
+            let report = Report {
+                units: vec![unit("tests::stable", "pass"), flaky],
+                ..Default::default()
+            };
+            history.record(&report, None).unwrap();
+        }
+        assert_eq!(
+            query(&history, Query::Tests, None),
+            " RUNS  FAILURES    AVERAGE       LAST  CHANGE  TEST\n    \
+                 3         1     1.333s     2.000s    +50%  my-crate tests::flaky\n    \
+                 3         0     0.500s     0.500s     +0%  my-crate tests::stable\n"
+        );
+        let filtered = query(&history, Query::Tests, Some("stable"));
+        assert_eq!(filtered.lines().count(), 2);
+        assert!(filtered.ends_with("my-crate tests::stable\n"));
+    }
+}
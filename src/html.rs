@@ -0,0 +1,191 @@
+//! Standalone HTML page of a report, with a sortable table of the tests, the lints grouped by
+//! file and the coverage of each file
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use std::io::Write;
+
+use crate::report::{Report, Sink};
+use crate::LintResult;
+
+/// Sink writing the report as a single HTML file, without external resources
+pub(crate) struct Html;
+
+fn escape(text: &str) -> String {
+    let mut out = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => out.push_str("&amp;"),
+            '<' => out.push_str("&lt;"),
+            '>' => out.push_str("&gt;"),
+            '"' => out.push_str("&quot;"),
+            '\'' => out.push_str("&#39;"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+const STYLE: &str = "
+body { font-family: sans-serif; margin: 2em; color: #222; }
+table { border-collapse: collapse; width: 100%; }
+th, td { border-bottom: 1px solid #ddd; padding: 4px 8px; text-align: left; vertical-align: top; }
+th { cursor: pointer; background: #f4f4f4; user-select: none; }
+pre { margin: 0; white-space: pre-wrap; font-size: 0.9em; }
+details summary { cursor: pointer; }
+.pass { color: #2a7a2a; } .fail, .broken, .unsound { color: #b22; } .skip { color: #888; }
+.error { color: #b22; } .warning { color: #b70; } .advice, .autofix, .disabled { color: #557; }
+.bar { background: #e6b3b3; width: 200px; height: 12px; display: inline-block; }
+.bar div { background: #5a5; height: 100%; }
+";
+
+/// Sorting of the tests table by the clicked column, numerically when possible
+const SCRIPT: &str = "
+document.querySelectorAll('th').forEach((th, column) => th.addEventListener('click', () => {
+  const body = th.closest('table').tBodies[0];
+  const ascending = th.dataset.order !== 'asc';
+  th.dataset.order = ascending ? 'asc' : 'desc';
+  const key = row => row.cells[column].dataset.value ?? row.cells[column].textContent;
+  const rows = Array.from(body.rows).sort((a, b) => {
+    const [x, y] = [key(a), key(b)];
+    const order = isNaN(x) || isNaN(y) ? x.localeCompare(y) : x - y;
+    return ascending ? order : -order;
+  });
+  body.append(...rows);
+}));
+";
+
+impl Html {
+    fn tests(report: &Report, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "<h2>Tests ({})</h2>", report.units.len())?;
+        if report.units.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "<table><thead><tr><th>Result</th><th>Namespace</th><th>Name</th>\
+             <th>Duration (s)</th><th>Details</th></tr></thead><tbody>"
+        )?;
+        for unit in &report.units {
+            let details = match unit.details.as_deref() {
+                Some(details) if !details.is_empty() => format!(
+                    "<details><summary>Show</summary><pre>{}</pre></details>",
+                    escape(details)
+                ),
+                _ => String::new(),
+            };
+            writeln!(
+                out,
+                "<tr><td class=\"{0}\">{0}</td><td>{1}</td><td>{2}</td><td data-value=\"{3}\">{4}</td>\
+                 <td>{5}</td></tr>",
+                escape(&unit.result),
+                escape(unit.namespace.as_deref().unwrap_or_default()),
+                escape(&unit.name),
+                unit.duration_s.unwrap_or_default(),
+                unit.duration_s.map(|d| format!("{:.3}", d)).unwrap_or_default(),
+                details
+            )?;
+        }
+        writeln!(out, "</tbody></table>")
+    }
+
+    fn lints(report: &Report, out: &mut String) -> std::fmt::Result {
+        writeln!(out, "<h2>Lints ({})</h2>", report.lints.len())?;
+        let mut files = BTreeMap::<&str, Vec<&LintResult>>::new();
+        for lint in &report.lints {
+            files.entry(&lint.path).or_default().push(lint);
+        }
+        for (path, mut lints) in files {
+            lints.sort_by_key(|l| (l.line, l.severity_rank()));
+            writeln!(
+                out,
+                "<details open><summary><code>{}</code> ({})</summary><table><tbody>",
+                escape(path),
+                lints.len()
+            )?;
+            for lint in lints {
+                writeln!(
+                    out,
+                    "<tr><td>{0}</td><td class=\"{1}\">{1}</td><td><code>{2}</code></td>\
+                     <td><pre>{3}</pre></td></tr>",
+                    lint.line.map(|l| l.to_string()).unwrap_or_default(),
+                    escape(&lint.severity.to_lowercase()),
+                    escape(&lint.code),
+                    escape(lint.description.as_deref().unwrap_or_default())
+                )?;
+            }
+            writeln!(out, "</tbody></table></details>")?;
+        }
+        Ok(())
+    }
+
+    fn coverage(report: &Report, out: &mut String) -> std::fmt::Result {
+        if report.coverage.is_empty() {
+            return Ok(());
+        }
+        writeln!(
+            out,
+            "<h2>Coverage</h2><table><thead><tr><th>File</th><th>Covered lines</th>\
+             <th>Coverage (%)</th></tr></thead><tbody>"
+        )?;
+        for (path, lines) in &report.coverage {
+            let covered = lines.matches('C').count();
+            let total = covered + lines.matches('U').count();
+            let percent = match total {
+                0 => 100.0,
+                total => 100.0 * covered as f64 / total as f64,
+            };
+            writeln!(
+                out,
+                "<tr><td><code>{}</code></td><td>{} / {}</td><td data-value=\"{3}\">\
+                 <span class=\"bar\"><div style=\"width: {3:.1}%\"></div></span> {3:.1}</td></tr>",
+                escape(path),
+                covered,
+                total,
+                percent
+            )?;
+        }
+        writeln!(out, "</tbody></table>")
+    }
+
+    fn page(report: &Report, out: &mut String) -> std::fmt::Result {
+        writeln!(
+            out,
+            "<!DOCTYPE html>\n<html><head><meta charset=\"utf-8\">\
+             <title>Build report</title><style>{}</style></head><body>",
+            STYLE
+        )?;
+        Self::tests(report, out)?;
+        Self::lints(report, out)?;
+        Self::coverage(report, out)?;
+        writeln!(out, "<script>{}</script></body></html>", SCRIPT)
+    }
+}
+
+impl Sink for Html {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        let mut page = String::new();
+        Self::page(report, &mut page)?;
+        out.write_all(page.as_bytes())?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{report, written};
+
+    #[test]
+    fn escapes_and_groups() {
+        let mut report = report();
+        report.units[1].details = Some("assertion failed: a < b".into());
+        report.coverage.insert("src/lib.rs".into(), "NCCU".into());
+        let html = written(&Html, &report);
+        assert!(html.contains("<h2>Tests (2)</h2>"));
+        assert!(html.contains("<pre>assertion failed: a &lt; b</pre>"));
+        assert!(html.contains("<summary><code>src/lib.rs</code> (1)</summary>"));
+        assert!(html.contains("<summary><code>src/main.rs</code> (1)</summary>"));
+        assert!(html.contains("<td>2 / 3</td>"));
+        assert!(html.contains("width: 66.7%"));
+    }
+}
@@ -0,0 +1,64 @@
+//! Reading of input files, transparently decompressing gzip and zstd files
+use std::io::{BufRead, BufReader, Read};
+use std::path::Path;
+
+use anyhow::Context;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Open an input file for streaming. Compression is detected from the magic bytes, so that
+/// the file extension does not matter.
+pub(crate) fn open(path: &Path) -> anyhow::Result<Box<dyn BufRead + Send>> {
+    let file = std::fs::File::open(path).with_context(|| format!("Failed to open {:?}", path))?;
+    let mut reader = BufReader::new(file);
+    let magic = reader.fill_buf()?;
+    Ok(if magic.starts_with(GZIP_MAGIC) {
+        Box::new(BufReader::new(flate2::bufread::MultiGzDecoder::new(reader)))
+    } else if magic.starts_with(ZSTD_MAGIC) {
+        Box::new(BufReader::new(zstd::Decoder::with_buffer(reader)?))
+    } else {
+        Box::new(reader)
+    })
+}
+
+pub(crate) fn read_to_string(path: &Path) -> anyhow::Result<String> {
+    let mut data = String::new();
+    open(path)?
+        .read_to_string(&mut data)
+        .with_context(|| format!("Failed to read {:?}", path))?;
+    Ok(data)
+}
+
+/// Read a text input, e.g. captured terminal output, without its ANSI escape sequences
+pub(crate) fn read_text(path: &Path) -> anyhow::Result<String> {
+    let data = read_to_string(path)?;
+    Ok(strip_ansi(&data).into_owned())
+}
+
+/// Remove the ANSI escape sequences from a text: control sequences (e.g. colors or cursor
+/// movements), operating system commands (e.g. hyperlinks or window titles), character set
+/// selections and other escapes
+pub(crate) fn strip_ansi(text: &str) -> std::borrow::Cow<'_, str> {
+    static ANSI: std::sync::OnceLock<regex::Regex> = std::sync::OnceLock::new();
+    ANSI.get_or_init(|| {
+        regex::Regex::new(
+            r"\x1b(?:\[[0-?]*[ -/]*[@-~]|\][^\x07\x1b\n]*(?:\x07|\x1b\\)?|[()][0-9A-Za-z]|[@-Z\\-_=>])",
+        )
+        .unwrap()
+    })
+    .replace_all(text, "")
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::path::PathBuf;
+
+    /// Write a fixture to a temporary file, unique to the test process
+    pub(crate) fn fixture(name: &str, contents: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("harbormaster-{}-{}", std::process::id(), name));
+        std::fs::write(&path, contents).unwrap();
+        path
+    }
+}
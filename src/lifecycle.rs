@@ -0,0 +1,55 @@
+//! Bare status transitions of a build target (e.g. `work` when a job starts, `abort` from a shell
+//! trap), without parsing any input
+use anyhow::Context;
+use log::*;
+
+use crate::{config, submit, Auth, Failure, Flags, Params, Status};
+
+#[derive(clap::Args)]
+pub(crate) struct LifecycleArgs {
+    /// Build PHID (PHID-...)
+    build_phid: String,
+    /// Status to send, e.g. work when the job starts, restart on a retry or abort on cancellation
+    #[clap(value_enum)]
+    status: Status,
+}
+
+pub(crate) fn run(
+    args: &Flags,
+    config: &config::Config,
+    lifecycle: &LifecycleArgs,
+) -> anyhow::Result<()> {
+    anyhow::ensure!(
+        !matches!(lifecycle.status, Status::Auto),
+        "The auto status requires inputs"
+    );
+    let uri = args
+        .conduit_uri
+        .as_deref()
+        .context("--conduit-uri is required")?;
+    let token = args.token.as_deref().context("--token is required")?;
+    let params = Params {
+        build: lifecycle.build_phid.clone(),
+        status: lifecycle.status,
+        unit: None,
+        lint: None,
+        auth: Auth {
+            token: token.into(),
+        },
+    };
+    params.validate().context(Failure::Validation)?;
+    let client = crate::client(args, config, uri, token)?;
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(async {
+            if args.dry_run {
+                client.check().await?;
+                info!("Dry run, not sending the {:?} status", lifecycle.status);
+                return Ok(());
+            }
+            submit::send(&client, &params, None, 1).await?;
+            info!("Sent the {:?} status to {}", lifecycle.status, params.build);
+            anyhow::Ok(())
+        })
+}
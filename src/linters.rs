@@ -0,0 +1,428 @@
+//! Parsers for the JSON outputs of linters for other languages, and of cross-language linters
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::paths::PathResolver;
+use crate::{input, LintResult};
+
+/// Finding of `shellcheck -f json` (or `-f json1`)
+#[derive(Deserialize)]
+struct ShellcheckComment {
+    file: String,
+    line: usize,
+    column: usize,
+    level: String,
+    code: u32,
+    message: String,
+}
+
+/// Parse the output of `shellcheck -f json` or `shellcheck -f json1`
+pub(crate) fn shellcheck(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    #[derive(Deserialize)]
+    #[serde(untagged)]
+    enum Output {
+        Json(Vec<ShellcheckComment>),
+        Json1 { comments: Vec<ShellcheckComment> },
+    }
+    let comments = match serde_json::from_str(&input::read_to_string(path)?)? {
+        Output::Json(comments) | Output::Json1 { comments } => comments,
+    };
+    Ok(comments
+        .into_iter()
+        .map(|c| LintResult {
+            name: "shellcheck".into(),
+            code: format!("SC{}", c.code),
+            severity: match c.level.as_str() {
+                "error" => "error",
+                "warning" => "warning",
+                // info and style
+                _ => "advice",
+            }
+            .into(),
+            path: resolver
+                .resolve(Path::new(&c.file), None)
+                .to_string_lossy()
+                .to_string(),
+            line: Some(c.line),
+            position: Some(c.column),
+            description: Some(format!(
+                "{}\n\nSee https://www.shellcheck.net/wiki/SC{}",
+                c.message, c.code
+            )),
+        })
+        .collect())
+}
+
+/// File of the ESLint JSON formatter output
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EslintFile {
+    file_path: String,
+    messages: Vec<EslintMessage>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct EslintMessage {
+    /// Absent e.g. for parsing errors
+    rule_id: Option<String>,
+    severity: u8,
+    message: String,
+    line: Option<usize>,
+    column: Option<usize>,
+}
+
+/// Parse the output of `eslint --format json`. As the paths are absolute, those within the
+/// directory it ran in are made relative to it.
+pub(crate) fn eslint(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    let files: Vec<EslintFile> = serde_json::from_str(&input::read_to_string(path)?)?;
+    Ok(files
+        .into_iter()
+        .flat_map(|file| {
+            let path = resolver
+                .resolve(Path::new(&file.file_path), Some(&resolver.cwd))
+                .to_string_lossy()
+                .to_string();
+            file.messages.into_iter().map(move |m| LintResult {
+                name: "eslint".into(),
+                code: m.rule_id.unwrap_or_else(|| "eslint-error".into()),
+                severity: if m.severity >= 2 { "error" } else { "warning" }.into(),
+                path: path.clone(),
+                line: m.line,
+                position: m.column,
+                description: Some(m.message),
+            })
+        })
+        .collect())
+}
+
+/// Output of `golangci-lint run --out-format json`
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GolangciReport {
+    issues: Option<Vec<GolangciIssue>>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GolangciIssue {
+    from_linter: String,
+    text: String,
+    #[serde(default)]
+    severity: String,
+    pos: GolangciPosition,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct GolangciPosition {
+    filename: String,
+    line: usize,
+    column: usize,
+}
+
+/// Parse the output of `golangci-lint run --out-format json`. The codes are the linter names,
+/// followed by the check id when the message starts with one (e.g. `staticcheck:SA1019`).
+pub(crate) fn golangci(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    let report: GolangciReport = serde_json::from_str(&input::read_to_string(path)?)?;
+    let check = regex::Regex::new(r"^([A-Z]+[0-9]+): (.*)$")?;
+    Ok(report
+        .issues
+        .unwrap_or_default()
+        .into_iter()
+        .map(|issue| {
+            let (code, description) = match check.captures(&issue.text) {
+                Some(c) => (format!("{}:{}", issue.from_linter, &c[1]), c[2].to_string()),
+                None => (issue.from_linter.clone(), issue.text.clone()),
+            };
+            LintResult {
+                name: "golangci-lint".into(),
+                code,
+                // Only set when configured with severity rules
+                severity: match issue.severity.to_lowercase().as_str() {
+                    "error" => "error",
+                    "info" | "note" => "advice",
+                    _ => "warning",
+                }
+                .into(),
+                path: resolver
+                    .resolve(Path::new(&issue.pos.filename), None)
+                    .to_string_lossy()
+                    .to_string(),
+                line: Some(issue.pos.line),
+                position: Some(issue.pos.column),
+                description: Some(description),
+            }
+        })
+        .collect())
+}
+
+/// Message of `typos --format json`, one per line
+#[derive(Deserialize)]
+struct TyposMessage {
+    #[serde(rename = "type")]
+    kind: String,
+    path: Option<String>,
+    line_num: Option<usize>,
+    /// Within the line, from 0
+    byte_offset: Option<usize>,
+    typo: Option<String>,
+    #[serde(default)]
+    corrections: Vec<String>,
+}
+
+/// Parse the output of `typos --format json`, with an advice per typo
+pub(crate) fn typos(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    let mut lints = vec![];
+    for line in input::read_to_string(path)?.lines() {
+        if line.trim().is_empty() {
+            continue;
+        }
+        let message: TyposMessage = serde_json::from_str(line)?;
+        // Other messages are e.g. about skipped binary files
+        let (Some(file), Some(typo), "typo") = (message.path, message.typo, message.kind.as_str())
+        else {
+            continue;
+        };
+        lints.push(LintResult {
+            name: "typos".into(),
+            code: "typo".into(),
+            severity: "advice".into(),
+            path: resolver
+                .resolve(Path::new(&file), None)
+                .to_string_lossy()
+                .to_string(),
+            line: message.line_num,
+            position: message.byte_offset.map(|o| o + 1),
+            description: Some(match message.corrections.as_slice() {
+                [] => format!("`{}` is a typo", typo),
+                corrections => format!(
+                    "`{}` should be {}",
+                    typo,
+                    corrections
+                        .iter()
+                        .map(|c| format!("`{}`", c))
+                        .collect::<Vec<_>>()
+                        .join(" or ")
+                ),
+            }),
+        });
+    }
+    Ok(lints)
+}
+
+/// Entry of the generic lints format
+#[derive(Deserialize)]
+struct GenericLint {
+    path: String,
+    line: Option<usize>,
+    #[serde(rename = "char")]
+    position: Option<usize>,
+    code: String,
+    severity: String,
+    message: String,
+    name: Option<String>,
+}
+
+/// Parse a JSON array of `{path, line, char, code, severity, message, name}` objects, for tools
+/// without first-class support. `line`, `char` and `name` are optional.
+pub(crate) fn generic(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    let lints: Vec<GenericLint> = serde_json::from_str(&input::read_to_string(path)?)?;
+    Ok(lints
+        .into_iter()
+        .map(|lint| LintResult {
+            name: lint.name.unwrap_or_else(|| "generic".into()),
+            code: lint.code,
+            severity: lint.severity.to_lowercase(),
+            path: resolver
+                .resolve(Path::new(&lint.path), None)
+                .to_string_lossy()
+                .to_string(),
+            line: lint.line,
+            position: lint.position,
+            description: Some(lint.message),
+        })
+        .collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    fn resolver() -> PathResolver {
+        PathResolver {
+            workspace: "crates".into(),
+            maps: vec![],
+            cwd: "/ws".into(),
+        }
+    }
+
+    /// Code, severity, path, line and position of a lint
+    type Summary<'a> = (&'a str, &'a str, &'a str, Option<usize>, Option<usize>);
+
+    fn summary(lints: &[LintResult]) -> Vec<Summary<'_>> {
+        lints
+            .iter()
+            .map(|l| {
+                let (code, severity, path) = (&l.code, &l.severity, &l.path);
+                (
+                    code.as_str(),
+                    severity.as_str(),
+                    path.as_str(),
+                    l.line,
+                    l.position,
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn shellcheck_outputs() {
+        let json = r#"[
+            {"file": "ci/run.sh", "line": 3, "endLine": 3, "column": 8, "endColumn": 12,
+             "level": "warning", "code": 2086, "message": "Double quote to prevent globbing."},
+            {"file": "ci/run.sh", "line": 7, "column": 1, "level": "style", "code": 2006,
+             "message": "Use $(...) notation instead of legacy backticks."}
+        ]"#;
+        let lints = shellcheck(&fixture("shellcheck.json", json), &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [
+                ("SC2086", "warning", "crates/ci/run.sh", Some(3), Some(8)),
+                ("SC2006", "advice", "crates/ci/run.sh", Some(7), Some(1)),
+            ]
+        );
+        assert!(lints[0]
+            .description
+            .as_ref()
+            .unwrap()
+            .ends_with("See https://www.shellcheck.net/wiki/SC2086"));
+
+        let json1 = r#"{"comments": [{"file": "run.sh", "line": 1, "column": 2,
+            "level": "error", "code": 1009, "message": "The mentioned syntax error was in..."}]}"#;
+        let lints = shellcheck(&fixture("shellcheck1.json", json1), &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [("SC1009", "error", "crates/run.sh", Some(1), Some(2))]
+        );
+    }
+
+    #[test]
+    fn eslint_output() {
+        let json = serde_json::json!([
+            {
+                "filePath": "/ws/web/src/app.js",
+                "messages": [
+                    {"ruleId": "no-unused-vars", "severity": 2, "message": "'x' is unused.",
+                     "line": 4, "column": 7},
+                    {"ruleId": "eqeqeq", "severity": 1, "message": "Expected '==='.",
+                     "line": 9, "column": 13},
+                    {"ruleId": null, "fatal": true, "severity": 2,
+                     "message": "Parsing error: Unexpected token"}
+                ]
+            },
+            {"filePath": "/ws/web/src/clean.js", "messages": []}
+        ]);
+        let path = fixture("eslint.json", &json.to_string());
+        let lints = eslint(&path, &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [
+                (
+                    "no-unused-vars",
+                    "error",
+                    "crates/web/src/app.js",
+                    Some(4),
+                    Some(7)
+                ),
+                (
+                    "eqeqeq",
+                    "warning",
+                    "crates/web/src/app.js",
+                    Some(9),
+                    Some(13)
+                ),
+                ("eslint-error", "error", "crates/web/src/app.js", None, None),
+            ]
+        );
+    }
+
+    #[test]
+    fn golangci_output() {
+        let json = r#"{"Issues": [
+            {"FromLinter": "staticcheck", "Text": "SA1019: ioutil.ReadAll is deprecated",
+             "Severity": "", "Pos": {"Filename": "cmd/main.go", "Line": 12, "Column": 2}},
+            {"FromLinter": "errcheck", "Text": "Error return value is not checked",
+             "Severity": "error", "Pos": {"Filename": "pkg/io.go", "Line": 30, "Column": 9}},
+            {"FromLinter": "godot", "Text": "Comment should end in a period",
+             "Severity": "info", "Pos": {"Filename": "pkg/io.go", "Line": 1, "Column": 1}}
+        ], "Report": {}}"#;
+        let lints = golangci(&fixture("golangci.json", json), &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [
+                (
+                    "staticcheck:SA1019",
+                    "warning",
+                    "crates/cmd/main.go",
+                    Some(12),
+                    Some(2)
+                ),
+                ("errcheck", "error", "crates/pkg/io.go", Some(30), Some(9)),
+                ("godot", "advice", "crates/pkg/io.go", Some(1), Some(1)),
+            ]
+        );
+        assert_eq!(
+            lints[0].description.as_deref(),
+            Some("ioutil.ReadAll is deprecated")
+        );
+
+        let empty = golangci(
+            &fixture("golangci-empty.json", r#"{"Issues": null}"#),
+            &resolver(),
+        );
+        assert!(empty.unwrap().is_empty());
+    }
+
+    #[test]
+    fn generic_format() {
+        let json = r#"[
+            {"path": "tools/gen.py", "line": 5, "char": 3, "code": "E501", "severity": "Warning",
+             "message": "line too long", "name": "flake8"},
+            {"path": "LICENSE", "code": "license", "severity": "error", "message": "missing"}
+        ]"#;
+        let lints = generic(&fixture("generic.json", json), &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [
+                ("E501", "warning", "crates/tools/gen.py", Some(5), Some(3)),
+                ("license", "error", "crates/LICENSE", None, None),
+            ]
+        );
+        assert_eq!(lints[0].name, "flake8");
+        assert_eq!(lints[1].name, "generic");
+    }
+
+    #[test]
+    fn typos_output() {
+        let jsonl = concat!(
+            r#"{"type":"typo","path":"src/lib.rs","line_num":3,"byte_offset":0,"typo":"teh","corrections":["the"]}"#,
+            "\n",
+            r#"{"type":"binary_file","path":"assets/logo.png"}"#,
+            "\n\n",
+            r#"{"type":"typo","path":"README.md","line_num":10,"byte_offset":14,"typo":"ba","corrections":["by","be"]}"#,
+            "\n",
+        );
+        let lints = typos(&fixture("typos.jsonl", jsonl), &resolver()).unwrap();
+        assert_eq!(
+            summary(&lints),
+            [
+                ("typo", "advice", "crates/src/lib.rs", Some(3), Some(1)),
+                ("typo", "advice", "crates/README.md", Some(10), Some(15)),
+            ]
+        );
+        assert_eq!(
+            lints[1].description.as_deref(),
+            Some("`ba` should be `by` or `be`")
+        );
+    }
+}
@@ -0,0 +1,46 @@
+//! Logs as text or JSON lines (`--log-format`), with the secrets redacted and the duration of the
+//! stages of the reporting (parsing of each input, submission of each chunk...)
+use std::io::{IsTerminal, Write};
+
+use tracing_subscriber::fmt::format::FmtSpan;
+use tracing_subscriber::EnvFilter;
+
+use crate::redact;
+
+#[derive(Debug, Copy, Clone, Default, clap::ValueEnum)]
+pub(crate) enum LogFormat {
+    /// Human-readable lines
+    #[default]
+    Text,
+    /// A JSON object per line, with the fields of the event and of its spans
+    Json,
+}
+
+/// Writer of the log lines to stderr, redacting the secrets. The lines are written at once, so
+/// that secrets are not split across writes.
+struct Redacted;
+impl Write for Redacted {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        std::io::stderr().write_all(redact::redact_log(&line).as_bytes())?;
+        Ok(buf.len())
+    }
+    fn flush(&mut self) -> std::io::Result<()> {
+        std::io::stderr().flush()
+    }
+}
+
+/// Initialize the logs, filtered with `RUST_LOG` (`info` by default). The closed spans are
+/// logged with their duration, in `time.busy` and `time.idle`.
+pub(crate) fn init(format: LogFormat) {
+    let builder = tracing_subscriber::fmt()
+        .with_env_filter(
+            EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("info")),
+        )
+        .with_writer(|| Redacted)
+        .with_span_events(FmtSpan::CLOSE);
+    match format {
+        LogFormat::Text => builder.with_ansi(std::io::stderr().is_terminal()).init(),
+        LogFormat::Json => builder.json().init(),
+    }
+}
@@ -1,37 +1,883 @@
 use std::collections::{HashMap, HashSet};
 use std::hash::Hash;
+use std::io::{BufRead, Write};
 use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
+use cargo_metadata::diagnostic::DiagnosticLevel;
 use cargo_metadata::Message;
-use clap::{Parser, ValueEnum};
+use clap::{CommandFactory, Parser, ValueEnum};
+use itertools::Itertools;
 use log::*;
-use serde::Serialize;
+use serde::{Deserialize, Serialize};
+use tracing::field::Empty;
 
+mod bloat;
+mod budget;
+mod cache;
+mod conduit;
+mod config;
+mod coverage;
+mod diff;
+mod fetch;
+mod geiger;
+mod gitlab;
+mod history;
+mod html;
+mod input;
+mod lifecycle;
+mod linters;
+mod logging;
+mod merge;
+mod metrics;
+mod naming;
+mod nextest;
+mod parser;
+mod paths;
+mod redact;
+mod remarkup;
+mod report;
+mod rustfmt;
+mod sarif;
+mod schema;
+mod semver;
+mod serve;
+mod submit;
+mod summary;
+mod teamcity;
+mod truncate;
+mod verify;
+#[cfg(feature = "wasm")]
+mod wasm;
+mod watch;
+
+use paths::{PathMap, PathResolver};
+use report::{Report, Sink};
+
+/// Export the results of cargo check, clippy, nextest and other tools to Phabricator's
+/// Harbormaster
 #[derive(Parser)]
+#[clap(version)]
 struct Flags {
-    /// Path to the rust workspace relative to the repository root
-    #[clap(long)]
-    workspace: Option<PathBuf>,
+    #[clap(subcommand)]
+    command: Option<Command>,
+    #[clap(flatten)]
+    inputs: Inputs,
+    /// Path to a configuration file (e.g. harbormaster.toml)
+    #[clap(long, global = true)]
+    config: Option<PathBuf>,
+    /// How to merge the tests of workspace sections that only differ by their feature set
+    #[clap(long, value_enum, default_value_t)]
+    matrix_merge: merge::MatrixMerge,
+    #[clap(flatten)]
+    options: Options,
     /// Phabricator API token
-    #[clap(long, env = "PHAB_TOKEN")]
-    token: String,
+    #[clap(long, env = "PHAB_TOKEN", global = true)]
+    token: Option<String>,
     /// Build status
     #[clap(long)]
-    status: Status,
+    status: Option<Status>,
     /// Build PHID (PHID-...)
-    build_phid: String,
+    build_phid: Option<String>,
+    /// Output format
+    #[clap(long, value_enum, default_value_t = Format::Harbormaster)]
+    format: Format,
+    /// Path to write the output to ('-' for stdout). Defaults to stdout unless sending to Conduit
+    #[clap(long, short)]
+    output: Option<PathBuf>,
+    /// Also write a standalone HTML page of the report, with a sortable table of the tests, the
+    /// lints grouped by file and the coverage of each file
+    #[clap(long, value_name = "PATH")]
+    html_report: Option<PathBuf>,
+    /// Attach the --html-report to the build target as an artifact when sending to Conduit
+    #[clap(long, requires = "html_report", requires = "conduit_uri")]
+    attach_html_report: bool,
+    /// Emit compact rather than pretty-printed JSON
+    #[clap(long)]
+    compact: bool,
+    /// Include the API token in the printed message. It is omitted by default, as the output
+    /// often ends up in the CI logs.
+    #[clap(long)]
+    print_secrets: bool,
+    /// Redact the matches of this regular expression from the logs and results, like the API
+    /// token (repeatable)
+    #[clap(long, value_name = "REGEX", global = true)]
+    redact: Vec<String>,
+    /// Phabricator URI (https://...). When passed, the message is sent to harbormaster.sendmessage
+    #[clap(long, env = "PHAB_URI", global = true)]
+    conduit_uri: Option<String>,
+    /// Conduit server implementation, which names the server in the logs and errors and selects
+    /// the documentation of the hints
+    #[clap(long, value_enum, default_value_t, global = true)]
+    flavor: conduit::Flavor,
+    /// Build and validate the message, and check the connection and token, without sending it
+    #[clap(long, requires = "conduit_uri", global = true)]
+    dry_run: bool,
+    /// Keep watching the inputs after sending the results, sending the new results whenever they
+    /// change, and the final status on Ctrl-C
+    #[clap(long, requires = "conduit_uri", conflicts_with = "dry_run")]
+    watch: bool,
+    /// Directory recording the results sent to each build target, so that reruns only send
+    /// the new results along with the status
+    #[clap(long)]
+    cache_dir: Option<PathBuf>,
+    /// Maximum number of results per message. Larger payloads are split into several messages.
+    #[clap(long)]
+    chunk_size: Option<usize>,
+    /// Maximum number of concurrent Conduit requests
+    #[clap(long, default_value_t = 4)]
+    concurrency: usize,
+    /// Timeout of each Conduit request, in seconds
+    #[clap(long, default_value_t = 60, global = true)]
+    timeout: u64,
+    /// Maximum total duration of the Conduit requests, in seconds, including the retries and
+    /// chunks. When exceeded, the unsent results are written to --spill-file and the exit code
+    /// is 6.
+    #[clap(long, value_name = "SECS", requires = "conduit_uri")]
+    deadline: Option<f64>,
+    /// File receiving the results not sent before the --deadline
+    #[clap(long, default_value = "harbormaster-unsent.json")]
+    spill_file: PathBuf,
+    /// Send the results written to a spill file by a previous run, instead of parsing the inputs.
+    /// The file is removed once sent.
+    #[clap(long, value_name = "PATH", requires = "conduit_uri")]
+    resend: Option<PathBuf>,
+    /// Directory of the journals recording the chunks acknowledged by Conduit, with --resume
+    #[clap(long, default_value = ".harbormaster-journal")]
+    journal_dir: PathBuf,
+    /// Record the chunks acknowledged by Conduit in a journal, and skip the ones acknowledged
+    /// during a previous interrupted submission of the same results to the same build target
+    #[clap(long, requires = "conduit_uri", requires = "chunk_size")]
+    resume: bool,
+    /// Maximum number of Conduit requests per second. Rate-limited requests are retried in any
+    /// case, after the delay requested by the server.
+    #[clap(long, global = true)]
+    max_rps: Option<f64>,
+    /// TLS client certificate, either PEM (with --client-key) or a PKCS#12 archive containing
+    /// the key
+    #[clap(long, requires = "conduit_uri", global = true)]
+    client_cert: Option<PathBuf>,
+    /// PEM (PKCS#8) private key of the TLS client certificate
+    #[clap(long, requires = "client_cert", global = true)]
+    client_key: Option<PathBuf>,
+    /// Password of the PKCS#12 client certificate
+    #[clap(
+        long,
+        env = "PHAB_CLIENT_CERT_PASSWORD",
+        hide_env_values = true,
+        global = true
+    )]
+    client_cert_password: Option<String>,
+    /// Additional HTTP header of the Conduit requests ('Name: value'), e.g. for a reverse proxy
+    /// requiring its own credentials. Takes precedence over the headers of the configuration file.
+    #[clap(
+        long = "header",
+        value_name = "NAME: VALUE",
+        requires = "conduit_uri",
+        global = true
+    )]
+    headers: Vec<conduit::Header>,
+    /// Maximum number of lints to report, keeping the most severe ones.
+    /// When sending to Conduit, the full list is attached as an artifact.
+    #[clap(long)]
+    max_lints: Option<usize>,
+    /// Maximum number of warnings, in total (N), of a lint code (CODE=N) or in a package of the
+    /// workspace (package:NAME=N). Exceeding a budget adds an error lint explaining it, which
+    /// fails the build with --status auto. Can be repeated.
+    #[clap(long, value_name = "BUDGET")]
+    max_warnings: Vec<budget::Budget>,
+    /// Exit with code 1 after reporting the results if there are failed tests (tests), error lints
+    /// (errors) or warning or error lints (warnings). Can be repeated.
+    #[clap(long, value_enum)]
+    fail_on: Vec<FailOn>,
+    /// Exit with code 7 after reporting the other results if an input could not be parsed,
+    /// rather than only warning about it
+    #[clap(long)]
+    strict: bool,
+    /// Only report the lints on the lines changed since --diff-base, or on the staged changes
+    #[clap(long)]
+    changed_only: bool,
+    /// Git revision to compare to with --changed-only (e.g. origin/main)
+    #[clap(long, requires = "changed_only")]
+    diff_base: Option<String>,
+    /// With --changed-only, still report the error lints outside of the changed lines
+    #[clap(long, requires = "changed_only")]
+    keep_errors: bool,
+    /// Only report the tests that did not pass, the slowest passing ones (see --keep-slowest)
+    /// and a summary of the omitted ones
+    #[clap(long)]
+    only_failures: bool,
+    /// Truncate the test details (e.g. failure outputs) longer than this, noting the truncation
+    #[clap(long, value_name = "BYTES")]
+    max_details_bytes: Option<usize>,
+    /// Part of the details kept when truncating
+    #[clap(long, value_enum, default_value_t)]
+    truncate_keep: truncate::Keep,
+    /// Number of slowest passing tests reported with --only-failures
+    #[clap(long, default_value_t = 10)]
+    keep_slowest: usize,
+    /// SQLite database recording the test durations, lint counts and coverage of each run, for
+    /// the history subcommand. A rerun of the same build replaces its results.
+    #[clap(long, global = true)]
+    history_db: Option<PathBuf>,
+    /// Annotate the tests with the change of their duration versus the average over the
+    /// previous runs in --history-db, and report the changes of the lint count and coverage
+    #[clap(long, requires = "history_db")]
+    history_deltas: bool,
+    /// Number of previous runs averaged with --history-deltas
+    #[clap(long, default_value_t = 10)]
+    history_window: usize,
+    /// Write the metrics of the run (results, lint counts, payload size, submission latency) to
+    /// this file, for the Prometheus node exporter textfile collector
+    #[clap(long, value_name = "PATH")]
+    metrics_file: Option<PathBuf>,
+    /// Push the metrics of the run to this Prometheus Pushgateway (e.g. http://pushgateway:9091)
+    #[clap(long, value_name = "URL")]
+    pushgateway: Option<String>,
+    /// Job name of the metrics pushed to the Pushgateway
+    #[clap(long, default_value = "cargo-harbormaster")]
+    pushgateway_job: String,
+    /// Print the man page (in roff) and exit
+    #[clap(long)]
+    generate_manpage: bool,
+    /// Format of the logs, which are filtered with RUST_LOG
+    #[clap(long, value_enum, default_value_t, global = true)]
+    log_format: logging::LogFormat,
+}
+
+#[derive(clap::Subcommand)]
+enum Command {
+    /// Serve an endpoint for Harbormaster "Make HTTP Request" build steps, running the builds
+    /// described in the [serve] section of the configuration file
+    Serve(serve::ServeArgs),
+    /// Show the trends recorded in --history-db
+    History(history::HistoryArgs),
+    /// Send a bare status transition (e.g. work, restart or abort) to a build target, without
+    /// parsing any input, e.g. from the traps of CI scripts
+    Lifecycle(lifecycle::LifecycleArgs),
+    /// Print the completions script of a shell
+    Completions {
+        #[clap(value_enum)]
+        shell: clap_complete::Shell,
+    },
+}
+
+/// Options applying to all inputs
+#[derive(clap::Args)]
+struct Options {
+    /// Rewrite diagnostic paths starting with FROM to start with TO instead (FROM=TO, repeatable)
+    #[clap(long, value_name = "FROM=TO")]
+    path_map: Vec<PathMap>,
+    /// Template for the test namespaces, with the placeholders {binary_id}, {package},
+    /// {binary} and {module}
+    #[clap(long, default_value = "{binary_id}")]
+    namespace_format: String,
+    /// Name of the tests
+    #[clap(long, value_enum, default_value_t)]
+    test_name: naming::TestName,
+    /// Emit an advice lint for each test slower than this duration, in seconds
+    #[clap(long, value_name = "SECS")]
+    slow_test_lint: Option<f32>,
+    /// Mention the number of targets (lib, bins, tests...) for which each lint was reported
+    #[clap(long)]
+    count_lint_targets: bool,
+    /// Template for the names of the cargo lints, with the placeholders {tool} (cargo-clippy or
+    /// cargo-check), {package} and {target}
+    #[clap(long, default_value = "{tool}")]
+    lint_name_format: String,
+}
+
+/// Inputs for a workspace, passed on the command line or in a `[[workspace]]` config section
+#[derive(clap::Args, Clone, Debug, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+struct Inputs {
+    /// Path to the rust workspace relative to the repository root.
+    /// Detected with git and cargo metadata by default.
+    #[clap(long)]
+    #[serde(rename = "path")]
+    workspace: Option<PathBuf>,
     /// Path to 'cargo clippy --message-format=json' output
     #[clap(long)]
     clippy_json: Option<PathBuf>,
     /// Path to 'cargo check --message-format=json' output
     #[clap(long, conflicts_with = "clippy_json")]
     check_json: Option<PathBuf>,
-    /// Path to 'cargo nextest' stderr output
+    /// Path to 'cargo nextest' stderr output.
+    /// Can be repeated to merge the outputs of a partitioned run.
+    #[clap(long)]
+    #[serde(default, deserialize_with = "config::one_or_many")]
+    nextest_stderr: Vec<PathBuf>,
+    /// Path to 'cargo nextest list --message-format json' output, to report the listed tests
+    /// without results as skipped or broken
+    #[clap(long, requires = "nextest_stderr")]
+    nextest_list: Option<PathBuf>,
+    /// Path to 'cargo test --doc' output
+    #[clap(long)]
+    doctest_output: Option<PathBuf>,
+    /// Path to an LCOV coverage report (e.g. from 'cargo llvm-cov --lcov' or
+    /// 'cargo tarpaulin --out Lcov'). Can be repeated to merge the coverage of several runs.
+    #[clap(long)]
+    #[serde(default, deserialize_with = "config::one_or_many")]
+    lcov: Vec<PathBuf>,
+    /// Path to 'cargo fmt --check' output
+    #[clap(long)]
+    rustfmt_check: Option<PathBuf>,
+    /// Path to 'cargo semver-checks --output-format json' output
     #[clap(long)]
-    nextest_stderr: Option<PathBuf>,
+    semver_checks_json: Option<PathBuf>,
+    /// Path to 'cargo bloat --message-format json' output
+    #[clap(long)]
+    bloat_json: Option<PathBuf>,
+    /// Path to 'cargo geiger --output-format Json' output
+    #[clap(long)]
+    geiger_json: Option<PathBuf>,
+    /// Previous 'cargo geiger' output, to only report the crates using more unsafe code
+    #[clap(long, requires = "geiger_json")]
+    geiger_baseline: Option<PathBuf>,
+    /// Path to 'shellcheck -f json' output
+    #[clap(long)]
+    shellcheck_json: Option<PathBuf>,
+    /// Path to 'eslint --format json' output
+    #[clap(long)]
+    eslint_json: Option<PathBuf>,
+    /// Path to 'golangci-lint run --out-format json' output
+    #[clap(long)]
+    golangci_json: Option<PathBuf>,
+    /// Path to 'typos --format json' output
+    #[clap(long)]
+    typos_json: Option<PathBuf>,
+    /// Path to lints in the generic JSON format (see the README)
+    #[clap(long)]
+    generic_lints: Option<PathBuf>,
+    /// Input parsed by a compiled-in or plugin parser (NAME=PATH, repeatable)
+    #[clap(long, value_name = "NAME=PATH")]
+    #[serde(default)]
+    parser_input: Vec<parser::ParserInput>,
+    /// Engine of the test results [default: cargo-nextest]
+    #[clap(long)]
+    engine: Option<String>,
+    /// Name of the lints [default: cargo-clippy or cargo-check, depending on the lint]
+    #[clap(long)]
+    lint_name: Option<String>,
+    /// Feature set the inputs were produced with, for runs over a feature matrix
+    #[clap(skip)]
+    features: Option<String>,
+}
+impl Inputs {
+    /// Paths of all input files
+    fn paths(&self) -> impl Iterator<Item = &PathBuf> {
+        self.clippy_json
+            .iter()
+            .chain(&self.check_json)
+            .chain(&self.nextest_stderr)
+            .chain(&self.nextest_list)
+            .chain(&self.doctest_output)
+            .chain(&self.lcov)
+            .chain(&self.rustfmt_check)
+            .chain(&self.semver_checks_json)
+            .chain(&self.bloat_json)
+            .chain(&self.geiger_json)
+            .chain(&self.shellcheck_json)
+            .chain(&self.eslint_json)
+            .chain(&self.golangci_json)
+            .chain(&self.typos_json)
+            .chain(&self.generic_lints)
+            .chain(self.parser_input.iter().map(|i| &i.path))
+    }
+    /// Mutable paths of all input files, including the baseline of cargo-geiger
+    fn paths_mut(&mut self) -> impl Iterator<Item = &mut PathBuf> {
+        self.clippy_json
+            .iter_mut()
+            .chain(&mut self.check_json)
+            .chain(&mut self.nextest_stderr)
+            .chain(&mut self.nextest_list)
+            .chain(&mut self.doctest_output)
+            .chain(&mut self.lcov)
+            .chain(&mut self.rustfmt_check)
+            .chain(&mut self.semver_checks_json)
+            .chain(&mut self.bloat_json)
+            .chain(&mut self.geiger_json)
+            .chain(&mut self.geiger_baseline)
+            .chain(&mut self.shellcheck_json)
+            .chain(&mut self.eslint_json)
+            .chain(&mut self.golangci_json)
+            .chain(&mut self.typos_json)
+            .chain(&mut self.generic_lints)
+            .chain(self.parser_input.iter_mut().map(|i| &mut i.path))
+    }
+    fn is_empty(&self) -> bool {
+        self.clippy_json.is_none()
+            && self.check_json.is_none()
+            && self.nextest_stderr.is_empty()
+            && self.doctest_output.is_none()
+            && self.lcov.is_empty()
+            && self.rustfmt_check.is_none()
+            && self.semver_checks_json.is_none()
+            && self.bloat_json.is_none()
+            && self.geiger_json.is_none()
+            && self.shellcheck_json.is_none()
+            && self.eslint_json.is_none()
+            && self.golangci_json.is_none()
+            && self.typos_json.is_none()
+            && self.generic_lints.is_none()
+            && self.parser_input.is_empty()
+    }
+    /// Path to the workspace relative to the repository root, detected from `dir` if not passed
+    fn workspace(&self, dir: &Path) -> PathBuf {
+        let workspace = self.workspace.clone().unwrap_or_else(|| {
+            paths::detect_workspace(dir).unwrap_or_else(|e| {
+                warn!(
+                    "Failed to detect the workspace path, assuming the repository root: {:#}",
+                    e
+                );
+                PathBuf::new()
+            })
+        });
+        info!("Workspace path: {:?}", workspace);
+        workspace
+    }
+    /// Parse all inputs, each in its own thread, as produced by tools running in `dir`
+    fn parse(
+        &self,
+        dir: &Path,
+        options: &Options,
+        parsers: &parser::Registry,
+    ) -> anyhow::Result<Report> {
+        let lints_path = match (&self.clippy_json, &self.check_json) {
+            (Some(path), None) | (None, Some(path)) => Some(path),
+            (Some(_), Some(_)) => {
+                anyhow::bail!("Only one of clippy_json and check_json can be passed")
+            }
+            (None, None) => None,
+        };
+        if self.is_empty() {
+            return Ok(Default::default());
+        }
+        let workspace = self.workspace(dir);
+        let resolver = PathResolver {
+            workspace: workspace.clone(),
+            maps: options.path_map.clone(),
+            cwd: dir.into(),
+        };
+        // Of the parsers running in other threads
+        let parent = tracing::Span::current();
+        std::thread::scope(|s| {
+            let lints = lints_path.map(|path| {
+                let (parent, resolver) = (&parent, &resolver);
+                s.spawn(move || {
+                    let span =
+                        tracing::info_span!(parent: parent, "parse-clippy", path = %path.display(), lints = Empty)
+                            .entered();
+                    let res = LintResult::from_clippy(path, resolver, options);
+                    if let Ok((lints, _)) = &res {
+                        span.record("lints", lints.len());
+                    }
+                    res
+                })
+            });
+            let doctests = self.doctest_output.as_ref().map(|path| {
+                let (parent, resolver) = (&parent, &resolver);
+                s.spawn(move || {
+                    let span =
+                        tracing::info_span!(parent: parent, "parse-doctest", path = %path.display(), tests = Empty)
+                            .entered();
+                    let res = UnitResult::from_doctest(path, resolver);
+                    if let Ok(tests) = &res {
+                        span.record("tests", tests.len());
+                    }
+                    res
+                })
+            });
+            let metadata = (!self.nextest_stderr.is_empty() || !self.lcov.is_empty())
+                .then(|| s.spawn(|| paths::metadata(dir, self.workspace.as_deref())));
+            let partitions: Vec<_> = self
+                .nextest_stderr
+                .iter()
+                .map(|path| {
+                    let parent = &parent;
+                    let handle = s.spawn(move || {
+                        let span = tracing::info_span!(parent: parent, "parse-nextest", path = %path.display(), tests = Empty)
+                            .entered();
+                        let res = UnitResult::from_nextest(path);
+                        if let Ok((tests, _)) = &res {
+                            span.record("tests", tests.len());
+                        }
+                        res
+                    });
+                    (path, handle)
+                })
+                .collect();
+            let rustfmt = self.rustfmt_check.as_ref().map(|path| {
+                spawn(s, &parent, "rustfmt", path, || {
+                    rustfmt::parse(path, &resolver)
+                })
+            });
+            let semver = self.semver_checks_json.as_ref().map(|path| {
+                spawn(s, &parent, "semver-checks", path, || {
+                    semver::parse(path, &resolver)
+                })
+            });
+            let bloat = self
+                .bloat_json
+                .as_ref()
+                .map(|path| spawn(s, &parent, "bloat", path, || bloat::parse(path)));
+            let geiger = self.geiger_json.as_ref().map(|path| {
+                spawn(s, &parent, "geiger", path, || {
+                    geiger::parse(path, self.geiger_baseline.as_deref(), &resolver)
+                })
+            });
+            type Linter = fn(&Path, &PathResolver) -> anyhow::Result<Vec<LintResult>>;
+            let linters: Vec<_> = [
+                (
+                    &self.shellcheck_json,
+                    linters::shellcheck as Linter,
+                    "shellcheck lints",
+                ),
+                (&self.eslint_json, linters::eslint, "ESLint lints"),
+                (
+                    &self.golangci_json,
+                    linters::golangci,
+                    "golangci-lint lints",
+                ),
+                (&self.typos_json, linters::typos, "typos"),
+                (&self.generic_lints, linters::generic, "generic lints"),
+            ]
+            .into_iter()
+            .filter_map(|(path, parse, what)| {
+                let path = path.as_ref()?;
+                let resolver = &resolver;
+                Some((
+                    what,
+                    spawn(s, &parent, "linter", path, move || parse(path, resolver)),
+                ))
+            })
+            .collect();
+            let plugins: Vec<_> = self
+                .parser_input
+                .iter()
+                .map(|input| {
+                    let handle = spawn(s, &parent, "plugin", &input.path, || {
+                        parser::parse(parsers, input, &resolver)
+                    });
+                    (input, handle)
+                })
+                .collect();
+
+            let mut parsed = Report::default();
+            if let Some(handle) = lints {
+                match handle.join().unwrap() {
+                    Ok((res, ice)) => {
+                        parsed.lints.extend(res.into_iter().map(|mut lint| {
+                            if let Some(name) = &self.lint_name {
+                                lint.name = name.clone();
+                            }
+                            lint
+                        }));
+                        if let Some(details) = ice {
+                            error!("Internal compiler error in {:?}", lints_path.unwrap());
+                            parsed.metadata.ice = true;
+                            parsed.units.push(UnitResult {
+                                name: "internal compiler error".into(),
+                                result: "broken".into(),
+                                engine: Some("rustc".into()),
+                                details: Some(remarkup::code_block(&details)),
+                                format: Some("remarkup".into()),
+                                ..Default::default()
+                            });
+                        }
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse clippy/check lints: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            let mut summaries = vec![];
+            let partitions = partitions
+                .into_iter()
+                .filter_map(|(path, handle)| match handle.join().unwrap() {
+                    Ok((res, summary)) => {
+                        summaries.extend(summary.map(|summary| (path, summary)));
+                        Some(res)
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse nextest results {:?}: {:?}", path, e);
+                        parsed.metadata.parse_failures += 1;
+                        None
+                    }
+                })
+                .collect();
+            let mut units = merge::merge_partitions(partitions);
+            if let Some(path) = &self.nextest_list {
+                match nextest::not_run(path, &units) {
+                    Ok(res) => {
+                        if !res.is_empty() {
+                            warn!("{} listed tests have no results", res.len());
+                        }
+                        units.extend(res);
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse the nextest test list: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            let metadata = match metadata.map(|h| h.join().unwrap()) {
+                Some(Ok(metadata)) => Some(metadata),
+                Some(Err(e)) => {
+                    warn!(
+                        "Failed to get the test targets paths and the packages: {:#}",
+                        e
+                    );
+                    None
+                }
+                None => None,
+            };
+            if let Some(metadata) = &metadata {
+                for unit in &mut units {
+                    if let Some(namespace) = &unit.namespace {
+                        unit.path = paths::target_path(metadata, &workspace, namespace)
+                            .map(|p| p.to_string_lossy().to_string());
+                    }
+                }
+            }
+            if let Some(max) = options.slow_test_lint {
+                parsed
+                    .lints
+                    .extend(units.iter().filter_map(|u| u.slow_lint(max)));
+            }
+            for unit in &mut units {
+                naming::rename(unit, &options.namespace_format, options.test_name);
+                if let Some(engine) = &self.engine {
+                    unit.engine = Some(engine.clone());
+                }
+            }
+            parsed.units.extend(units);
+            let partitioned = summaries.len() > 1;
+            for (path, mut summary) in summaries {
+                if partitioned {
+                    summary.name = format!("{} ({})", summary.name, path.display());
+                }
+                if let Some(engine) = &self.engine {
+                    summary.engine = Some(engine.clone());
+                }
+                parsed.units.push(summary);
+            }
+            match doctests.map(|h| h.join().unwrap()) {
+                Some(Ok(res)) => parsed.units.extend(res),
+                Some(Err(e)) => {
+                    warn!("Failed to parse doctest results: {:?}", e);
+                    parsed.metadata.parse_failures += 1;
+                }
+                None => {}
+            }
+            if !self.lcov.is_empty() {
+                // The workspace where the coverage was measured
+                let build_root = match &metadata {
+                    Some(metadata) => metadata.workspace_root.clone().into_std_path_buf(),
+                    None => dir.into(),
+                };
+                let handles: Vec<_> = self
+                    .lcov
+                    .iter()
+                    .map(|path| {
+                        let (resolver, build_root) = (&resolver, build_root.clone());
+                        let handle = spawn(s, &parent, "lcov", path, move || {
+                            coverage::parse(path, resolver, &build_root)
+                        });
+                        (path, handle)
+                    })
+                    .collect();
+                for (path, handle) in handles {
+                    match handle.join().unwrap() {
+                        Ok(res) => {
+                            for (file, lines) in res {
+                                coverage::add(&mut parsed.coverage, file, &lines);
+                            }
+                        }
+                        Err(e) => {
+                            warn!("Failed to parse the LCOV coverage {:?}: {:?}", path, e);
+                            parsed.metadata.parse_failures += 1;
+                        }
+                    }
+                }
+                match metadata.as_ref().map(paths::package_dirs) {
+                    Some(Ok((packages, _))) => {
+                        for file in parsed.coverage.keys() {
+                            if let Some(package) = paths::package_of(&packages, file) {
+                                parsed
+                                    .metadata
+                                    .packages
+                                    .insert(file.clone(), package.into());
+                            }
+                        }
+                    }
+                    Some(Err(e)) => warn!("Failed to find the packages of the workspace: {:#}", e),
+                    None => {}
+                }
+            }
+            if let Some(handle) = rustfmt {
+                match handle.join().unwrap() {
+                    Ok((res, patch)) => {
+                        parsed.lints.extend(res);
+                        parsed.metadata.rustfmt_patch = (!patch.is_empty()).then_some(patch);
+                    }
+                    Err(e) => {
+                        warn!("Failed to parse rustfmt diffs: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            if let Some(handle) = semver {
+                match handle.join().unwrap() {
+                    Ok(res) => parsed.lints.extend(res),
+                    Err(e) => {
+                        warn!("Failed to parse cargo-semver-checks results: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            if let Some(handle) = bloat {
+                match handle.join().unwrap() {
+                    Ok(res) => parsed.units.extend(res),
+                    Err(e) => {
+                        warn!("Failed to parse cargo-bloat results: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            if let Some(handle) = geiger {
+                match handle.join().unwrap() {
+                    Ok(res) => parsed.lints.extend(res),
+                    Err(e) => {
+                        warn!("Failed to parse cargo-geiger results: {:?}", e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            for (what, handle) in linters {
+                match handle.join().unwrap() {
+                    Ok(res) => parsed.lints.extend(res),
+                    Err(e) => {
+                        warn!("Failed to parse {}: {:?}", what, e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            for (input, handle) in plugins {
+                match handle.join().unwrap() {
+                    Ok(res) => parsed.extend(res),
+                    Err(e) => {
+                        warn!("Failed to parse {:?}: {:?}", input.path, e);
+                        parsed.metadata.parse_failures += 1;
+                    }
+                }
+            }
+            Ok(parsed)
+        })
+    }
+}
+
+/// Run the parser of an input in its own thread, within a span of the `parent` one
+fn spawn<'scope, T: Send + 'scope>(
+    scope: &'scope std::thread::Scope<'scope, '_>,
+    parent: &'scope tracing::Span,
+    input: &'static str,
+    path: &'scope Path,
+    parse: impl FnOnce() -> anyhow::Result<T> + Send + 'scope,
+) -> std::thread::ScopedJoinHandle<'scope, anyhow::Result<T>> {
+    scope.spawn(move || {
+        let _span =
+            tracing::info_span!(parent: parent, "parse", input, path = %path.display()).entered();
+        parse()
+    })
 }
 
-#[derive(Debug, Copy, Clone, Serialize, ValueEnum)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq, PartialOrd, Ord, Deserialize, ValueEnum)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// JSON parameters for harbormaster.sendmessage
+    Harbormaster,
+    /// TeamCity service messages
+    Teamcity,
+    /// SARIF log of the lints
+    Sarif,
+    /// GitLab Code Quality report of the lints
+    Gitlab,
+    /// JSON summary of the counts of tests and lints, and of the coverage
+    Summary,
+    /// Standalone HTML page of the tests, lints and coverage
+    Html,
+}
+
+/// Results failing the run with `--fail-on`
+#[derive(Debug, Copy, Clone, PartialEq, Eq, Hash, ValueEnum)]
+enum FailOn {
+    Tests,
+    Errors,
+    Warnings,
+}
+
+/// Class of the errors that are not Conduit or network errors, attached as context to
+/// distinguish their exit codes
+#[derive(Debug)]
+enum Failure {
+    Parse,
+    Validation,
+    /// `--fail-on` condition met by the results
+    Policy(String),
+}
+impl std::fmt::Display for Failure {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Failure::Parse => write!(f, "Failed to parse the inputs"),
+            Failure::Validation => write!(f, "Invalid message"),
+            Failure::Policy(reason) => {
+                write!(f, "Failing the run because of {} (--fail-on)", reason)
+            }
+        }
+    }
+}
+impl std::error::Error for Failure {}
+
+/// Process exit code for an error: 1 for --fail-on, 3 for authentication errors, 4 for requests
+/// rejected by the server, 5 for unreachable or overloaded servers, 6 when the deadline expired,
+/// 7 for inputs that could not be parsed, 8 for invalid messages, and 2 otherwise.
+fn exit_code(error: &anyhow::Error) -> i32 {
+    for cause in error.chain() {
+        if cause.is::<submit::DeadlineExceeded>() {
+            return 6;
+        }
+        if let Some(e) = cause.downcast_ref::<conduit::Error>() {
+            return match e.code.as_str() {
+                "ERR-INVALID-AUTH" | "ERR-INVALID-SESSION" => 3,
+                "ERR-RATE-LIMIT" => 5,
+                _ => 4,
+            };
+        }
+        if let Some(e) = cause.downcast_ref::<reqwest::Error>() {
+            let overloaded = |s: reqwest::StatusCode| {
+                s.is_server_error() || s == reqwest::StatusCode::TOO_MANY_REQUESTS
+            };
+            if e.is_timeout() || e.is_connect() || e.status().is_some_and(overloaded) {
+                return 5;
+            }
+            if e.status() == Some(reqwest::StatusCode::UNAUTHORIZED) {
+                return 3;
+            }
+        }
+    }
+    // E.g. a failed download of an input is a network error rather than a parse failure
+    match error.downcast_ref::<Failure>() {
+        Some(Failure::Policy(_)) => 1,
+        Some(Failure::Parse) => 7,
+        Some(Failure::Validation) => 8,
+        None => 2,
+    }
+}
+
+#[derive(Debug, Copy, Clone, Serialize, Deserialize, ValueEnum)]
 #[serde(rename_all = "lowercase")]
 enum Status {
     Abort,
@@ -41,9 +887,12 @@ enum Status {
     Restart,
     Resume,
     Work,
+    /// Fail if any result is build-breaking (error lints, failed tests), pass otherwise
+    #[serde(skip_serializing)]
+    Auto,
 }
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, Serialize)]
 struct Params {
     #[serde(rename = "buildTargetPHID")]
     build: String,
@@ -57,12 +906,57 @@ struct Params {
     #[serde(rename = "__conduit__")]
     auth: Auth, // output: OutputFormat,
 }
-#[derive(Debug, Serialize)]
+impl Params {
+    /// Local sanity checks before sending the message
+    fn validate(&self) -> anyhow::Result<()> {
+        anyhow::ensure!(
+            self.build.starts_with("PHID-HMBT-"),
+            "{} is not a build target PHID (PHID-HMBT-...)",
+            self.build
+        );
+        anyhow::ensure!(!self.auth.token.is_empty(), "The API token is empty");
+        Ok(())
+    }
+}
+#[derive(Debug, Clone, Serialize)]
 struct Auth {
     token: String,
 }
 
-#[derive(Debug, Serialize)]
+/// Name of the result carrying the nextest summary line
+const NEXTEST_SUMMARY: &str = "nextest summary";
+
+/// Parse a nextest duration, e.g. `0.004s`, `1m 03s` or `1h 02m 03s`, in seconds
+fn parse_duration(duration: &str) -> Option<f32> {
+    let mut total = 0.0;
+    for part in duration.split_whitespace() {
+        let (value, unit) = part.split_at(part.len().checked_sub(1)?);
+        let value: f32 = value.parse().ok()?;
+        total += value
+            * match unit {
+                "h" => 3600.0,
+                "m" => 60.0,
+                "s" => 1.0,
+                _ => return None,
+            };
+    }
+    (!duration.trim().is_empty()).then_some(total)
+}
+
+/// Harbormaster result of a nextest status (e.g. `PASS`, `LEAK`, `SIGSEGV`)
+fn nextest_result(status: &str) -> &'static str {
+    match status {
+        "PASS" => "pass",
+        "SKIP" => "skip",
+        // Passed, but leaked handles or needed retries
+        "LEAK" | "FLAKY" => "unsound",
+        // Not run, e.g. after a failure without --no-fail-fast
+        "CANCEL" => "skip",
+        _ => "fail",
+    }
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
 struct UnitResult {
     name: String,
     result: String,
@@ -82,35 +976,214 @@ struct UnitResult {
     format: Option<String>,
 }
 impl UnitResult {
-    fn from_nextest(path: &Path) -> anyhow::Result<impl Iterator<Item = Self>> {
+    /// Whether the test failed the build with `--status auto`. The nextest summary is not a test,
+    /// and is not counted.
+    fn failed(&self) -> bool {
+        !self.is_summary() && !matches!(self.result.as_str(), "pass" | "skip" | "unsound")
+    }
+    /// Whether this is the result carrying the nextest summary line
+    fn is_summary(&self) -> bool {
+        self.name.starts_with(NEXTEST_SUMMARY)
+    }
+    /// Parse the status lines of the nextest output (e.g. `PASS [   0.004s] my-crate::tests it_works`),
+    /// tolerating colors and other output interleaved with them.
+    /// The final `Summary` line is returned as a separate result.
+    fn from_nextest(path: &Path) -> anyhow::Result<(Vec<Self>, Option<Self>)> {
+        let (results, summary, unparsed) = Self::parse_nextest(&input::read_text(path)?)?;
+        if unparsed > 0 {
+            warn!(
+                "{} nextest status lines could not be parsed in {:?}",
+                unparsed, path
+            );
+        }
+        Ok((results, summary))
+    }
+    /// Results and summary of a nextest output, with the number of status lines that could not
+    /// be parsed
+    fn parse_nextest(data: &str) -> anyhow::Result<(Vec<Self>, Option<Self>, usize)> {
         let mut results = HashMap::<(String, String), UnitResult>::new();
-        let data = std::fs::read_to_string(path)?;
-        let regex = regex::Regex::new(r"([A-Z]+) \[\s*((?:\d|\.)+)s\] (.*?) (.*?)$")?;
+        // Status, optionally preceded by the attempt and followed by the attempts count
+        let regex = regex::Regex::new(
+            r"(?:^|\s)(?:TRY \d+ )?([A-Z][A-Z-]*)(?: \d+/\d+)? \[\s*(>\s*)?([^\]]*?)\s*\] (\S+) (.+?)\s*$",
+        )?;
+        // Anything looking like a status line, to report the ones that could not be parsed
+        let candidate = regex::Regex::new(r"^\s*(?:TRY \d+ )?[A-Z][A-Z-]*(?: \d+/\d+)? \[")?;
+        let summary_regex = regex::Regex::new(r"^\s*Summary \[\s*([^\]]*?)\s*\] (.+?)\s*$")?;
+        let failed_regex = regex::Regex::new(r"[1-9]\d* (?:failed|timed out)")?;
+        let mut summary = None;
+        // Slow thresholds crossed by tests, printed before their results
+        let mut slow = HashMap::new();
+        let mut unparsed = 0;
         for line in data.lines() {
+            if let Some(captures) = summary_regex.captures(line) {
+                summary = Some(UnitResult {
+                    name: NEXTEST_SUMMARY.into(),
+                    result: if failed_regex.is_match(&captures[2]) {
+                        "fail"
+                    } else {
+                        "pass"
+                    }
+                    .into(),
+                    engine: Some("cargo-nextest".into()),
+                    duration_s: parse_duration(&captures[1]),
+                    details: Some(captures[2].to_string()),
+                    ..Default::default()
+                });
+                continue;
+            }
             let Some(captures) = regex.captures(line) else {
+                if candidate.is_match(line) {
+                    debug!("Unparsed nextest status line: {}", line);
+                    unparsed += 1;
+                }
                 continue;
             };
-            let name = captures.get(4).unwrap().as_str().to_string();
-            let namespace = captures.get(3).unwrap().as_str().to_string();
-            results.insert(
-                (namespace.clone(), name.clone()),
-                UnitResult {
+            let key = (captures[4].to_string(), captures[5].to_string());
+            let status = &captures[1];
+            // Progress lines, not results, without a duration except for the slow thresholds
+            let progress = matches!(status, "START" | "RETRY" | "SLOW");
+            if progress && !(status == "SLOW" && captures.get(2).is_some()) {
+                continue;
+            }
+            let Some(duration) = parse_duration(&captures[3]) else {
+                debug!("Unparsed nextest duration: {}", line);
+                unparsed += 1;
+                continue;
+            };
+            match status {
+                "SLOW" => {
+                    slow.insert(key, duration);
+                }
+                status => {
+                    results.insert(
+                        key.clone(),
+                        UnitResult {
+                            name: key.1,
+                            result: nextest_result(status).into(),
+                            duration_s: Some(duration),
+                            namespace: Some(key.0),
+                            engine: Some("cargo-nextest".into()),
+                            ..Default::default()
+                        },
+                    );
+                }
+            }
+        }
+        for (key, threshold) in slow {
+            if let Some(unit) = results.get_mut(&key) {
+                unit.details = Some(format!("Slow: exceeded {}s", threshold));
+            }
+        }
+        Ok((results.into_values().collect(), summary, unparsed))
+    }
+    /// Captured output, as a Remarkup code block
+    fn set_output(&mut self, lines: &[&str]) {
+        self.details = Some(remarkup::code_block(lines.join("\n").trim()));
+        self.format = Some("remarkup".into());
+    }
+    /// Advice lint if the test took longer than `max` seconds. The test path must be known.
+    fn slow_lint(&self, max: f32) -> Option<LintResult> {
+        let duration = self.duration_s.filter(|d| *d > max)?;
+        Some(LintResult {
+            name: self.engine.clone().unwrap_or_default(),
+            code: "slow-test".into(),
+            severity: "advice".into(),
+            path: self.path.clone()?,
+            line: None,
+            position: None,
+            description: Some(format!(
+                "{} {} took {:.1}s (more than {}s)",
+                self.namespace.as_deref().unwrap_or_default(),
+                self.name,
+                duration,
+                max
+            )),
+        })
+    }
+    /// Parse the output of `cargo test --doc`. The crate names are only retrieved when stderr was
+    /// captured in the same file (`2>&1`).
+    fn from_doctest(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<Self>> {
+        let data = input::read_text(path)?;
+        let crate_regex = regex::Regex::new(r"^\s*Doc-tests (\S+)")?;
+        let test_regex =
+            regex::Regex::new(r"^test (.+?) - (.+?) \(line (\d+)\)(?: - .*)? \.\.\. (\w+)")?;
+        let mut results = vec![];
+        // Test name (as printed by rustdoc) to result index, to attach the failure outputs
+        let mut indices = HashMap::new();
+        let mut namespace = None;
+        // Failure output of the current test
+        let mut failure: Option<(usize, Vec<&str>)> = None;
+        for line in data.lines() {
+            if let Some(captures) = crate_regex.captures(line) {
+                namespace = Some(captures[1].to_string());
+            } else if let Some(captures) = test_regex.captures(line) {
+                let path = resolver.resolve(Path::new(&captures[1]), None);
+                let path = path.to_string_lossy();
+                let name = format!("{}:{} - {}", path, &captures[3], &captures[2]);
+                indices.insert(
+                    line[5..line.find(" ... ").unwrap()].to_string(),
+                    results.len(),
+                );
+                results.push(UnitResult {
                     name,
-                    result: captures.get(1).unwrap().as_str().to_lowercase(),
-                    duration_s: Some(captures.get(2).unwrap().as_str().parse()?),
-                    namespace: Some(namespace),
-                    engine: Some("cargo-nextest".into()),
-                    coverage: None,
-                    path: None,
-                    details: None,
-                    format: None,
-                },
-            );
+                    result: match &captures[4] {
+                        "ok" => "pass",
+                        "ignored" => "skip",
+                        _ => "fail",
+                    }
+                    .into(),
+                    namespace: namespace.clone(),
+                    engine: Some("rustdoc".into()),
+                    path: Some(path.to_string()),
+                    ..Default::default()
+                });
+            } else if let Some(name) = line
+                .strip_prefix("---- ")
+                .and_then(|l| l.strip_suffix(" stdout ----"))
+            {
+                if let Some((i, lines)) = failure.take() {
+                    results[i].set_output(&lines);
+                }
+                failure = indices.get(name).map(|i| (*i, vec![]));
+            } else if line == "failures:" || line.starts_with("test result:") {
+                if let Some((i, lines)) = failure.take() {
+                    results[i].set_output(&lines);
+                }
+            } else if let Some((_, lines)) = &mut failure {
+                lines.push(line);
+            }
         }
-        Ok(results.into_values())
+        Ok(results)
     }
 }
-#[derive(Debug, Eq, PartialEq, Serialize, Hash)]
+/// Documentation of a lint or error code, preferably the link given in the diagnostic notes
+fn doc_url(diag: &cargo_metadata::diagnostic::Diagnostic, code: &str) -> Option<String> {
+    diag.children
+        .iter()
+        .find_map(|child| {
+            let url = child
+                .message
+                .strip_prefix("for further information visit ")?;
+            Some(url.trim().to_string())
+        })
+        .or_else(|| {
+            if let Some(lint) = code.strip_prefix("clippy::") {
+                Some(format!(
+                    "https://rust-lang.github.io/rust-clippy/master/index.html#{}",
+                    lint
+                ))
+            } else if code.starts_with('E') && code[1..].chars().all(|c| c.is_ascii_digit()) {
+                Some(format!(
+                    "https://doc.rust-lang.org/error_codes/{}.html",
+                    code
+                ))
+            } else {
+                None
+            }
+        })
+}
+
+#[derive(Debug, Clone, Eq, PartialEq, Serialize, Deserialize, Hash)]
 struct LintResult {
     name: String,
     code: String,
@@ -124,85 +1197,1173 @@ struct LintResult {
     description: Option<String>,
 }
 impl LintResult {
-    fn from_clippy(path: &Path, workspace: &Path) -> anyhow::Result<HashSet<Self>> {
-        let mut results = HashSet::new();
-        let json = std::fs::read(path)?;
-        for msg in Message::parse_stream(json.as_slice()) {
-            if let Message::CompilerMessage(msg) = msg? {
+    /// Lower is more severe
+    fn severity_rank(&self) -> u8 {
+        match self.severity.to_lowercase().as_str() {
+            "error" => 0,
+            "warning" => 1,
+            "advice" => 2,
+            _ => 3,
+        }
+    }
+    /// Parse the lints and internal compiler errors (ICE) of a `--message-format=json` stream.
+    /// When stderr was captured in the same file, the ICE backtraces are retrieved.
+    fn from_clippy(
+        path: &Path,
+        resolver: &PathResolver,
+        options: &Options,
+    ) -> anyhow::Result<(Vec<Self>, Option<String>)> {
+        // Lints with the target they were reported for
+        let mut results = vec![];
+        let mut ice = vec![];
+        let mut in_ice_text = false;
+        // To determine the workspace root at build time, either from relative paths or from the
+        // directories of the local packages.
+        let mut roots = HashSet::new();
+        let mut manifest_dirs = HashSet::new();
+        for line in input::open(path)?.lines() {
+            let line = line?;
+            if !line.starts_with('{') {
+                // Text output, e.g. from stderr
+                in_ice_text |= line.contains("internal compiler error")
+                    || line.contains("thread 'rustc' panicked");
+                if in_ice_text {
+                    ice.push(input::strip_ansi(&line).into_owned());
+                }
+                continue;
+            }
+            in_ice_text = false;
+            // Cheap pre-filter, as artifact and build script messages make up most of the stream
+            if !line.contains(r#""reason":"compiler-message""#) {
+                continue;
+            }
+            if let Message::CompilerMessage(msg) = serde_json::from_str(&line)? {
+                // Lints of dependencies, e.g. with `[patch]` or non-capped lints
+                let repr = &msg.package_id.repr;
+                // `registry+...#serde@1.0.0`, or `serde 1.0.0 (registry+...)` before cargo 1.77
+                let source = repr.split_once('(').map_or(repr.as_str(), |(_, s)| s);
+                if source.starts_with("registry+") || source.starts_with("git+") {
+                    debug!("Skipping diagnostic of the dependency {}", repr);
+                    continue;
+                }
+                let package_dir = paths::package_dir(&msg.package_id);
                 let diag = msg.message;
-                let Some(code) = &diag.code else {
+                if diag.level == DiagnosticLevel::Ice {
+                    ice.push(diag.rendered.unwrap_or(diag.message));
+                    continue;
+                }
+                let code = match (&diag.code, diag.level) {
+                    (Some(code), _) => code.code.clone(),
+                    // Many errors (e.g. follow-ups) have no code, but must not be dropped
+                    (None, DiagnosticLevel::Error) => "rustc-error".into(),
+                    (None, _) => continue,
+                };
+                // Diagnostics without location, e.g. "aborting due to previous error"
+                let Some(span) = diag
+                    .spans
+                    .iter()
+                    .find(|s| s.is_primary)
+                    .or(diag.spans.first())
+                else {
                     continue;
                 };
-                let code = code.code.clone();
-                let span = &diag.spans[0];
+                if let Some(dir) = package_dir {
+                    let file_name = paths::normalize(&span.file_name);
+                    if !paths::is_absolute(&file_name) {
+                        roots.extend(paths::infer_root(&dir, &file_name));
+                    }
+                    manifest_dirs.insert(dir);
+                }
 
+                let description = match doc_url(&diag, &code) {
+                    Some(url) => format!("{}\n\nSee {}", diag.message, url),
+                    None => diag.message,
+                };
+                let target = match msg.target.kind.first() {
+                    Some(kind) if kind == "lib" => kind.clone(),
+                    Some(kind) => format!("{}/{}", kind, msg.target.name),
+                    None => msg.target.name.clone(),
+                };
+                let tool = if code.contains("clippy") {
+                    "cargo-clippy"
+                } else {
+                    "cargo-check"
+                };
                 let res = LintResult {
-                    name: if code.contains("clippy") {
-                        "cargo-clippy".into()
-                    } else {
-                        "cargo-check".into()
-                    },
+                    name: options
+                        .lint_name_format
+                        .replace("{tool}", tool)
+                        .replace("{package}", &paths::package_name(&msg.package_id))
+                        .replace("{target}", &target),
                     code,
-                    severity: format!("{:?}", diag.level),
-                    path: workspace
-                        .join(&span.file_name)
-                        .to_string_lossy()
-                        .to_string(),
+                    severity: match diag.level {
+                        DiagnosticLevel::Error | DiagnosticLevel::Ice => "error",
+                        DiagnosticLevel::Warning => "warning",
+                        _ => "advice",
+                    }
+                    .into(),
+                    path: span.file_name.clone(),
                     line: Some(span.line_start),
                     position: None,
-                    description: Some(diag.message),
+                    description: Some(description),
                 };
-                results.insert(res);
+                results.push((res, format!("{} {}", msg.package_id.repr, target)));
             }
         }
-        Ok(results)
+        let build_root = paths::common_ancestor(roots.iter().map(PathBuf::as_path))
+            .or_else(|| paths::common_ancestor(manifest_dirs.iter().map(PathBuf::as_path)));
+        debug!("Workspace root at build time: {:?}", build_root);
+        let (external, results): (Vec<_>, Vec<_>) = results.into_iter().partition(|(res, _)| {
+            resolver.is_external(Path::new(&res.path), build_root.as_deref())
+        });
+        if !external.is_empty() {
+            warn!(
+                "Dropping {} diagnostics outside of the workspace, e.g. in {:?}",
+                external.len(),
+                external[0].0.path
+            );
+        }
+        let results = merge::dedupe_lints(results)
+            .into_iter()
+            .map(|(mut res, targets)| {
+                if options.count_lint_targets && targets.len() > 1 {
+                    let description = res.description.get_or_insert_with(String::new);
+                    *description += &format!("\n\nReported for {} targets", targets.len());
+                }
+                res.path = resolver
+                    .resolve(Path::new(&res.path), build_root.as_deref())
+                    .to_string_lossy()
+                    .to_string();
+                res
+            })
+            .collect();
+        Ok((results, (!ice.is_empty()).then(|| ice.join("\n"))))
     }
 }
+/// Parse the command line, with the `conduit_uri` and `token` of the configuration file in place
+/// of the missing flags and environment variables, and load the configuration. Its errors are
+/// returned once the logs are set up.
+fn parse_flags(mut argv: Vec<std::ffi::OsString>) -> (Flags, anyhow::Result<config::Config>) {
+    // Errors (e.g. --help) are reported by the second pass
+    let Ok(matches) = Flags::command()
+        .ignore_errors(true)
+        .try_get_matches_from(&argv)
+    else {
+        return (Flags::parse_from(argv), Ok(Default::default()));
+    };
+    let config = match matches.get_one::<PathBuf>("config") {
+        Some(path) => match config::Config::load(path) {
+            Ok(config) => config,
+            Err(e) => return (Flags::parse_from(argv), Err(e)),
+        },
+        None => config::Config::default(),
+    };
+    // Passed as flags rather than defaults, which do not satisfy the flags requiring them
+    for (name, value) in [
+        ("conduit-uri", &config.conduit_uri),
+        ("token", &config.token),
+    ] {
+        if let Some(value) = value.as_ref().filter(|_| {
+            let id = name.replace('-', "_");
+            matches.value_source(&id).is_none()
+        }) {
+            argv.insert(1, format!("--{}={}", name, value).into());
+        }
+    }
+    (Flags::parse_from(argv), Ok(config))
+}
+
 fn main_impl() -> anyhow::Result<()> {
-    env_logger::Builder::from_env(env_logger::Env::default().default_filter_or("info")).init();
-    let args = Flags::parse();
-    let workspace = args.workspace.unwrap_or_default();
-    let mut lints: Vec<LintResult> = vec![];
-    match (args.clippy_json, args.check_json) {
-        (Some(path), None) | (None, Some(path)) => {
-            match LintResult::from_clippy(&path, &workspace) {
-                Ok(res) => lints.extend(res),
-                Err(e) => {
-                    warn!("Failed to parse clippy/check lints: {:?}", e);
-                }
+    let (args, config) = parse_flags(std::env::args_os().collect());
+    if args.generate_manpage {
+        clap_mangen::Man::new(Flags::command()).render(&mut std::io::stdout().lock())?;
+        return Ok(());
+    }
+    if let Some(Command::Completions { shell }) = args.command {
+        let name = env!("CARGO_PKG_NAME");
+        clap_complete::generate(shell, &mut Flags::command(), name, &mut std::io::stdout());
+        return Ok(());
+    }
+    logging::init(args.log_format);
+    let config = config?;
+    redactor(&args, &config)?.install();
+    if let Some(Command::Serve(serve_args)) = &args.command {
+        return serve::serve(&args, serve_args, &config);
+    }
+    if let Some(Command::Lifecycle(lifecycle_args)) = &args.command {
+        return lifecycle::run(&args, &config, lifecycle_args);
+    }
+    if let Some(Command::History(history_args)) = &args.command {
+        let path = args
+            .history_db
+            .as_deref()
+            .context("--history-db is required")?;
+        return history::History::open(path)?.query(history_args, &mut std::io::stdout().lock());
+    }
+    if let Some(path) = &args.resend {
+        return resend(&args, &config, path);
+    }
+    let cwd = std::env::current_dir()?;
+    let mut report = parse_all(&args, &config, &cwd).context(Failure::Parse)?;
+    if let Some(path) = &args.history_db {
+        let mut history = history::History::open(path)?;
+        if args.history_deltas {
+            history.annotate(&mut report, args.history_window)?;
+        }
+        if !args.dry_run {
+            history.record(&report, args.build_phid.as_deref())?;
+        }
+    }
+    report
+        .lints
+        .extend(budget::check(&cwd, &args.max_warnings, &report.lints)?);
+    // Before --max-lints, which omits lints
+    let policy = check_policy(&args.fail_on, &report);
+    let mut metrics = metrics::Metrics::new(&report);
+    let omitted = apply_limits(&args, &mut report.lints, &mut report.units);
+    let output = match (args.output.clone(), &args.conduit_uri) {
+        (Some(path), _) => Some(path),
+        (None, None) => Some("-".into()),
+        (None, Some(_)) => None,
+    };
+    // Written first, so that they are available even if the submission fails
+    let failed_outputs = write_outputs(&args, &config, &report);
+    match args.format {
+        Format::Harbormaster => {
+            let sink = harbormaster_sink(&args, &report)?;
+            let mut params = sink.params(&report);
+            schema::validate(&params).context(Failure::Validation)?;
+            metrics.payload_bytes = Some(serde_json::to_vec(&params)?.len());
+            if let Some(path) = output {
+                let mut out = open_output(&path)?;
+                sink.write(&report, &mut out)?;
+                out.flush()?;
+            }
+            if let Some(uri) = &args.conduit_uri {
+                params.validate().context(Failure::Validation)?;
+                let client = client(&args, &config, uri, &params.auth.token)?;
+                let runtime = tokio::runtime::Builder::new_current_thread()
+                    .enable_all()
+                    .build()?;
+                runtime.block_on(async {
+                    if args.watch {
+                        return watch::watch(&args, &config, &client, params).await;
+                    }
+                    if args.dry_run {
+                        client.check().await?;
+                        info!("Dry run, not sending the message");
+                        return Ok(());
+                    }
+                    let progress = progress(&args);
+                    let pending = params.clone();
+                    let submission = async {
+                        if let Some(patch) = &report.metadata.rustfmt_patch {
+                            submit::attach_rustfmt_patch(&client, &mut params, patch).await?;
+                        }
+                        let start = Instant::now();
+                        match &args.cache_dir {
+                            Some(dir) => {
+                                let mut sent = cache::Sent::load(dir, &params.build)?;
+                                let all = params.unit.iter().flatten().count()
+                                    + params.lint.iter().flatten().count();
+                                let delta = Params {
+                                    unit: params.unit.as_deref().map(|u| sent.unsent(u)),
+                                    lint: params.lint.as_deref().map(|l| sent.unsent(l)),
+                                    ..params.clone()
+                                };
+                                let new = delta.unit.iter().flatten().count()
+                                    + delta.lint.iter().flatten().count();
+                                info!("{} results out of {} were already sent", all - new, all);
+                                submit::send_tracked(
+                                    &client,
+                                    &delta,
+                                    args.chunk_size,
+                                    args.concurrency,
+                                    &progress,
+                                )
+                                .await?;
+                                sent.mark(delta.unit.as_deref().unwrap_or_default());
+                                sent.mark(delta.lint.as_deref().unwrap_or_default());
+                                sent.save(dir, &params.build)?;
+                            }
+                            None => {
+                                submit::send_tracked(
+                                    &client,
+                                    &params,
+                                    args.chunk_size,
+                                    args.concurrency,
+                                    &progress,
+                                )
+                                .await?;
+                            }
+                        }
+                        metrics.submission = Some(start.elapsed());
+                        info!("Sent message to {}", params.build);
+                        if !omitted.is_empty() {
+                            submit::attach_lints(&client, &params, &omitted, "lints").await?;
+                        }
+                        if args.attach_html_report {
+                            let mut page = vec![];
+                            html::Html.write(&report, &mut page)?;
+                            client
+                                .upload_artifact(&params.build, "html-report", "report.html", &page)
+                                .await?;
+                        }
+                        anyhow::Ok(())
+                    };
+                    submit::with_deadline(
+                        args.deadline.map(Duration::from_secs_f64),
+                        &progress,
+                        &pending,
+                        &args.spill_file,
+                        submission,
+                    )
+                    .await
+                })?;
             }
         }
-        _ => {}
+        format => {
+            anyhow::ensure!(
+                args.conduit_uri.is_none(),
+                "Only the harbormaster format can be sent to Conduit"
+            );
+            let mut out = open_output(&output.unwrap_or_else(|| "-".into()))?;
+            sink(&args, &report, format)?.write(&report, &mut out)?;
+            out.flush()?;
+        }
+    }
+    export_metrics(&args, &metrics);
+    anyhow::ensure!(
+        failed_outputs == 0,
+        "Failed to write {} of the outputs of the configuration",
+        failed_outputs
+    );
+    if args.strict && report.metadata.parse_failures > 0 {
+        return Err(anyhow::anyhow!(
+            "{} inputs could not be parsed (--strict)",
+            report.metadata.parse_failures
+        )
+        .context(Failure::Parse));
+    }
+    policy
+}
+
+/// Error if the results meet one of the `--fail-on` conditions
+fn check_policy(fail_on: &[FailOn], report: &Report) -> anyhow::Result<()> {
+    let lints = |max_rank| {
+        report
+            .lints
+            .iter()
+            .filter(|l| l.severity_rank() <= max_rank)
+            .count()
+    };
+    let reasons: Vec<_> = fail_on
+        .iter()
+        .unique()
+        .filter_map(|condition| {
+            let (count, what) = match condition {
+                FailOn::Tests
+                    if summary_failed(&report.units) && failed_tests(&report.units) == 0 =>
+                {
+                    (1, "nextest summary reporting failures")
+                }
+                FailOn::Tests => (failed_tests(&report.units), "failed tests"),
+                FailOn::Errors => (lints(0), "error lints"),
+                FailOn::Warnings => (lints(1), "warning or error lints"),
+            };
+            (count > 0).then(|| format!("{} {}", count, what))
+        })
+        .collect();
+    match reasons.is_empty() {
+        true => Ok(()),
+        false => Err(Failure::Policy(reasons.join(" and ")).into()),
     }
-    let mut units: Vec<UnitResult> = vec![];
-    if let Some(path) = args.nextest_stderr {
-        match UnitResult::from_nextest(&path) {
-            Ok(res) => units.extend(res),
-            Err(e) => {
-                warn!("Failed to parse nextest results: {:?}", e);
+}
+
+/// Sink writing the parameters of harbormaster.sendmessage for the build target of the flags
+fn harbormaster_sink(args: &Flags, report: &Report) -> anyhow::Result<report::Harbormaster> {
+    Ok(report::Harbormaster {
+        build: args
+            .build_phid
+            .clone()
+            .context("A build PHID is required")?,
+        status: resolve_status(
+            args.status.context("--status is required")?,
+            &report.lints,
+            &report.units,
+            report.metadata.ice,
+        ),
+        token: match args.conduit_uri.is_some() || args.print_secrets {
+            true => args.token.clone().context("--token is required")?,
+            false => args.token.clone().unwrap_or_default(),
+        },
+        compact: args.compact,
+        print_secrets: args.print_secrets,
+    })
+}
+
+fn sink(args: &Flags, report: &Report, format: Format) -> anyhow::Result<Box<dyn Sink>> {
+    Ok(match format {
+        Format::Harbormaster => Box::new(harbormaster_sink(args, report)?),
+        Format::Teamcity => Box::new(teamcity::Teamcity),
+        Format::Sarif => Box::new(sarif::Sarif),
+        Format::Gitlab => Box::new(gitlab::Gitlab),
+        Format::Summary => Box::new(summary::Summary),
+        Format::Html => Box::new(html::Html),
+    })
+}
+
+/// Write the report in each format of the `[outputs]` section of the configuration, and the
+/// --html-report. A failing output is only logged, so that it does not prevent the other ones;
+/// the number of failures is returned.
+fn write_outputs(args: &Flags, config: &config::Config, report: &Report) -> usize {
+    config
+        .outputs
+        .iter()
+        .chain(args.html_report.as_ref().map(|path| (&Format::Html, path)))
+        .filter(|(format, path)| {
+            let result = (|| {
+                let mut out = open_output(path)?;
+                sink(args, report, **format)?.write(report, &mut out)?;
+                out.flush()?;
+                anyhow::Ok(())
+            })();
+            match result {
+                Ok(()) => info!("Wrote the {:?} output to {:?}", format, path),
+                Err(ref e) => error!(
+                    "Failed to write the {:?} output to {:?}: {:#}",
+                    format, path, e
+                ),
             }
+            result.is_err()
+        })
+        .count()
+}
+
+/// Write or push the metrics of the run, only warning on failure so as not to fail the build
+fn export_metrics(args: &Flags, metrics: &metrics::Metrics) {
+    if let Some(path) = &args.metrics_file {
+        if let Err(e) = metrics.write(path) {
+            warn!("Failed to write the metrics: {:#}", e);
+        }
+    }
+    if let Some(url) = &args.pushgateway {
+        let push = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .map_err(anyhow::Error::from)
+            .and_then(|runtime| {
+                runtime.block_on(metrics.push(
+                    url,
+                    &args.pushgateway_job,
+                    Duration::from_secs(args.timeout),
+                ))
+            });
+        if let Err(e) = push {
+            warn!("Failed to push the metrics: {:#}", e);
+        }
+    }
+}
+
+/// Parse the inputs of all workspaces concurrently, and merge the results in order. The relative
+/// paths of the inputs are relative to `dir`, where the tools ran.
+fn parse_all(args: &Flags, config: &config::Config, dir: &Path) -> anyhow::Result<Report> {
+    let mut groups = vec![];
+    if config.workspaces.is_empty() || !args.inputs.is_empty() {
+        groups.push(args.inputs.clone());
+    }
+    groups.extend(config.workspaces.iter().cloned());
+    for path in groups.iter_mut().flat_map(|inputs| inputs.paths_mut()) {
+        if path.is_relative() && !fetch::is_url(path) {
+            *path = dir.join(&*path);
         }
     }
-    units.sort_by(|a, b| {
+    let urls: Vec<_> = groups
+        .iter_mut()
+        .flat_map(|inputs| inputs.paths_mut())
+        .filter(|path| fetch::is_url(path))
+        .collect();
+    // Removed once parsed
+    let _downloads = match urls.is_empty() {
+        true => None,
+        false => {
+            let _span = tracing::info_span!("fetch-inputs", inputs = urls.len()).entered();
+            info!("Downloading {} inputs", urls.len());
+            let downloads = fetch::Downloads::new()?;
+            downloads.fetch(&http_client(args)?, urls)?;
+            Some(downloads)
+        }
+    };
+    let parsers = parser::Registry::new(&config.parsers)?;
+    let parsed = std::thread::scope(|s| {
+        let handles: Vec<_> = groups
+            .iter()
+            .map(|inputs| {
+                s.spawn(|| {
+                    let span = tracing::info_span!(
+                        "parse-workspace",
+                        workspace = ?inputs.workspace,
+                        tests = Empty,
+                        lints = Empty
+                    )
+                    .entered();
+                    let res = inputs.parse(dir, &args.options, &parsers);
+                    if let Ok(report) = &res {
+                        span.record("tests", report.units.len());
+                        span.record("lints", report.lints.len());
+                    }
+                    res
+                })
+            })
+            .collect();
+        handles
+            .into_iter()
+            .map(|h| h.join().unwrap())
+            .collect::<anyhow::Result<Vec<_>>>()
+    })?;
+    let mut merged = Report::default();
+    for (inputs, mut parsed) in groups.iter().zip(parsed) {
+        if let (Some(features), merge::MatrixMerge::Suffix) = (&inputs.features, args.matrix_merge)
+        {
+            merge::suffix_namespaces(&mut parsed.units, features);
+        }
+        merged.extend(parsed);
+    }
+    // Colors captured by the CI, e.g. in the failure outputs
+    merged.strip_ansi();
+    // e.g. tokens printed by the tests
+    merged.redact(&redactor(args, config)?);
+    // The same lints are emitted for each feature set
+    merged.lints = merge::dedupe_lints(merged.lints.into_iter().map(|l| (l, String::new())))
+        .into_iter()
+        .map(|(l, _)| l)
+        .collect();
+    if args.changed_only {
+        let changes = diff::Changes::load(dir, args.diff_base.as_deref())?;
+        let all = merged.lints.len();
+        merged
+            .lints
+            .retain(|l| changes.contains(l) || (args.keep_errors && l.severity_rank() == 0));
+        info!(
+            "Keeping {} of {} lints on the changed lines",
+            merged.lints.len(),
+            all
+        );
+        let covered = merged.coverage.len();
+        merged.coverage.retain(|path, _| changes.touches(path));
+        if covered > 0 {
+            info!(
+                "Keeping the coverage of {} of {} files, changed by the diff",
+                merged.coverage.len(),
+                covered
+            );
+        }
+    }
+    if let merge::MatrixMerge::Worst = args.matrix_merge {
+        merged.units = merge::keep_worst(merged.units);
+    }
+    // Deterministic order, for stable payloads and displays
+    merged.lints.sort_by(|a, b| {
+        (
+            a.severity_rank(),
+            &a.path,
+            a.line,
+            a.position,
+            &a.code,
+            &a.description,
+        )
+            .cmp(&(
+                b.severity_rank(),
+                &b.path,
+                b.line,
+                b.position,
+                &b.code,
+                &b.description,
+            ))
+    });
+    merged.units.sort_by(|a, b| {
         b.duration_s
             .unwrap_or_default()
-            .partial_cmp(&a.duration_s.unwrap_or_default())
-            .unwrap()
+            .total_cmp(&a.duration_s.unwrap_or_default())
+            .then_with(|| (&a.namespace, &a.name).cmp(&(&b.namespace, &b.name)))
     });
-    let output = Params {
-        build: args.build_phid,
-        status: args.status,
-        unit: Some(units),
-        lint: Some(lints),
-        auth: Auth { token: args.token },
-    };
-    print!("{}", serde_json::to_string_pretty(&output)?);
+    log_lint_files(&merged.lints);
+    Ok(merged)
+}
+
+/// Send the results spilled past the deadline of a previous run
+fn resend(args: &Flags, config: &config::Config, path: &Path) -> anyhow::Result<()> {
+    let uri = args
+        .conduit_uri
+        .as_deref()
+        .context("--conduit-uri is required")?;
+    let token = args.token.as_deref().context("--token is required")?;
+    let params = submit::Spill::load(path)?.into_params(token);
+    params.validate().context(Failure::Validation)?;
+    let client = client(args, config, uri, token)?;
+    let progress = progress(args);
+    tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?
+        .block_on(submit::with_deadline(
+            args.deadline.map(Duration::from_secs_f64),
+            &progress,
+            &params,
+            &args.spill_file,
+            submit::send_tracked(
+                &client,
+                &params,
+                args.chunk_size,
+                args.concurrency,
+                &progress,
+            ),
+        ))?;
+    info!("Sent the results of {:?} to {}", path, params.build);
+    std::fs::remove_file(path).with_context(|| format!("Failed to remove {:?}", path))?;
     Ok(())
 }
 
+/// Redactor of the secrets passed on the command line, of the values of the credential headers,
+/// of the AWS credentials and of the `--redact` patterns
+fn redactor(args: &Flags, config: &config::Config) -> anyhow::Result<redact::Redactor> {
+    let patterns: Vec<_> = args.redact.iter().chain(&config.redact).cloned().collect();
+    let aws = fetch::secrets();
+    // With the credentials alone of the `Scheme credentials` values (e.g. `Bearer ...`)
+    let headers = args
+        .headers
+        .iter()
+        .filter_map(|h| Some((h.name.as_str(), h.value.to_str().ok()?)))
+        .chain(config.headers.iter().map(|(n, v)| (n.as_str(), v.trim())))
+        .filter(|(name, _)| conduit::is_secret_header(name))
+        .flat_map(|(_, v)| [v, v.rsplit(' ').next().unwrap_or(v)]);
+    redact::Redactor::new(
+        [args.token.as_deref(), args.client_cert_password.as_deref()]
+            .into_iter()
+            .flatten()
+            .chain(headers)
+            .chain(aws.iter().map(String::as_str)),
+        &patterns,
+    )
+}
+
+/// Progress of the submission, recorded in a journal with --resume
+fn progress(args: &Flags) -> submit::Progress {
+    match args.resume {
+        true => submit::Progress::journaled(args.journal_dir.clone()),
+        false => submit::Progress::default(),
+    }
+}
+
+/// TLS client certificate passed on the command line
+fn identity(args: &Flags) -> anyhow::Result<Option<reqwest::Identity>> {
+    args.client_cert
+        .as_deref()
+        .map(|cert| {
+            conduit::identity(
+                cert,
+                args.client_key.as_deref(),
+                args.client_cert_password.as_deref(),
+            )
+        })
+        .transpose()
+}
+
+/// Headers of the Conduit requests, from the configuration file and `--header`
+fn headers(args: &Flags, config: &config::Config) -> anyhow::Result<Vec<conduit::Header>> {
+    let mut headers = config
+        .headers
+        .iter()
+        .map(|(name, value)| format!("{}: {}", name, value).parse())
+        .collect::<anyhow::Result<Vec<conduit::Header>>>()
+        .context("Invalid header in the configuration file")?;
+    headers.retain(|header| args.headers.iter().all(|h| h.name != header.name));
+    headers.extend(args.headers.iter().cloned());
+    Ok(headers)
+}
+
+/// Conduit client configured from the command line and the configuration file
+fn client(
+    args: &Flags,
+    config: &config::Config,
+    uri: &str,
+    token: &str,
+) -> anyhow::Result<conduit::Client> {
+    conduit::Client::new(
+        uri,
+        token,
+        Duration::from_secs(args.timeout),
+        args.flavor,
+        identity(args)?,
+        &headers(args, config)?,
+        args.max_rps,
+    )
+}
+
+/// HTTP client of the downloads, with the same settings as the Conduit client, except for the
+/// headers, which are meant for the Conduit server only
+fn http_client(args: &Flags) -> anyhow::Result<reqwest::Client> {
+    conduit::http_client(Duration::from_secs(args.timeout), identity(args)?, &[])
+}
+
+/// Apply `--max-lints`, `--max-details-bytes` and `--only-failures`, returning the omitted lints
+fn apply_limits(
+    args: &Flags,
+    lints: &mut Vec<LintResult>,
+    units: &mut Vec<UnitResult>,
+) -> Vec<LintResult> {
+    let mut omitted = vec![];
+    if let Some(max) = args.max_lints.filter(|max| lints.len() > *max) {
+        lints.sort_by_key(LintResult::severity_rank);
+        omitted = lints.split_off(max);
+        let counts = omitted
+            .iter()
+            .counts_by(|l| l.severity.to_lowercase())
+            .into_iter()
+            .sorted()
+            .map(|(severity, count)| format!("{}: {}", severity, count))
+            .join(", ");
+        warn!("Omitting {} lints ({})", omitted.len(), counts);
+        units.push(UnitResult {
+            name: "Omitted lints".into(),
+            result: "skip".into(),
+            namespace: Some("cargo-harbormaster".into()),
+            engine: Some("cargo-harbormaster".into()),
+            details: Some(format!(
+                "{} more lints omitted ({}), see the attached lints artifact",
+                omitted.len(),
+                counts
+            )),
+            ..Default::default()
+        });
+    }
+    if let Some(max) = args.max_details_bytes {
+        for unit in units.iter_mut() {
+            truncate::details(unit, max, args.truncate_keep);
+        }
+    }
+    if args.only_failures {
+        // The units are sorted by decreasing duration
+        let mut slowest = 0;
+        let (kept, passing): (Vec<_>, Vec<_>) = std::mem::take(units).into_iter().partition(|u| {
+            if u.result != "pass" || u.is_summary() {
+                return true;
+            }
+            slowest += 1;
+            slowest <= args.keep_slowest
+        });
+        *units = kept;
+        if !passing.is_empty() {
+            let duration: f32 = passing.iter().filter_map(|u| u.duration_s).sum();
+            info!("Omitting {} passing tests", passing.len());
+            units.push(UnitResult {
+                name: "Omitted passing tests".into(),
+                result: "pass".into(),
+                namespace: Some("cargo-harbormaster".into()),
+                engine: Some("cargo-harbormaster".into()),
+                duration_s: Some(duration),
+                details: Some(format!(
+                    "{} more tests passed, in {:.1}s in total",
+                    passing.len(),
+                    duration
+                )),
+                ..Default::default()
+            });
+        }
+    }
+    omitted
+}
+
+/// Log the number of lints per file, for the files with the most lints
+fn log_lint_files(lints: &[LintResult]) {
+    const MAX_FILES: usize = 10;
+    let counts = lints
+        .iter()
+        .counts_by(|l| l.path.as_str())
+        .into_iter()
+        .sorted_by(|a, b| b.1.cmp(&a.1).then(a.0.cmp(b.0)))
+        .collect::<Vec<_>>();
+    if counts.is_empty() {
+        return;
+    }
+    info!("{} lints in {} files", lints.len(), counts.len());
+    for (path, count) in counts.iter().take(MAX_FILES) {
+        info!("  {}: {}", path, count);
+    }
+    if counts.len() > MAX_FILES {
+        info!("  ... and {} more files", counts.len() - MAX_FILES);
+    }
+}
+
+/// Resolve `--status auto`, and fail passing builds with internal compiler errors
+fn resolve_status(status: Status, lints: &[LintResult], units: &[UnitResult], ice: bool) -> Status {
+    let mut status = status;
+    if let Status::Auto = status {
+        status = auto_status(lints, units);
+        info!("Build status: {:?}", status);
+    }
+    if ice && matches!(status, Status::Pass) {
+        warn!("Reporting the build as failed because of an internal compiler error");
+        status = Status::Fail;
+    }
+    status
+}
+
+/// Number of failed tests, excluding the nextest summary
+fn failed_tests(units: &[UnitResult]) -> usize {
+    units.iter().filter(|u| u.failed()).count()
+}
+
+/// Whether a nextest summary reports failures, e.g. of tests whose results are missing from the
+/// output
+fn summary_failed(units: &[UnitResult]) -> bool {
+    units.iter().any(|u| u.is_summary() && u.result == "fail")
+}
+
+/// Status for `--status auto`
+fn auto_status(lints: &[LintResult], units: &[UnitResult]) -> Status {
+    let errors = lints.iter().filter(|l| l.severity_rank() == 0).count();
+    let failures = failed_tests(units);
+    if errors + failures > 0 || summary_failed(units) {
+        info!("{} error lints and {} failed tests", errors, failures);
+        Status::Fail
+    } else {
+        Status::Pass
+    }
+}
+
+fn open_output(path: &Path) -> anyhow::Result<Box<dyn Write>> {
+    Ok(if path == Path::new("-") {
+        Box::new(std::io::stdout().lock())
+    } else {
+        let file =
+            std::fs::File::create(path).with_context(|| format!("Failed to create {:?}", path))?;
+        Box::new(std::io::BufWriter::new(file))
+    })
+}
+
 fn main() {
     if let Err(e) = main_impl() {
-        error!("{}", e);
-        std::process::exit(2);
+        error!("{:#}", e);
+        std::process::exit(exit_code(&e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn unit(name: &str, result: &str) -> UnitResult {
+        UnitResult {
+            name: name.into(),
+            result: result.into(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn nextest_status_lines() {
+        let output = "    Starting 6 tests across 2 binaries
+       START [           ] my-crate::tests it_works
+\x1b[32;1m        PASS\x1b[0m [   0.004s] \x1b[35;1mmy-crate::tests\x1b[0m \x1b[36mit_works\x1b[0m
+        SLOW [> 60.000s] my-crate::tests long
+        PASS [ 1m 03s] my-crate::tests long
+  TRY 1 FAIL [   0.010s] my-crate::tests flaky
+       RETRY 2/3 [         ] my-crate::tests flaky
+  TRY 2 PASS [   0.010s] my-crate::tests flaky
+     TIMEOUT [  30.001s] my-crate::tests hangs
+        LEAK [   0.102s] my-crate::tests leaky
+        PASS [ weird ] my-crate::tests broken
+     Summary [  94.000s] 6 tests run: 5 passed, 1 timed out, 0 skipped
+";
+        let output = input::strip_ansi(output);
+        let (units, summary, unparsed) = UnitResult::parse_nextest(&output).unwrap();
+        // Only the invalid duration, not the START and RETRY progress lines
+        assert_eq!(unparsed, 1);
+        let result = |name: &str| {
+            let unit = units.iter().find(|u| u.name == name).unwrap();
+            (
+                unit.result.as_str(),
+                unit.duration_s,
+                unit.details.as_deref(),
+            )
+        };
+        assert_eq!(units.len(), 5);
+        assert_eq!(result("it_works"), ("pass", Some(0.004), None));
+        assert_eq!(
+            result("long"),
+            ("pass", Some(63.0), Some("Slow: exceeded 60s"))
+        );
+        assert_eq!(result("flaky"), ("pass", Some(0.01), None));
+        assert_eq!(result("hangs"), ("fail", Some(30.001), None));
+        assert_eq!(result("leaky"), ("unsound", Some(0.102), None));
+        assert_eq!(units[0].namespace.as_deref(), Some("my-crate::tests"));
+        let summary = summary.unwrap();
+        assert_eq!(summary.result, "fail");
+        assert_eq!(summary.duration_s, Some(94.0));
+    }
+
+    #[test]
+    fn failed_tests_exclude_the_summary() {
+        let mut units = vec![
+            unit("a", "fail"),
+            unit("b", "broken"),
+            unit("c", "pass"),
+            unit(NEXTEST_SUMMARY, "fail"),
+            unit(&format!("{} (partition.log)", NEXTEST_SUMMARY), "fail"),
+        ];
+        assert_eq!(failed_tests(&units), 2);
+        assert!(matches!(auto_status(&[], &units), Status::Fail));
+        let report = Report {
+            units: units.clone(),
+            ..Default::default()
+        };
+        let policy = check_policy(&[FailOn::Tests], &report).unwrap_err();
+        assert!(format!("{:#}", policy).contains("2 failed tests"));
+        // Failures only reported by the summary still fail the build
+        units.drain(..2);
+        assert_eq!(failed_tests(&units), 0);
+        assert!(matches!(auto_status(&[], &units), Status::Fail));
+        units.truncate(1);
+        assert!(matches!(auto_status(&[], &units), Status::Pass));
+    }
+
+    #[test]
+    fn configured_conduit() {
+        let config = input::tests::fixture(
+            "conduit.toml",
+            "conduit_uri = \"https://phab.example.com\"\ntoken = \"api-config\"\n",
+        );
+        let parse = |flags: &[&str]| {
+            let argv = ["cargo-harbormaster", "--config", config.to_str().unwrap()];
+            let argv = argv.iter().chain(flags).map(Into::into).collect();
+            parse_flags(argv).0
+        };
+        // Also satisfying the flags requiring --conduit-uri
+        let args = parse(&["--resume", "--chunk-size", "10", "PHID-HMBT-1"]);
+        assert_eq!(
+            args.conduit_uri.as_deref(),
+            Some("https://phab.example.com")
+        );
+        assert_eq!(args.token.as_deref(), Some("api-config"));
+        let args = parse(&[
+            "--conduit-uri",
+            "https://other.example.com",
+            "--token=api-flag",
+        ]);
+        assert_eq!(
+            args.conduit_uri.as_deref(),
+            Some("https://other.example.com")
+        );
+        assert_eq!(args.token.as_deref(), Some("api-flag"));
+    }
+
+    #[test]
+    fn global_flags() {
+        let argv = [
+            "cargo-harbormaster",
+            "lifecycle",
+            "PHID-HMBT-1",
+            "work",
+            "--conduit-uri",
+            "https://phab.example.com",
+            "--token=api-token",
+            "--dry-run",
+            "--timeout=5",
+        ];
+        let (args, config) = parse_flags(argv.iter().map(Into::into).collect());
+        assert!(config.unwrap().conduit_uri.is_none());
+        assert!(matches!(args.command, Some(Command::Lifecycle(_))));
+        assert_eq!(
+            args.conduit_uri.as_deref(),
+            Some("https://phab.example.com")
+        );
+        assert_eq!(args.token.as_deref(), Some("api-token"));
+        assert!(args.dry_run);
+        assert_eq!(args.timeout, 5);
+    }
+
+    #[test]
+    fn parses_the_inputs_concurrently() {
+        let generic = input::tests::fixture(
+            "inputs-generic.json",
+            r#"[{"path": "LICENSE", "code": "license", "severity": "error", "message": "missing"}]"#,
+        );
+        let typos = input::tests::fixture(
+            "inputs-typos.jsonl",
+            r#"{"type":"typo","path":"README.md","line_num":1,"byte_offset":0,"typo":"teh","corrections":["the"]}"#,
+        );
+        let fragment = input::tests::fixture(
+            "inputs-fragment.json",
+            r#"{"unit": [{"name": "plugin", "result": "pass"}]}"#,
+        );
+        let plugin = format!("harbormaster={}", fragment.display());
+        let args = Flags::parse_from([
+            "cargo-harbormaster",
+            "--workspace=.",
+            "--generic-lints",
+            generic.to_str().unwrap(),
+            "--typos-json",
+            typos.to_str().unwrap(),
+            "--golangci-json",
+            "/nonexistent/golangci.json",
+            "--parser-input",
+            &plugin,
+        ]);
+        let parsers = parser::Registry::new(&[]).unwrap();
+        let report = args
+            .inputs
+            .parse(Path::new("/ws"), &args.options, &parsers)
+            .unwrap();
+        let lints: Vec<_> = report.lints.iter().map(|l| l.code.as_str()).collect();
+        // In a fixed order of the input types, whichever finishes first
+        assert_eq!(lints, ["typo", "license"]);
+        assert_eq!(report.units.len(), 1);
+        assert_eq!(report.units[0].name, "plugin");
+        assert_eq!(report.metadata.parse_failures, 1);
+    }
+
+    #[test]
+    fn internal_compiler_errors() {
+        let message = |level: &str, message: &str| {
+            let line = serde_json::json!({
+                "reason": "compiler-message",
+                "package_id": "path+file:///ws/crates/foo#0.1.0",
+                "manifest_path": "/ws/crates/foo/Cargo.toml",
+                "target": {
+                    "kind": ["lib"], "crate_types": ["lib"], "name": "foo",
+                    "src_path": "/ws/crates/foo/src/lib.rs", "edition": "2021",
+                    "doc": true, "doctest": true, "test": true
+                },
+                "message": {
+                    "$message_type": "diagnostic", "children": [], "code": null,
+                    "level": level, "message": message, "spans": [],
+                    "rendered": format!("{}: {}\n", level, message)
+                }
+            });
+            line.to_string()
+        };
+        // stderr captured in the same file, with the backtrace of the panic
+        let stream = [
+            "\u{1b}[31merror: internal compiler error\u{1b}[0m: compiler/rustc_middle/src/ty/mod.rs:1: unexpected type".into(),
+            "thread 'rustc' panicked at compiler/rustc_middle/src/ty/mod.rs:1:5:".into(),
+            "note: rustc 1.72.1 running on x86_64-unknown-linux-gnu".into(),
+            message("error: internal compiler error", "unexpected type"),
+            r#"{"reason":"build-finished","success":false}"#.into(),
+            "error: could not compile `foo` (lib)".into(),
+        ]
+        .join("\n");
+        let path = input::tests::fixture("ice.json", &stream);
+        let args = Flags::parse_from([
+            "cargo-harbormaster",
+            "--workspace=.",
+            "--clippy-json",
+            path.to_str().unwrap(),
+        ]);
+        let parsers = parser::Registry::new(&[]).unwrap();
+        let report = args
+            .inputs
+            .parse(Path::new("/ws"), &args.options, &parsers)
+            .unwrap();
+        assert!(report.metadata.ice);
+        assert!(report.lints.is_empty());
+        assert_eq!(report.units.len(), 1);
+        let unit = &report.units[0];
+        assert_eq!(unit.name, "internal compiler error");
+        assert_eq!(unit.result, "broken");
+        assert_eq!(unit.engine.as_deref(), Some("rustc"));
+        let details = unit.details.as_deref().unwrap();
+        assert!(details.contains("error: internal compiler error: compiler/rustc_middle"));
+        assert!(details.contains("thread 'rustc' panicked at"));
+        assert!(details.contains("note: rustc 1.72.1"));
+        assert!(details.contains("error: internal compiler error: unexpected type"));
+        assert!(!details.contains("could not compile"));
+        assert!(!details.contains('\u{1b}'));
+
+        // Passing builds are escalated, and the other statuses kept
+        let status = |status| resolve_status(status, &report.lints, &[], report.metadata.ice);
+        assert!(matches!(status(Status::Pass), Status::Fail));
+        assert!(matches!(status(Status::Auto), Status::Fail));
+        assert!(matches!(status(Status::Work), Status::Work));
+        assert!(matches!(status(Status::Abort), Status::Abort));
+        assert!(matches!(
+            resolve_status(Status::Pass, &[], &[], false),
+            Status::Pass
+        ));
+    }
+
+    #[test]
+    fn doctests() {
+        let output = "   Doc-tests my_crate
+
+running 4 tests
+test src/lib.rs - foo (line 12) ... ok
+test src/lib.rs - Bar::new (line 40) - compile fail ... ok
+test src/util.rs - util::slow (line 7) ... ignored
+test src/lib.rs - baz (line 55) ... FAILED
+
+failures:
+
+---- src/lib.rs - baz (line 55) stdout ----
+Test executable failed (exit status: 101).
+
+stderr:
+thread 'main' panicked at 'assertion failed: false', src/lib.rs:3:1
+
+failures:
+    src/lib.rs - baz (line 55)
+
+test result: FAILED. 2 passed; 1 failed; 1 ignored; 0 measured; 0 filtered out; finished in 0.12s
+
+   Doc-tests other
+
+running 1 test
+test src/lib.rs - other (line 1) ... ok
+";
+        let path = input::tests::fixture("doctest.txt", output);
+        let resolver = PathResolver {
+            workspace: "crates/my-crate".into(),
+            maps: vec![],
+            cwd: "/ws".into(),
+        };
+        let units = UnitResult::from_doctest(&path, &resolver).unwrap();
+        let summary: Vec<_> = units
+            .iter()
+            .map(|u| {
+                (
+                    u.namespace.as_deref().unwrap(),
+                    u.name.as_str(),
+                    u.result.as_str(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("my_crate", "crates/my-crate/src/lib.rs:12 - foo", "pass"),
+                (
+                    "my_crate",
+                    "crates/my-crate/src/lib.rs:40 - Bar::new",
+                    "pass"
+                ),
+                (
+                    "my_crate",
+                    "crates/my-crate/src/util.rs:7 - util::slow",
+                    "skip"
+                ),
+                ("my_crate", "crates/my-crate/src/lib.rs:55 - baz", "fail"),
+                ("other", "crates/my-crate/src/lib.rs:1 - other", "pass"),
+            ]
+        );
+        assert!(units.iter().all(|u| u.engine.as_deref() == Some("rustdoc")));
+        assert_eq!(
+            units[2].path.as_deref(),
+            Some("crates/my-crate/src/util.rs")
+        );
+        // The output of the failed test only
+        let details = units[3].details.as_deref().unwrap();
+        assert!(details.starts_with("```\nTest executable failed (exit status: 101)."));
+        assert!(details.contains("panicked at 'assertion failed: false'"));
+        assert!(!details.contains("test result"));
+        assert!(units.iter().filter(|u| u.details.is_some()).count() == 1);
     }
 }
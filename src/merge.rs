@@ -0,0 +1,173 @@
+//! Merging of the results of several runs, e.g. over a feature matrix
+use std::collections::{BTreeSet, HashMap, HashSet};
+
+use clap::ValueEnum;
+use itertools::Itertools;
+use log::*;
+
+use crate::{LintResult, UnitResult};
+
+/// How to merge tests with the same name coming from runs with different feature sets
+#[derive(Debug, Copy, Clone, Default, ValueEnum)]
+pub(crate) enum MatrixMerge {
+    /// Suffix the test namespaces with the feature set
+    #[default]
+    Suffix,
+    /// Keep the worst result for each test
+    Worst,
+}
+
+pub(crate) fn suffix_namespaces(units: &mut [UnitResult], features: &str) {
+    for unit in units {
+        unit.namespace = Some(match &unit.namespace {
+            Some(namespace) => format!("{} [{}]", namespace, features),
+            None => format!("[{}]", features),
+        });
+    }
+}
+
+/// Ordering of the results from best to worst
+fn rank(result: &str) -> u8 {
+    match result {
+        "pass" => 0,
+        "skip" => 1,
+        "unsound" => 2,
+        _ => 3,
+    }
+}
+
+type TestKey = (Option<String>, String);
+
+/// Keep a single result per test (namespace and name), the worst one.
+/// Also returns the tests that had several results.
+fn dedupe(units: Vec<UnitResult>) -> (Vec<UnitResult>, HashSet<TestKey>) {
+    let mut results = HashMap::<TestKey, UnitResult>::new();
+    let mut duplicates = HashSet::new();
+    for unit in units {
+        let key = (unit.namespace.clone(), unit.name.clone());
+        match results.get(&key) {
+            Some(prev) => {
+                duplicates.insert(key.clone());
+                if rank(&prev.result) < rank(&unit.result) {
+                    results.insert(key, unit);
+                }
+            }
+            None => {
+                results.insert(key, unit);
+            }
+        }
+    }
+    (results.into_values().collect(), duplicates)
+}
+
+/// Keep a single result per test (namespace and name), the worst one
+pub(crate) fn keep_worst(units: Vec<UnitResult>) -> Vec<UnitResult> {
+    dedupe(units).0
+}
+
+/// Merge the results of a partitioned run (`cargo nextest run --partition`), where each test
+/// is expected to appear in a single partition.
+pub(crate) fn merge_partitions(partitions: Vec<Vec<UnitResult>>) -> Vec<UnitResult> {
+    let (units, duplicates) = dedupe(partitions.into_iter().flatten().collect());
+    if !duplicates.is_empty() {
+        warn!(
+            "{} tests appear in multiple partitions, keeping their worst result: {}",
+            duplicates.len(),
+            duplicates
+                .iter()
+                .map(|(namespace, name)| match namespace {
+                    Some(namespace) => format!("{} {}", namespace, name),
+                    None => name.clone(),
+                })
+                .sorted()
+                .join(", ")
+        );
+    }
+    units
+}
+
+/// Keep a single lint per name, code, location and message, the most severe one, along with the
+/// targets it was reported for (e.g. `lib`, `bin/foo`), in the order of first appearance. Lints
+/// of different names (e.g. given by `--lint-name` for stable and nightly clippy) are kept apart.
+pub(crate) fn dedupe_lints(
+    lints: impl IntoIterator<Item = (LintResult, String)>,
+) -> Vec<(LintResult, BTreeSet<String>)> {
+    type LintKey = (String, String, String, Option<usize>, Option<String>);
+    let mut order = vec![];
+    let mut results = HashMap::<LintKey, (LintResult, BTreeSet<String>)>::new();
+    for (lint, target) in lints {
+        let key = (
+            lint.name.clone(),
+            lint.code.clone(),
+            lint.path.clone(),
+            lint.line,
+            lint.description.clone(),
+        );
+        match results.get_mut(&key) {
+            Some((prev, targets)) => {
+                if lint.severity_rank() < prev.severity_rank() {
+                    *prev = lint;
+                }
+                targets.insert(target);
+            }
+            None => {
+                order.push(key.clone());
+                results.insert(key, (lint, BTreeSet::from([target])));
+            }
+        }
+    }
+    order
+        .into_iter()
+        .map(|key| results.remove(&key).unwrap())
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{lint, unit};
+
+    #[test]
+    fn dedupe_by_name() {
+        let nightly = LintResult {
+            name: "clippy-nightly".into(),
+            ..lint("warning", "src/lib.rs", Some(1))
+        };
+        let lints = [
+            (lint("warning", "src/lib.rs", Some(1)), "lib".to_string()),
+            (lint("error", "src/lib.rs", Some(1)), "bin/foo".to_string()),
+            (nightly, "lib".to_string()),
+        ];
+        let deduped = dedupe_lints(lints);
+        assert_eq!(deduped.len(), 2);
+        assert_eq!(deduped[0].0.severity, "error");
+        assert_eq!(deduped[0].1.len(), 2);
+        assert_eq!(deduped[1].0.name, "clippy-nightly");
+    }
+
+    #[test]
+    fn merges_partitions() {
+        let other = UnitResult {
+            namespace: Some("other-crate".into()),
+            ..unit("tests::a", "pass")
+        };
+        let partitions = vec![
+            vec![unit("tests::a", "fail"), unit("tests::b", "pass")],
+            vec![unit("tests::a", "pass"), unit("tests::b", "skip"), other],
+            vec![unit("tests::c", "pass")],
+        ];
+        let merged = merge_partitions(partitions)
+            .into_iter()
+            .map(|u| (u.namespace.unwrap(), u.name, u.result))
+            .sorted()
+            .collect::<Vec<_>>();
+        let expected = [
+            ("my-crate", "tests::a", "fail"),
+            ("my-crate", "tests::b", "skip"),
+            ("my-crate", "tests::c", "pass"),
+            ("other-crate", "tests::a", "pass"),
+        ]
+        .map(|(n, t, r)| (n.to_string(), t.to_string(), r.to_string()));
+        assert_eq!(merged, expected);
+    }
+}
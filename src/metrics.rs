@@ -0,0 +1,120 @@
+//! Metrics of a run in the Prometheus exposition format, written for the node exporter textfile
+//! collector (`--metrics-file`) or pushed to a Pushgateway (`--pushgateway`)
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::path::Path;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+use itertools::Itertools;
+
+use crate::report::Report;
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    /// Test results by result (pass, fail...)
+    tests: BTreeMap<String, usize>,
+    /// Lints by severity
+    lints: BTreeMap<String, usize>,
+    /// Size of the harbormaster.sendmessage parameters
+    pub payload_bytes: Option<usize>,
+    /// Duration of the submission to Conduit
+    pub submission: Option<Duration>,
+}
+impl Metrics {
+    pub fn new(report: &Report) -> Self {
+        Self {
+            tests: report
+                .units
+                .iter()
+                .counts_by(|u| u.result.clone())
+                .into_iter()
+                .collect(),
+            lints: report
+                .lints
+                .iter()
+                .counts_by(|l| l.severity.to_lowercase())
+                .into_iter()
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    fn render(&self) -> String {
+        let mut out = String::new();
+        let mut gauge = |name: &str, help: &str, values: Vec<(String, f64)>| {
+            writeln!(out, "# HELP {} {}", name, help).unwrap();
+            writeln!(out, "# TYPE {} gauge", name).unwrap();
+            for (labels, value) in values {
+                writeln!(out, "{}{} {}", name, labels, value).unwrap();
+            }
+        };
+        let labelled = |label: &str, counts: &BTreeMap<String, usize>| {
+            counts
+                .iter()
+                .map(|(value, count)| {
+                    let value = value.replace('\\', "\\\\").replace('"', "\\\"");
+                    (format!("{{{}=\"{}\"}}", label, value), *count as f64)
+                })
+                .collect()
+        };
+        gauge(
+            "harbormaster_tests",
+            "Number of test results, by result",
+            labelled("result", &self.tests),
+        );
+        gauge(
+            "harbormaster_lints",
+            "Number of lints, by severity",
+            labelled("severity", &self.lints),
+        );
+        if let Some(bytes) = self.payload_bytes {
+            gauge(
+                "harbormaster_payload_bytes",
+                "Size of the harbormaster.sendmessage parameters",
+                vec![(String::new(), bytes as f64)],
+            );
+        }
+        if let Some(submission) = self.submission {
+            gauge(
+                "harbormaster_submission_seconds",
+                "Duration of the submission to Conduit",
+                vec![(String::new(), submission.as_secs_f64())],
+            );
+        }
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .unwrap_or_default();
+        gauge(
+            "harbormaster_last_run_timestamp_seconds",
+            "Unix time of the run",
+            vec![(String::new(), now.as_secs() as f64)],
+        );
+        out
+    }
+
+    /// Write the metrics for the textfile collector, atomically so that it never reads a
+    /// partial file
+    pub fn write(&self, path: &Path) -> anyhow::Result<()> {
+        let tmp = path.with_extension("prom.tmp");
+        std::fs::write(&tmp, self.render())
+            .with_context(|| format!("Failed to write {:?}", tmp))?;
+        std::fs::rename(&tmp, path).with_context(|| format!("Failed to write {:?}", path))?;
+        Ok(())
+    }
+
+    /// Replace the metrics of the job on a Pushgateway (e.g. http://pushgateway:9091)
+    pub async fn push(&self, url: &str, job: &str, timeout: Duration) -> anyhow::Result<()> {
+        let url = format!("{}/metrics/job/{}", url.trim_end_matches('/'), job);
+        reqwest::Client::builder()
+            .timeout(timeout)
+            .build()?
+            .put(&url)
+            .header(reqwest::header::CONTENT_TYPE, "text/plain; version=0.0.4")
+            .body(self.render())
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -0,0 +1,98 @@
+//! Naming of the tests reported by nextest
+use clap::ValueEnum;
+
+use crate::UnitResult;
+
+/// Name of the reported tests
+#[derive(Debug, Copy, Clone, Default, ValueEnum)]
+pub(crate) enum TestName {
+    /// Full path, including the module (e.g. `tests::it_works`)
+    #[default]
+    Full,
+    /// Function name only (e.g. `it_works`). The module can be added to the namespace with
+    /// `{module}`.
+    Function,
+}
+
+/// Apply the namespace template and test name format to a test, whose namespace is the nextest
+/// binary id (e.g. `my-crate::bin/foo`) and name the full test path (e.g. `tests::it_works`).
+///
+/// Supported placeholders: `{binary_id}`, `{package}`, `{binary}` (e.g. `lib`, `bin/foo`,
+/// `integration`) and `{module}`.
+pub(crate) fn rename(unit: &mut UnitResult, namespace_format: &str, test_name: TestName) {
+    let binary_id = unit.namespace.clone().unwrap_or_default();
+    let (package, binary) = binary_id.split_once("::").unwrap_or((&binary_id, "lib"));
+    let (module, function) = match unit.name.rsplit_once("::") {
+        Some((module, function)) => (module.to_string(), function.to_string()),
+        None => (String::new(), unit.name.clone()),
+    };
+    let namespace = namespace_format
+        .replace("{binary_id}", &binary_id)
+        .replace("{package}", package)
+        .replace("{binary}", binary)
+        .replace("{module}", &module);
+    // Drop the empty components, e.g. for tests at the root of a crate
+    let namespace = namespace
+        .split("::")
+        .filter(|c| !c.is_empty())
+        .collect::<Vec<_>>();
+    unit.namespace = (!namespace.is_empty()).then(|| namespace.join("::"));
+    if let TestName::Function = test_name {
+        unit.name = function;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::unit;
+
+    fn renamed(binary_id: &str, name: &str, format: &str, test_name: TestName) -> UnitResult {
+        let mut unit = unit(name, "pass");
+        unit.namespace = Some(binary_id.into());
+        rename(&mut unit, format, test_name);
+        unit
+    }
+
+    #[test]
+    fn templates() {
+        let unit = renamed(
+            "my-crate::bin/foo",
+            "tests::it_works",
+            "{binary_id}",
+            TestName::Full,
+        );
+        assert_eq!(unit.namespace.as_deref(), Some("my-crate::bin/foo"));
+        assert_eq!(unit.name, "tests::it_works");
+
+        let unit = renamed(
+            "my-crate::bin/foo",
+            "a::b::it_works",
+            "{package}::{binary}::{module}",
+            TestName::Function,
+        );
+        assert_eq!(unit.namespace.as_deref(), Some("my-crate::bin/foo::a::b"));
+        assert_eq!(unit.name, "it_works");
+
+        // The library has no binary in its id
+        let unit = renamed(
+            "my-crate",
+            "tests::it_works",
+            "{package} {binary}",
+            TestName::Full,
+        );
+        assert_eq!(unit.namespace.as_deref(), Some("my-crate lib"));
+
+        // Empty components are dropped
+        let unit = renamed(
+            "my-crate",
+            "it_works",
+            "{package}::{module}",
+            TestName::Function,
+        );
+        assert_eq!(unit.namespace.as_deref(), Some("my-crate"));
+        let unit = renamed("my-crate", "it_works", "{module}", TestName::Function);
+        assert_eq!(unit.namespace, None);
+        assert_eq!(unit.name, "it_works");
+    }
+}
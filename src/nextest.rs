@@ -0,0 +1,150 @@
+//! Tests listed by `cargo nextest list --message-format json`, to report the ones without results
+use std::collections::{BTreeMap, HashSet};
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::{input, UnitResult};
+
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct List {
+    rust_suites: BTreeMap<String, Suite>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct Suite {
+    binary_id: String,
+    #[serde(default)]
+    testcases: BTreeMap<String, TestCase>,
+}
+#[derive(Deserialize)]
+#[serde(rename_all = "kebab-case")]
+struct TestCase {
+    filter_match: FilterMatch,
+}
+#[derive(Deserialize)]
+struct FilterMatch {
+    /// `matches` or `mismatch`
+    status: String,
+    /// Reason of a mismatch, e.g. `ignored`, `string`, `expression` or `partition`
+    #[serde(default)]
+    reason: Option<String>,
+}
+
+/// Results for the listed tests that are missing from `results`: `skip` for the tests that are
+/// filtered out (e.g. ignored), and `broken` for the other ones, which were expected to run (e.g.
+/// when their test binary crashed)
+pub(crate) fn not_run(path: &Path, results: &[UnitResult]) -> anyhow::Result<Vec<UnitResult>> {
+    let list: List = serde_json::from_str(&input::read_to_string(path)?)?;
+    let run: HashSet<_> = results
+        .iter()
+        .map(|u| (u.namespace.as_deref().unwrap_or_default(), u.name.as_str()))
+        .collect();
+    let mut missing = vec![];
+    for suite in list.rust_suites.into_values() {
+        for (name, test) in suite.testcases {
+            if run.contains(&(suite.binary_id.as_str(), name.as_str())) {
+                continue;
+            }
+            // The ignored tests only match with --run-ignored
+            let (result, details) =
+                match (test.filter_match.status.as_str(), test.filter_match.reason) {
+                    ("matches", _) => (
+                        "broken",
+                        "Listed by cargo nextest list, but not run (e.g. the test binary crashed)"
+                            .into(),
+                    ),
+                    (_, Some(reason)) => ("skip", format!("Filtered out ({})", reason)),
+                    (_, None) => ("skip", "Filtered out".into()),
+                };
+            missing.push(UnitResult {
+                name,
+                result: result.into(),
+                namespace: Some(suite.binary_id.clone()),
+                engine: Some("cargo-nextest".into()),
+                details: Some(details),
+                ..Default::default()
+            });
+        }
+    }
+    Ok(missing)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+    use crate::report::tests::unit;
+
+    #[test]
+    fn reports_the_tests_not_run() {
+        let json = r#"{
+            "rust-build-meta": {"target-directory": "/ws/target"},
+            "test-count": 5,
+            "rust-suites": {
+                "my-crate": {
+                    "package-name": "my-crate",
+                    "binary-id": "my-crate",
+                    "kind": "lib",
+                    "testcases": {
+                        "tests::passes": {"ignored": false, "filter-match": {"status": "matches"}},
+                        "tests::crashed": {"ignored": false, "filter-match": {"status": "matches"}},
+                        "tests::ignored": {
+                            "ignored": true,
+                            "filter-match": {"status": "mismatch", "reason": "ignored"}
+                        }
+                    }
+                },
+                "my-crate::integration": {
+                    "package-name": "my-crate",
+                    "binary-id": "my-crate::integration",
+                    "kind": "test",
+                    "testcases": {
+                        "filtered": {"ignored": false, "filter-match": {"status": "mismatch"}}
+                    }
+                },
+                "my-crate::bench": {"binary-id": "my-crate::bench"}
+            }
+        }"#;
+        let results = [unit("tests::passes", "pass")];
+        let missing = not_run(&fixture("nextest-list.json", json), &results).unwrap();
+        let summary: Vec<_> = missing
+            .iter()
+            .map(|u| {
+                (
+                    u.namespace.as_deref().unwrap(),
+                    u.name.as_str(),
+                    u.result.as_str(),
+                    u.details.as_deref().unwrap(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                (
+                    "my-crate",
+                    "tests::crashed",
+                    "broken",
+                    "Listed by cargo nextest list, but not run (e.g. the test binary crashed)"
+                ),
+                (
+                    "my-crate",
+                    "tests::ignored",
+                    "skip",
+                    "Filtered out (ignored)"
+                ),
+                ("my-crate::integration", "filtered", "skip", "Filtered out"),
+            ]
+        );
+        assert!(missing
+            .iter()
+            .all(|u| u.engine.as_deref() == Some("cargo-nextest")));
+        // A test of the same name in another binary is not a result of the listed one
+        let mut other = unit("tests::crashed", "pass");
+        other.namespace = Some("other".into());
+        let missing = not_run(&fixture("nextest-list.json", json), &[other]).unwrap();
+        assert_eq!(missing.len(), 4);
+    }
+}
@@ -0,0 +1,230 @@
+//! Parsers of additional input formats, either compiled in or loaded as WASM plugins declared in
+//! the `[[parser]]` sections of the configuration file
+use std::collections::BTreeMap;
+use std::io::Read;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+use anyhow::Context;
+use serde::{Deserialize, Deserializer};
+
+use crate::paths::PathResolver;
+use crate::report::Report;
+use crate::{input, LintResult, UnitResult};
+
+/// Parser of an input format into a report fragment
+pub(crate) trait Parser: Send + Sync {
+    /// Parse the (decompressed) contents of an input file. The paths of the results are resolved
+    /// afterwards, relative to the current directory.
+    fn parse(&self, input: &[u8]) -> anyhow::Result<Report>;
+}
+
+/// Results in the format of harbormaster.sendmessage, i.e. `{"unit": [...], "lint": [...]}`,
+/// for tools producing them directly. This is also the output format of the WASM plugins.
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct Fragment {
+    #[serde(default)]
+    unit: Vec<UnitResult>,
+    #[serde(default)]
+    lint: Vec<LintResult>,
+}
+impl From<Fragment> for Report {
+    fn from(fragment: Fragment) -> Self {
+        Report {
+            units: fragment.unit,
+            lints: fragment.lint,
+            ..Default::default()
+        }
+    }
+}
+
+struct Harbormaster;
+impl Parser for Harbormaster {
+    fn parse(&self, input: &[u8]) -> anyhow::Result<Report> {
+        Ok(serde_json::from_slice::<Fragment>(input)?.into())
+    }
+}
+
+/// `[[parser]]` section of the configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ParserConfig {
+    /// Name used in `--parser-input NAME=PATH`
+    name: String,
+    /// Path to the WASM module, relative to the current directory
+    wasm: PathBuf,
+}
+
+/// Available parsers, by name
+pub(crate) struct Registry {
+    parsers: BTreeMap<String, Box<dyn Parser>>,
+}
+impl Registry {
+    /// The compiled-in parsers, followed by the plugins of the configuration file. Parsers
+    /// behind cargo features are added here.
+    pub fn new(plugins: &[ParserConfig]) -> anyhow::Result<Self> {
+        let mut parsers: BTreeMap<String, Box<dyn Parser>> = BTreeMap::new();
+        parsers.insert("harbormaster".into(), Box::new(Harbormaster));
+        for plugin in plugins {
+            anyhow::ensure!(
+                !parsers.contains_key(&plugin.name),
+                "Duplicate parser {}",
+                plugin.name
+            );
+            parsers.insert(plugin.name.clone(), load_plugin(&plugin.wasm)?);
+        }
+        Ok(Self { parsers })
+    }
+    pub fn get(&self, name: &str) -> anyhow::Result<&dyn Parser> {
+        self.parsers.get(name).map(|p| p.as_ref()).with_context(|| {
+            format!(
+                "Unknown parser {} (available: {})",
+                name,
+                self.parsers.keys().cloned().collect::<Vec<_>>().join(", ")
+            )
+        })
+    }
+}
+
+#[cfg(feature = "wasm")]
+fn load_plugin(path: &Path) -> anyhow::Result<Box<dyn Parser>> {
+    Ok(Box::new(crate::wasm::Plugin::load(path)?))
+}
+#[cfg(not(feature = "wasm"))]
+fn load_plugin(path: &Path) -> anyhow::Result<Box<dyn Parser>> {
+    anyhow::bail!(
+        "Cannot load {:?}: cargo-harbormaster was built without the wasm feature",
+        path
+    )
+}
+
+/// Input file of a parser (`NAME=PATH`)
+#[derive(Debug, Clone)]
+pub(crate) struct ParserInput {
+    pub parser: String,
+    pub path: PathBuf,
+}
+impl FromStr for ParserInput {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (parser, path) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected NAME=PATH, got {}", s))?;
+        Ok(Self {
+            parser: parser.into(),
+            path: path.into(),
+        })
+    }
+}
+impl<'de> Deserialize<'de> for ParserInput {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(serde::de::Error::custom)
+    }
+}
+
+/// Parse an input with the registered parser, resolving the paths of the results
+pub(crate) fn parse(
+    registry: &Registry,
+    input: &ParserInput,
+    resolver: &PathResolver,
+) -> anyhow::Result<Report> {
+    let parser = registry.get(&input.parser)?;
+    let mut data = vec![];
+    input::open(&input.path)?
+        .read_to_end(&mut data)
+        .with_context(|| format!("Failed to read {:?}", input.path))?;
+    let mut report = parser
+        .parse(&data)
+        .with_context(|| format!("{} failed to parse {:?}", input.parser, input.path))?;
+    let resolve = |path: &str| {
+        resolver
+            .resolve(Path::new(path), Some(&resolver.cwd))
+            .to_string_lossy()
+            .to_string()
+    };
+    for lint in &mut report.lints {
+        lint.path = resolve(&lint.path);
+    }
+    for unit in &mut report.units {
+        unit.path = unit.path.as_deref().map(resolve);
+    }
+    Ok(report)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    fn resolver() -> PathResolver {
+        PathResolver {
+            workspace: "crates/foo".into(),
+            maps: vec![],
+            cwd: "/ws/crates/foo".into(),
+        }
+    }
+
+    #[test]
+    fn dispatch() {
+        let registry = Registry::new(&[]).unwrap();
+        let error = registry.get("sonar").err().unwrap();
+        assert_eq!(
+            error.to_string(),
+            "Unknown parser sonar (available: harbormaster)"
+        );
+        let json = r#"{
+            "unit": [{"name": "it_works", "result": "pass", "path": "tests/it.rs"}],
+            "lint": [{"name": "sonar", "code": "S1", "severity": "warning",
+                      "path": "/ws/crates/foo/src/lib.rs", "line": 3}]
+        }"#;
+        let input: ParserInput =
+            format!("harbormaster={}", fixture("fragment.json", json).display())
+                .parse()
+                .unwrap();
+        assert_eq!(input.parser, "harbormaster");
+        let report = parse(&registry, &input, &resolver()).unwrap();
+        assert_eq!(report.units[0].name, "it_works");
+        assert_eq!(
+            report.units[0].path.as_deref(),
+            Some("crates/foo/tests/it.rs")
+        );
+        assert_eq!(report.lints[0].code, "S1");
+        assert_eq!(report.lints[0].path, "crates/foo/src/lib.rs");
+
+        let invalid = ParserInput {
+            parser: "harbormaster".into(),
+            path: fixture("fragment-invalid.json", r#"{"units": []}"#),
+        };
+        let error = parse(&registry, &invalid, &resolver()).unwrap_err();
+        assert!(format!("{:#}", error).starts_with("harbormaster failed to parse"));
+        let unknown = ParserInput {
+            parser: "sonar".into(),
+            ..invalid
+        };
+        assert!(parse(&registry, &unknown, &resolver()).is_err());
+        assert!("fragment.json".parse::<ParserInput>().is_err());
+    }
+
+    #[test]
+    fn plugins() {
+        let plugin = ParserConfig {
+            name: "harbormaster".into(),
+            wasm: "plugin.wasm".into(),
+        };
+        let error = Registry::new(&[plugin]).err().unwrap();
+        assert_eq!(error.to_string(), "Duplicate parser harbormaster");
+        #[cfg(not(feature = "wasm"))]
+        {
+            let plugin = ParserConfig {
+                name: "sonar".into(),
+                wasm: "plugin.wasm".into(),
+            };
+            let error = Registry::new(&[plugin]).err().unwrap();
+            assert!(error.to_string().contains("built without the wasm feature"));
+        }
+    }
+}
@@ -0,0 +1,371 @@
+//! Mapping of the paths found in diagnostics to paths in the repository
+use std::path::{Component, Path, PathBuf};
+use std::process::Command;
+use std::str::FromStr;
+
+use anyhow::Context;
+
+/// Rewrite rule replacing the `from` prefix by `to`
+#[derive(Debug, Clone)]
+pub(crate) struct PathMap {
+    from: PathBuf,
+    to: PathBuf,
+}
+impl FromStr for PathMap {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (from, to) = s
+            .split_once('=')
+            .ok_or_else(|| anyhow::anyhow!("Expected FROM=TO, got {}", s))?;
+        Ok(Self {
+            from: normalize(from),
+            to: to.into(),
+        })
+    }
+}
+
+pub(crate) struct PathResolver {
+    /// Path to the workspace relative to the repository root
+    pub workspace: PathBuf,
+    pub maps: Vec<PathMap>,
+    /// Directory the tools ran in (the current directory, or the checkout of the server mode),
+    /// for the tools reporting absolute paths
+    pub cwd: PathBuf,
+}
+impl PathResolver {
+    /// Convert a path found in a diagnostic into a path relative to the repository root, with
+    /// forward slashes.
+    ///
+    /// The first matching `--path-map` rule wins. Otherwise, absolute paths within `build_root`
+    /// (the workspace root at build time) are made relative to it, and relative paths are
+    /// interpreted relative to the workspace, without `.` components.
+    pub fn resolve(&self, path: &Path, build_root: Option<&Path>) -> PathBuf {
+        let path = normalize(&path.to_string_lossy());
+        for map in &self.maps {
+            if let Ok(rest) = path.strip_prefix(&map.from) {
+                return join(&map.to, rest);
+            }
+        }
+        let path = build_root
+            .and_then(|root| path.strip_prefix(normalize(&root.to_string_lossy())).ok())
+            .unwrap_or(&path);
+        join(&self.workspace, path)
+    }
+    /// Whether a diagnostic path is outside of the workspace sources (dependencies, standard
+    /// library or generated code in the target directory), unless it is mapped with `--path-map`.
+    pub fn is_external(&self, path: &Path, build_root: Option<&Path>) -> bool {
+        let path = normalize(&path.to_string_lossy());
+        if self.maps.iter().any(|map| path.starts_with(&map.from)) {
+            return false;
+        }
+        let text = path.to_string_lossy();
+        if text.contains("/.cargo/registry/") || text.contains("/.cargo/git/") {
+            return true;
+        }
+        let relative = match build_root {
+            _ if !is_absolute(&path) => &path,
+            Some(root) => match path.strip_prefix(normalize(&root.to_string_lossy())) {
+                Ok(relative) => relative,
+                Err(_) => return true,
+            },
+            // Without the build root, only the known locations can be excluded
+            None => return text.starts_with("/rustc/"),
+        };
+        relative.starts_with("target")
+    }
+}
+
+/// Path with forward slashes, as the paths produced on Windows agents (`src\lib.rs`,
+/// `C:\agent\ws\src\lib.rs`, `\\?\C:\agent\ws`...) have to be matched on any host. The
+/// drive letter is upper-cased, as cargo and rustc do not always agree on its case.
+pub(crate) fn normalize(path: &str) -> PathBuf {
+    let path = path.replace('\\', "/");
+    // Verbatim paths, and the paths of `file:///C:/...` URLs
+    let path = path.strip_prefix("//?/").unwrap_or(&path);
+    let path = match path.as_bytes() {
+        [b'/', drive, b':', ..] if drive.is_ascii_alphabetic() => &path[1..],
+        _ => path,
+    };
+    match path.as_bytes() {
+        [drive, b':', ..] if drive.is_ascii_alphabetic() => {
+            format!("{}{}", drive.to_ascii_uppercase() as char, &path[1..]).into()
+        }
+        _ => path.into(),
+    }
+}
+
+/// Whether a normalized path is absolute, including paths starting with a drive letter on hosts
+/// other than Windows
+pub(crate) fn is_absolute(path: &Path) -> bool {
+    path.has_root()
+        || matches!(
+            path.to_string_lossy().as_bytes(),
+            [drive, b':', b'/', ..] if drive.is_ascii_alphabetic()
+        )
+}
+
+/// Join a relative path to a directory with forward slashes, whatever the host, without `.`
+/// components (e.g. `./src/main.rs`, which would not match the paths of the repository).
+/// Absolute paths are returned as is.
+pub(crate) fn join(dir: &Path, path: &Path) -> PathBuf {
+    if is_absolute(path) {
+        return path.to_string_lossy().replace('\\', "/").into();
+    }
+    let mut joined = String::new();
+    for component in dir.components().chain(path.components()) {
+        if component == Component::CurDir {
+            continue;
+        }
+        if !joined.is_empty() && !joined.ends_with('/') && component != Component::RootDir {
+            joined.push('/');
+        }
+        joined.push_str(&component.as_os_str().to_string_lossy().replace('\\', "/"));
+    }
+    joined.into()
+}
+
+/// Root of the git repository containing a directory
+fn git_root(dir: &Path) -> anyhow::Result<PathBuf> {
+    let output = Command::new("git")
+        .args(["rev-parse", "--show-toplevel"])
+        .current_dir(dir)
+        .output()
+        .context("Failed to run git")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "git rev-parse failed: {}",
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(PathBuf::from(String::from_utf8(output.stdout)?.trim()).canonicalize()?)
+}
+
+/// Path of the cargo workspace containing a directory, relative to the root of the git
+/// repository.
+pub(crate) fn detect_workspace(dir: &Path) -> anyhow::Result<PathBuf> {
+    let repo = git_root(dir)?;
+    let metadata = cargo_metadata::MetadataCommand::new()
+        .no_deps()
+        .current_dir(dir)
+        .exec()?;
+    let workspace = metadata.workspace_root.as_std_path().canonicalize()?;
+    Ok(workspace
+        .strip_prefix(&repo)
+        .with_context(|| format!("{:?} is not within {:?}", workspace, repo))?
+        .to_path_buf())
+}
+
+/// Metadata of a workspace, given by its path relative to the root of the git repository
+/// containing `dir`, or of the workspace containing `dir`.
+pub(crate) fn metadata(
+    dir: &Path,
+    workspace: Option<&Path>,
+) -> anyhow::Result<cargo_metadata::Metadata> {
+    let mut cmd = cargo_metadata::MetadataCommand::new();
+    cmd.no_deps();
+    match workspace {
+        Some(workspace) => cmd.current_dir(git_root(dir)?.join(workspace)),
+        None => cmd.current_dir(dir),
+    };
+    Ok(cmd.exec()?)
+}
+
+/// Local packages of a workspace, with their directories relative to the repository root, and
+/// the manifest of the workspace
+pub(crate) fn package_dirs(
+    metadata: &cargo_metadata::Metadata,
+) -> anyhow::Result<(Vec<(String, PathBuf)>, PathBuf)> {
+    let repo = git_root(metadata.workspace_root.as_std_path())?;
+    let relative = |path: &Path| -> anyhow::Result<PathBuf> {
+        let path = path.canonicalize()?;
+        Ok(path
+            .strip_prefix(&repo)
+            .with_context(|| format!("{:?} is not within {:?}", path, repo))?
+            .to_path_buf())
+    };
+    let packages = metadata
+        .workspace_packages()
+        .into_iter()
+        .map(|p| {
+            let dir = p.manifest_path.parent().unwrap_or(&metadata.workspace_root);
+            Ok((p.name.clone(), relative(dir.as_std_path())?))
+        })
+        .collect::<anyhow::Result<_>>()?;
+    let manifest = join(
+        &relative(metadata.workspace_root.as_std_path())?,
+        Path::new("Cargo.toml"),
+    );
+    Ok((packages, manifest))
+}
+
+/// Innermost package containing a path, given the packages and their directories (as returned
+/// by [`package_dirs`])
+pub(crate) fn package_of<'a>(packages: &'a [(String, PathBuf)], path: &str) -> Option<&'a str> {
+    packages
+        .iter()
+        .filter(|(_, dir)| Path::new(path).starts_with(dir))
+        .max_by_key(|(_, dir)| dir.components().count())
+        .map(|(name, _)| name.as_str())
+}
+
+/// Path of the source root of a test binary relative to the repository root, from its nextest
+/// binary id (`package`, `package::test`, `package::bin/name`, ...).
+pub(crate) fn target_path(
+    metadata: &cargo_metadata::Metadata,
+    workspace: &Path,
+    binary_id: &str,
+) -> Option<PathBuf> {
+    let (package, target) = match binary_id.split_once("::") {
+        Some((package, target)) => (package, Some(target)),
+        None => (binary_id, None),
+    };
+    let package = metadata
+        .workspace_packages()
+        .into_iter()
+        .find(|p| p.name == package)?;
+    let target = match target {
+        // Library unit tests
+        None => package.targets.iter().find(|t| {
+            t.kind
+                .iter()
+                .any(|k| k.ends_with("lib") || k == "proc-macro")
+        })?,
+        Some(target) => {
+            let (kind, name) = target.split_once('/').unwrap_or(("test", target));
+            package
+                .targets
+                .iter()
+                .find(|t| t.name == name && t.kind.iter().any(|k| k == kind))?
+        }
+    };
+    let path = target
+        .src_path
+        .strip_prefix(&metadata.workspace_root)
+        .ok()?;
+    Some(join(workspace, path.as_std_path()))
+}
+
+/// Directory of a local package, from its id (e.g. `path+file:///ws/foo#0.1.0`,
+/// `path+file:///C:/ws/foo#0.1.0` or `foo 0.1.0 (path+file:///ws/foo)`), normalized
+pub(crate) fn package_dir(id: &cargo_metadata::PackageId) -> Option<PathBuf> {
+    let (_, rest) = id.repr.split_once("path+file://")?;
+    let end = rest.find(['#', ')']).unwrap_or(rest.len());
+    Some(normalize(&rest[..end]))
+}
+
+/// Name of a package, from its id (e.g. `path+file:///ws/foo#0.1.0`,
+/// `path+file:///ws/crates/foo-core#foo@0.1.0` or `foo 0.1.0 (path+file:///ws/foo)`)
+pub(crate) fn package_name(id: &cargo_metadata::PackageId) -> String {
+    let repr = &id.repr;
+    if let Some((name, _)) = repr.split_once(' ') {
+        return name.into();
+    }
+    match repr.rsplit_once('#') {
+        Some((_, fragment)) if fragment.contains('@') => fragment.split('@').next().unwrap().into(),
+        // The name is the last path component when it matches the directory
+        Some((url, _)) => url.rsplit('/').next().unwrap_or(url).into(),
+        None => repr.clone(),
+    }
+}
+
+/// Workspace root at build time, given the directory of a package and a path relative to the
+/// workspace root pointing inside of it
+pub(crate) fn infer_root(package_dir: &Path, relative: &Path) -> Option<PathBuf> {
+    let ancestors: Vec<_> = package_dir.ancestors().collect();
+    ancestors.into_iter().rev().find_map(|root| {
+        let sub = package_dir.strip_prefix(root).ok()?;
+        relative.starts_with(sub).then(|| root.to_path_buf())
+    })
+}
+
+/// Deepest directory containing all the given paths
+pub(crate) fn common_ancestor<'a>(paths: impl IntoIterator<Item = &'a Path>) -> Option<PathBuf> {
+    let mut paths = paths.into_iter();
+    let mut ancestor = paths.next()?.to_path_buf();
+    for path in paths {
+        while !path.starts_with(&ancestor) {
+            if !ancestor.pop() {
+                return None;
+            }
+        }
+    }
+    Some(ancestor)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn resolver(workspace: &str, maps: &[&str]) -> PathResolver {
+        PathResolver {
+            workspace: workspace.into(),
+            maps: maps.iter().map(|m| m.parse().unwrap()).collect(),
+            cwd: PathBuf::new(),
+        }
+    }
+
+    #[test]
+    fn windows_paths() {
+        let resolver = resolver("crates", &[r"D:\vendor\patched=third-party/patched"]);
+        let resolve = |path: &str, root: Option<&str>| {
+            let path = resolver.resolve(Path::new(path), root.map(Path::new));
+            path.to_str().unwrap().to_string()
+        };
+        let root = Some(r"C:\agent\ws");
+        assert_eq!(resolve(r"foo\src\lib.rs", root), "crates/foo/src/lib.rs");
+        assert_eq!(
+            resolve(r".\foo\src/main.rs", None),
+            "crates/foo/src/main.rs"
+        );
+        assert_eq!(
+            resolve(r"C:\agent\ws\foo\src\lib.rs", root),
+            "crates/foo/src/lib.rs"
+        );
+        assert_eq!(
+            resolve(r"c:\agent\ws\foo\build.rs", root),
+            "crates/foo/build.rs"
+        );
+        assert_eq!(
+            resolve(r"\\?\C:\agent\ws\foo\src\lib.rs", root),
+            "crates/foo/src/lib.rs"
+        );
+        assert_eq!(
+            resolve("C:/agent/ws/foo/src/lib.rs", Some("c:/agent/ws/")),
+            "crates/foo/src/lib.rs"
+        );
+        assert_eq!(
+            resolve(r"D:\vendor\patched\src\lib.rs", root),
+            "third-party/patched/src/lib.rs"
+        );
+        assert_eq!(resolve(r"D:\other\lib.rs", root), "D:/other/lib.rs");
+        assert_eq!(
+            resolve("/ws/foo/src/lib.rs", Some("/ws")),
+            "crates/foo/src/lib.rs"
+        );
+
+        let external = |path: &str| resolver.is_external(Path::new(path), root.map(Path::new));
+        assert!(external(
+            r"C:\Users\ci\.cargo\registry\src\serde-1.0.0\src\lib.rs"
+        ));
+        assert!(external(r"C:\agent\ws\target\debug\build\foo\out\gen.rs"));
+        assert!(external(r"E:\rustc\library\core\src\option.rs"));
+        assert!(!external(r"C:\agent\ws\foo\src\lib.rs"));
+        assert!(!external(r"foo\src\lib.rs"));
+        assert!(!external(r"D:\vendor\patched\src\lib.rs"));
+    }
+
+    #[test]
+    fn windows_package_ids() {
+        let id = |repr: &str| cargo_metadata::PackageId { repr: repr.into() };
+        let dir = package_dir(&id("path+file:///c:/agent/ws/crates/foo#0.1.0")).unwrap();
+        assert_eq!(dir, Path::new("C:/agent/ws/crates/foo"));
+        let relative = normalize(r"crates\foo\src\lib.rs");
+        assert!(!is_absolute(&relative));
+        assert_eq!(
+            infer_root(&dir, &relative).unwrap(),
+            Path::new("C:/agent/ws")
+        );
+        assert!(is_absolute(&normalize(r"C:\agent")));
+        assert!(is_absolute(Path::new("/ws")));
+    }
+}
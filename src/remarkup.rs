@@ -0,0 +1,46 @@
+//! Formatting of the unit details in Remarkup, the Phabricator markup language
+//! See <https://secure.phabricator.com/book/phabricator/article/remarkup/>
+
+/// Fence delimiting code blocks
+const FENCE: &str = "```";
+/// Fence broken by a zero-width space, so that it does not end the block
+const ESCAPED_FENCE: &str = "``\u{200b}`";
+
+/// Wrap text (e.g. a failure output) in a code block
+pub(crate) fn code_block(text: &str) -> String {
+    format!(
+        "{}\n{}\n{}",
+        FENCE,
+        text.replace(FENCE, ESCAPED_FENCE),
+        FENCE
+    )
+}
+
+/// Inverse of [code_block], for the outputs that do not render Remarkup
+pub(crate) fn plain(text: &str) -> String {
+    text.lines()
+        .filter(|line| *line != FENCE)
+        .collect::<Vec<_>>()
+        .join("\n")
+        .replace(ESCAPED_FENCE, FENCE)
+}
+
+/// Split text at its first code block, into the text before it, the text of the block (as given
+/// to [code_block]) and the text after it
+pub(crate) fn split_code_block(text: &str) -> Option<(&str, String, &str)> {
+    let mut fences = vec![];
+    let mut offset = 0;
+    for line in text.split('\n') {
+        if line == FENCE {
+            fences.push(offset);
+        }
+        offset += line.len() + 1;
+    }
+    let (&open, &close) = (fences.first()?, fences.get(1)?);
+    let code = text.get(open + FENCE.len() + 1..close - 1)?;
+    Some((
+        &text[..open],
+        code.replace(ESCAPED_FENCE, FENCE),
+        &text[close + FENCE.len()..],
+    ))
+}
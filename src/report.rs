@@ -0,0 +1,253 @@
+//! Tool-agnostic representation of the results, filled by the parsers of the inputs and written
+//! out by the sinks of the output formats
+use std::borrow::Cow;
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use crate::redact::Redactor;
+use crate::{coverage, input, Auth, LintResult, Params, Status, UnitResult};
+
+/// Results of the parsing of inputs
+#[derive(Debug, Default)]
+pub(crate) struct Report {
+    pub units: Vec<UnitResult>,
+    pub lints: Vec<LintResult>,
+    /// Coverage of each file relative to the repository root, in the Harbormaster format: a
+    /// character per line, `N` (not executable), `C` (covered) or `U` (not covered)
+    pub coverage: BTreeMap<String, String>,
+    pub metadata: Metadata,
+}
+
+/// Information about the results, beyond the results themselves
+#[derive(Debug, Default)]
+pub(crate) struct Metadata {
+    /// An internal compiler error was detected
+    pub ice: bool,
+    /// Patch fixing the formatting violations
+    pub rustfmt_patch: Option<String>,
+    /// Number of inputs that could not be parsed, and were skipped
+    pub parse_failures: usize,
+    /// Package containing each covered file, for the coverage results of each package
+    pub packages: BTreeMap<String, String>,
+}
+
+impl Report {
+    /// Merge the results of another input into this report
+    pub fn extend(&mut self, other: Report) {
+        self.units.extend(other.units);
+        self.lints.extend(other.lints);
+        for (path, lines) in other.coverage {
+            coverage::add(&mut self.coverage, path, &lines);
+        }
+        self.metadata.packages.extend(other.metadata.packages);
+        self.metadata.ice |= other.metadata.ice;
+        self.metadata.parse_failures += other.metadata.parse_failures;
+        if let Some(patch) = other.metadata.rustfmt_patch {
+            self.metadata
+                .rustfmt_patch
+                .get_or_insert_with(String::new)
+                .push_str(&patch);
+        }
+    }
+    /// Remove the ANSI escape sequences from the texts
+    pub fn strip_ansi(&mut self) {
+        self.rewrite(input::strip_ansi);
+    }
+    /// Redact the secrets from the test details and lint descriptions
+    pub fn redact(&mut self, redactor: &Redactor) {
+        self.rewrite(|text| redactor.redact(text));
+    }
+    /// Rewrite the test details and lint descriptions
+    fn rewrite(&mut self, f: impl Fn(&str) -> Cow<str>) {
+        let rewrite = |text: &mut Option<String>| {
+            if let Some(Cow::Owned(rewritten)) = text.as_deref().map(&f) {
+                *text = Some(rewritten);
+            }
+        };
+        for unit in &mut self.units {
+            rewrite(&mut unit.details);
+        }
+        for lint in &mut self.lints {
+            rewrite(&mut lint.description);
+        }
+    }
+}
+
+/// Output format of a report
+pub(crate) trait Sink {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()>;
+}
+
+/// Parameters of harbormaster.sendmessage, as expected by `arc` and the Conduit API
+pub(crate) struct Harbormaster {
+    pub build: String,
+    /// Resolved status (not `auto`)
+    pub status: Status,
+    pub token: String,
+    pub compact: bool,
+    /// Include the API token in the written message
+    pub print_secrets: bool,
+}
+impl Harbormaster {
+    /// Message for a report. The coverage is attached to a unit result per package.
+    pub fn params(&self, report: &Report) -> Params {
+        let mut units = report.units.clone();
+        units.extend(coverage::units(&report.coverage, &report.metadata.packages));
+        Params {
+            build: self.build.clone(),
+            status: self.status,
+            unit: Some(units),
+            lint: Some(report.lints.clone()),
+            auth: Auth {
+                token: self.token.clone(),
+            },
+        }
+    }
+}
+impl Sink for Harbormaster {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        let mut params = serde_json::to_value(self.params(report))?;
+        if !self.print_secrets {
+            params.as_object_mut().unwrap().remove("__conduit__");
+        }
+        if self.compact {
+            serde_json::to_writer(out, &params)?;
+        } else {
+            serde_json::to_writer_pretty(out, &params)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use super::*;
+
+    pub(crate) fn lint(severity: &str, path: &str, line: Option<usize>) -> LintResult {
+        LintResult {
+            name: "cargo-clippy".into(),
+            code: "clippy::len_zero".into(),
+            severity: severity.into(),
+            path: path.into(),
+            line,
+            position: line.map(|_| 5),
+            description: Some("length comparison to zero".into()),
+        }
+    }
+
+    pub(crate) fn unit(name: &str, result: &str) -> UnitResult {
+        UnitResult {
+            name: name.into(),
+            result: result.into(),
+            namespace: Some("my-crate".into()),
+            engine: Some("cargo-nextest".into()),
+            duration_s: Some(0.5),
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn report() -> Report {
+        Report {
+            units: vec![unit("tests::passes", "pass"), unit("tests::fails", "fail")],
+            lints: vec![
+                lint("error", "src/lib.rs", Some(3)),
+                lint("advice", "src/main.rs", None),
+            ],
+            ..Default::default()
+        }
+    }
+
+    pub(crate) fn written(sink: &dyn Sink, report: &Report) -> String {
+        let mut out = vec![];
+        sink.write(report, &mut out).unwrap();
+        String::from_utf8(out).unwrap()
+    }
+
+    fn harbormaster() -> Harbormaster {
+        Harbormaster {
+            build: "PHID-HMBT-1".into(),
+            status: Status::Fail,
+            token: "api-token".into(),
+            compact: true,
+            print_secrets: false,
+        }
+    }
+
+    #[test]
+    fn extend_merges_the_fragments() {
+        let mut merged = report();
+        let mut other = report();
+        other.metadata.ice = true;
+        other.metadata.parse_failures = 1;
+        other.metadata.rustfmt_patch = Some("--- a/src/lib.rs\n".into());
+        other.coverage.insert("src/lib.rs".into(), "NCU".into());
+        merged.extend(other);
+        merged.extend(Report {
+            coverage: [("src/lib.rs".into(), "NUC".into())].into(),
+            metadata: Metadata {
+                ice: false,
+                rustfmt_patch: Some("--- a/src/main.rs\n".into()),
+                parse_failures: 2,
+                packages: [("src/lib.rs".into(), "foo".into())].into(),
+            },
+            ..Default::default()
+        });
+        assert_eq!(merged.units.len(), 4);
+        assert_eq!(merged.lints.len(), 4);
+        assert_eq!(merged.coverage["src/lib.rs"], "NCC");
+        assert_eq!(merged.metadata.packages["src/lib.rs"], "foo");
+        assert!(merged.metadata.ice);
+        assert_eq!(merged.metadata.parse_failures, 3);
+        assert_eq!(
+            merged.metadata.rustfmt_patch.as_deref(),
+            Some("--- a/src/lib.rs\n--- a/src/main.rs\n")
+        );
+    }
+
+    #[test]
+    fn harbormaster_message() {
+        let message: serde_json::Value =
+            serde_json::from_str(&written(&harbormaster(), &report())).unwrap();
+        assert_eq!(message["buildTargetPHID"], "PHID-HMBT-1");
+        assert_eq!(message["type"], "fail");
+        assert!(message.get("__conduit__").is_none());
+        assert_eq!(message["unit"].as_array().unwrap().len(), 2);
+        assert_eq!(message["lint"][0]["char"], 5);
+        assert!(message["lint"][1].get("line").is_none());
+        let printed = Harbormaster {
+            print_secrets: true,
+            ..harbormaster()
+        };
+        let message: serde_json::Value =
+            serde_json::from_str(&written(&printed, &report())).unwrap();
+        assert_eq!(message["__conduit__"]["token"], "api-token");
+    }
+
+    #[test]
+    fn harbormaster_coverage_unit() {
+        let mut report = report();
+        report.coverage.insert("src/lib.rs".into(), "NCCU".into());
+        let params = harbormaster().params(&report);
+        let units = params.unit.unwrap();
+        let coverage = units.last().unwrap();
+        assert_eq!(coverage.name, "coverage");
+        assert_eq!(coverage.coverage.as_ref().unwrap()["src/lib.rs"], "NCCU");
+        assert!(harbormaster()
+            .params(&Report::default())
+            .unit
+            .unwrap()
+            .is_empty());
+    }
+
+    #[test]
+    fn strip_ansi() {
+        let mut report = report();
+        report.units[1].details =
+            Some("\x1b[1;31merror\x1b[0m\x1b(B: \x1b]8;;https://x\x1b\\link\x1b]8;;\x07".into());
+        report.lints[0].description = Some("\x1b[2Kplain".into());
+        report.strip_ansi();
+        assert_eq!(report.units[1].details.as_deref(), Some("error: link"));
+        assert_eq!(report.units[0].details, None);
+        assert_eq!(report.lints[0].description.as_deref(), Some("plain"));
+    }
+}
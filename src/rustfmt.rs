@@ -0,0 +1,213 @@
+//! Formatting violations reported by `cargo fmt --check`, converted into lints and a patch
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::paths::PathResolver;
+use crate::{input, remarkup, LintResult};
+
+pub(crate) const CODE: &str = "rustfmt";
+
+/// Mismatch between a file and its formatted version
+struct Hunk {
+    path: String,
+    /// First line of the hunk in the original file
+    line: usize,
+    /// Lines prefixed with ' ', '-' or '+'
+    lines: Vec<String>,
+}
+impl Hunk {
+    fn count(&self, prefix: char) -> usize {
+        self.lines.iter().filter(|l| l.starts_with(prefix)).count()
+    }
+    /// Line of the first change, after the context
+    fn first_change(&self) -> usize {
+        self.line + self.lines.iter().take_while(|l| !is_change(l)).count()
+    }
+}
+
+fn is_change(line: &str) -> bool {
+    line.starts_with('-') || line.starts_with('+')
+}
+
+/// Parse the output of `cargo fmt --check`, with `Diff in {path}:{line}:` or
+/// `Diff in {path} at line {line}:` headers. The absolute paths are made relative to the directory
+/// it ran in.
+///
+/// Returns a lint for each hunk, and a unified diff of all the hunks, which can be applied with
+/// `git apply` from the repository root.
+pub(crate) fn parse(
+    path: &Path,
+    resolver: &PathResolver,
+) -> anyhow::Result<(Vec<LintResult>, String)> {
+    let header = regex::Regex::new(r"^Diff in (.+?)(?::| at line )(\d+):$")?;
+    let mut hunks: Vec<Hunk> = vec![];
+    let mut sources = vec![];
+    for line in input::read_text(path)?.lines() {
+        if let Some(c) = header.captures(line) {
+            sources.push(c[1].to_string());
+            hunks.push(Hunk {
+                path: resolver
+                    .resolve(Path::new(&c[1]), Some(&resolver.cwd))
+                    .to_string_lossy()
+                    .to_string(),
+                line: c[2].parse()?,
+                lines: vec![],
+            });
+        } else if let Some(hunk) = hunks.last_mut().filter(|_| !line.is_empty()) {
+            // Context lines start with a space, so that empty lines only separate the hunks
+            hunk.lines.push(line.into());
+        }
+    }
+    // rustfmt splits the files on newlines, adding an empty context line at the end of the files
+    let mut lengths = std::collections::HashMap::new();
+    for (hunk, source) in hunks.iter_mut().zip(&sources) {
+        let length = lengths.entry(source).or_insert_with(|| {
+            std::fs::read_to_string(source)
+                .ok()
+                .map(|data| data.lines().count())
+        });
+        let end = hunk.line + hunk.count(' ') + hunk.count('-') - 1;
+        if length.is_some_and(|length| end > length) && hunk.lines.last().is_some_and(|l| l == " ")
+        {
+            hunk.lines.pop();
+        }
+    }
+    let lints = hunks
+        .iter()
+        .map(|hunk| LintResult {
+            name: "rustfmt".into(),
+            code: CODE.into(),
+            severity: "autofix".into(),
+            path: hunk.path.clone(),
+            line: Some(hunk.first_change()),
+            position: None,
+            description: Some(format!(
+                "Not formatted with rustfmt:\n\n{}",
+                remarkup::code_block(&hunk.lines.join("\n"))
+            )),
+        })
+        .collect();
+    Ok((lints, patch(&hunks)))
+}
+
+/// Unified diff of the hunks, which rustfmt lists in order for each file
+fn patch(hunks: &[Hunk]) -> String {
+    let mut patch = String::new();
+    let mut current = None;
+    // Number of lines added by the previous hunks of the file
+    let mut offset = 0isize;
+    for hunk in hunks {
+        if current != Some(&hunk.path) {
+            current = Some(&hunk.path);
+            offset = 0;
+            writeln!(patch, "--- a/{0}\n+++ b/{0}", hunk.path).unwrap();
+        }
+        let old = hunk.count(' ') + hunk.count('-');
+        let new = hunk.count(' ') + hunk.count('+');
+        // Empty ranges start at the previous line
+        let start = |count: usize, line: isize| if count == 0 { line - 1 } else { line };
+        let line = hunk.line as isize;
+        writeln!(
+            patch,
+            "@@ -{},{} +{},{} @@",
+            start(old, line),
+            old,
+            start(new, line + offset),
+            new
+        )
+        .unwrap();
+        for line in &hunk.lines {
+            writeln!(patch, "{}", line).unwrap();
+        }
+        offset += new as isize - old as isize;
+    }
+    patch
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOURCE: &str = "fn main() {
+    let x   = 1;
+    println!(\"{}\", x);
+}
+
+fn a() {}
+fn b() {}
+fn c() {}
+fn d() {}
+fn e() {}
+fn f() {}
+fn g() {}
+
+fn other( ) {  }
+";
+
+    #[test]
+    fn check() {
+        let dir = std::env::temp_dir().join(format!("harbormaster-{}-rustfmt", std::process::id()));
+        std::fs::create_dir_all(dir.join("src")).unwrap();
+        std::fs::write(dir.join("src/lib.rs"), SOURCE).unwrap();
+        // The last hunk ends with the empty context line added by rustfmt
+        let output = format!(
+            "Diff in {0}/src/lib.rs:1:
+ fn main() {{
+-    let x   = 1;
++    let x = 1;
+     println!(\"{{}}\", x);
+ }}
+ 
+
+Diff in {0}/src/lib.rs at line 11:
+ fn f() {{}}
+ fn g() {{}}
+ 
+-fn other( ) {{  }}
++fn other() {{}}
+ 
+",
+            dir.display()
+        );
+        let resolver = PathResolver {
+            workspace: "".into(),
+            maps: vec![],
+            cwd: dir.clone(),
+        };
+        let path = crate::input::tests::fixture("rustfmt.txt", &output);
+        let (lints, patch) = parse(&path, &resolver).unwrap();
+        let lines: Vec<_> = lints.iter().map(|l| (l.path.as_str(), l.line)).collect();
+        assert_eq!(lines, [("src/lib.rs", Some(2)), ("src/lib.rs", Some(14))]);
+        assert!(lints[1]
+            .description
+            .as_deref()
+            .unwrap()
+            .contains("-fn other( ) {  }\n+fn other() {}"));
+        assert_eq!(
+            patch,
+            "--- a/src/lib.rs
++++ b/src/lib.rs
+@@ -1,5 +1,5 @@
+ fn main() {
+-    let x   = 1;
++    let x = 1;
+     println!(\"{}\", x);
+ }
+ 
+@@ -11,4 +11,4 @@
+ fn f() {}
+ fn g() {}
+ 
+-fn other( ) {  }
++fn other() {}
+"
+        );
+        std::fs::write(dir.join("rustfmt.patch"), &patch).unwrap();
+        let status = std::process::Command::new("git")
+            .args(["apply", "--check", "rustfmt.patch"])
+            .current_dir(&dir)
+            .status()
+            .unwrap();
+        assert!(status.success());
+    }
+}
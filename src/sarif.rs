@@ -0,0 +1,121 @@
+//! SARIF 2.1.0 output, e.g. for GitHub code scanning
+//! See <https://docs.oasis-open.org/sarif/sarif/v2.1.0/sarif-v2.1.0.html>
+use std::io::Write;
+
+use itertools::Itertools;
+use serde_json::json;
+
+use crate::report::{Report, Sink};
+use crate::LintResult;
+
+/// Sink writing the lints as a SARIF log, with a run per tool. The tests are not reported, as
+/// SARIF only describes static analysis results.
+pub(crate) struct Sarif;
+
+fn level(lint: &LintResult) -> &'static str {
+    match lint.severity.as_str() {
+        "error" => "error",
+        "warning" => "warning",
+        "disabled" => "none",
+        // advice and autofix
+        _ => "note",
+    }
+}
+
+fn result(lint: &LintResult) -> serde_json::Value {
+    let mut location = json!({ "artifactLocation": { "uri": lint.path } });
+    if let Some(line) = lint.line.filter(|l| *l > 0) {
+        location["region"] = json!({ "startLine": line });
+        if let Some(position) = lint.position.filter(|p| *p > 0) {
+            location["region"]["startColumn"] = position.into();
+        }
+    }
+    json!({
+        "ruleId": lint.code,
+        "level": level(lint),
+        "message": { "text": lint.description.as_deref().unwrap_or(&lint.code) },
+        "locations": [{ "physicalLocation": location }],
+    })
+}
+
+impl Sink for Sarif {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        let runs: Vec<_> = report
+            .lints
+            .iter()
+            .into_group_map_by(|l| l.name.as_str())
+            .into_iter()
+            .sorted_by_key(|(name, _)| *name)
+            .map(|(name, lints)| {
+                let rules: Vec<_> = lints
+                    .iter()
+                    .map(|l| l.code.as_str())
+                    .unique()
+                    .sorted()
+                    .map(|code| json!({ "id": code }))
+                    .collect();
+                json!({
+                    "tool": { "driver": { "name": name, "rules": rules } },
+                    "results": lints.into_iter().map(result).collect::<Vec<_>>(),
+                })
+            })
+            .collect();
+        serde_json::to_writer_pretty(
+            out,
+            &json!({
+                "$schema": "https://json.schemastore.org/sarif-2.1.0.json",
+                "version": "2.1.0",
+                "runs": runs,
+            }),
+        )?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{lint, report, written};
+
+    #[test]
+    fn runs_per_tool() {
+        let mut report = report();
+        report.lints.push(LintResult {
+            name: "shellcheck".into(),
+            code: "SC2086".into(),
+            ..lint("warning", "ci/build.sh", Some(7))
+        });
+        let log: serde_json::Value = serde_json::from_str(&written(&Sarif, &report)).unwrap();
+        assert_eq!(log["version"], "2.1.0");
+        let runs = log["runs"].as_array().unwrap();
+        assert_eq!(runs.len(), 2);
+        assert_eq!(runs[0]["tool"]["driver"]["name"], "cargo-clippy");
+        assert_eq!(
+            runs[0]["tool"]["driver"]["rules"],
+            json!([{ "id": "clippy::len_zero" }])
+        );
+        assert_eq!(runs[0]["results"].as_array().unwrap().len(), 2);
+        assert_eq!(runs[1]["results"][0]["ruleId"], "SC2086");
+    }
+
+    #[test]
+    fn levels_and_regions() {
+        let result = result(&lint("error", "src/lib.rs", Some(3)));
+        assert_eq!(result["level"], "error");
+        assert_eq!(result["message"]["text"], "length comparison to zero");
+        let location = &result["locations"][0]["physicalLocation"];
+        assert_eq!(location["artifactLocation"]["uri"], "src/lib.rs");
+        assert_eq!(
+            location["region"],
+            json!({ "startLine": 3, "startColumn": 5 })
+        );
+
+        let result = super::result(&lint("advice", "src/main.rs", None));
+        assert_eq!(result["level"], "note");
+        assert!(result["locations"][0]["physicalLocation"]
+            .get("region")
+            .is_none());
+        assert_eq!(level(&lint("autofix", "src/lib.rs", None)), "note");
+        assert_eq!(level(&lint("disabled", "src/lib.rs", None)), "none");
+    }
+}
@@ -0,0 +1,185 @@
+//! Validation of the results against the constraints of harbormaster.sendmessage, which silently
+//! drops invalid entries.
+use itertools::Itertools;
+
+use crate::{LintResult, Params, UnitResult};
+
+pub(crate) const RESULTS: &[&str] = &["pass", "fail", "skip", "broken", "unsound"];
+pub(crate) const SEVERITIES: &[&str] = &["advice", "autofix", "warning", "error", "disabled"];
+
+/// Maximum number of offending entries listed in the error
+const MAX_LISTED: usize = 20;
+
+/// Check that a field is non-empty and fits in the Harbormaster storage
+fn check_field(problems: &mut Vec<String>, field: &str, value: &str, max_len: usize) {
+    if value.is_empty() {
+        problems.push(format!("empty {}", field));
+    } else if value.len() > max_len {
+        problems.push(format!(
+            "{} longer than {} bytes ({})",
+            field,
+            max_len,
+            value.len()
+        ));
+    }
+}
+
+fn check_optional(problems: &mut Vec<String>, field: &str, value: Option<&str>, max_len: usize) {
+    if let Some(value) = value {
+        check_field(problems, field, value, max_len);
+    }
+}
+
+fn unit_problems(unit: &UnitResult) -> Vec<String> {
+    let mut problems = vec![];
+    check_field(&mut problems, "name", &unit.name, 255);
+    if !RESULTS.contains(&unit.result.as_str()) {
+        problems.push(format!(
+            "invalid result {:?} (expected one of {})",
+            unit.result,
+            RESULTS.join(", ")
+        ));
+    }
+    check_optional(&mut problems, "namespace", unit.namespace.as_deref(), 255);
+    check_optional(&mut problems, "engine", unit.engine.as_deref(), 255);
+    if unit.duration_s.is_some_and(|d| !d.is_finite() || d < 0.0) {
+        problems.push(format!("invalid duration {:?}", unit.duration_s.unwrap()));
+    }
+    problems
+}
+
+fn lint_problems(lint: &LintResult) -> Vec<String> {
+    let mut problems = vec![];
+    check_field(&mut problems, "name", &lint.name, 255);
+    check_field(&mut problems, "code", &lint.code, 128);
+    check_field(&mut problems, "path", &lint.path, 4096);
+    if !SEVERITIES.contains(&lint.severity.as_str()) {
+        problems.push(format!(
+            "invalid severity {:?} (expected one of {})",
+            lint.severity,
+            SEVERITIES.join(", ")
+        ));
+    }
+    problems
+}
+
+/// Check all the results, listing the offending entries in the error
+pub(crate) fn validate(params: &Params) -> anyhow::Result<()> {
+    let units = params.unit.iter().flatten().filter_map(|unit| {
+        let problems = unit_problems(unit);
+        (!problems.is_empty()).then(|| {
+            format!(
+                "test {}{}: {}",
+                unit.namespace
+                    .as_ref()
+                    .map(|n| format!("{} ", n))
+                    .unwrap_or_default(),
+                unit.name,
+                problems.join(", ")
+            )
+        })
+    });
+    let lints = params.lint.iter().flatten().filter_map(|lint| {
+        let problems = lint_problems(lint);
+        (!problems.is_empty()).then(|| {
+            format!(
+                "lint {} at {}:{}: {}",
+                lint.code,
+                lint.path,
+                lint.line.unwrap_or_default(),
+                problems.join(", ")
+            )
+        })
+    });
+    let invalid: Vec<_> = units.chain(lints).collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    let mut listed = invalid
+        .iter()
+        .take(MAX_LISTED)
+        .map(|line| format!("  {}", line))
+        .join("\n");
+    if invalid.len() > MAX_LISTED {
+        listed += &format!("\n  ... and {} more", invalid.len() - MAX_LISTED);
+    }
+    anyhow::bail!(
+        "{} results would be rejected by Harbormaster:\n{}",
+        invalid.len(),
+        listed
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{lint, unit};
+    use crate::{Auth, Status};
+
+    fn params(units: Vec<UnitResult>, lints: Vec<LintResult>) -> Params {
+        Params {
+            build: "PHID-HMBT-1".into(),
+            status: Status::Pass,
+            unit: Some(units),
+            lint: Some(lints),
+            auth: Auth {
+                token: "api-token".into(),
+            },
+        }
+    }
+
+    #[test]
+    fn accepts_valid_results() {
+        let units = RESULTS.iter().map(|result| unit("a", result)).collect();
+        let lints = SEVERITIES
+            .iter()
+            .map(|severity| lint(severity, "src/lib.rs", Some(1)))
+            .collect();
+        validate(&params(units, lints)).unwrap();
+        validate(&params(vec![], vec![])).unwrap();
+    }
+
+    #[test]
+    fn rejects_invalid_results() {
+        let mut long = unit(&"x".repeat(256), "pass");
+        long.namespace = None;
+        let mut slow = unit("slow", "pass");
+        slow.duration_s = Some(f32::NAN);
+        let mut code = lint("error", "src/lib.rs", Some(3));
+        code.code = "c".repeat(129);
+        let mut unnamed = lint("warning", "src/main.rs", None);
+        unnamed.name = String::new();
+        let params = params(
+            vec![unit("ok", "pass"), unit("a", "passed"), long, slow],
+            vec![
+                lint("error", "src/lib.rs", Some(1)),
+                lint("critical", "src/lib.rs", Some(2)),
+                code,
+                unnamed,
+            ],
+        );
+        let expected = format!(
+            "6 results would be rejected by Harbormaster:
+  test my-crate a: invalid result \"passed\" (expected one of pass, fail, skip, broken, unsound)
+  test {}: name longer than 255 bytes (256)
+  test my-crate slow: invalid duration NaN
+  lint clippy::len_zero at src/lib.rs:2: invalid severity \"critical\" (expected one of advice, autofix, warning, error, disabled)
+  lint {} at src/lib.rs:3: code longer than 128 bytes (129)
+  lint clippy::len_zero at src/main.rs:0: empty name",
+            "x".repeat(256),
+            "c".repeat(129)
+        );
+        assert_eq!(validate(&params).unwrap_err().to_string(), expected);
+    }
+
+    #[test]
+    fn lists_the_first_results() {
+        let units = (0..MAX_LISTED + 5)
+            .map(|i| unit(&i.to_string(), "unknown"))
+            .collect();
+        let error = validate(&params(units, vec![])).unwrap_err().to_string();
+        assert!(error.starts_with("25 results would be rejected"));
+        assert_eq!(error.lines().count(), MAX_LISTED + 2);
+        assert!(error.ends_with("\n  ... and 5 more"));
+    }
+}
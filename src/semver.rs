@@ -0,0 +1,144 @@
+//! Breaking changes found by `cargo semver-checks --output-format json`
+use std::collections::HashMap;
+use std::path::Path;
+
+use serde::Deserialize;
+
+use crate::paths::PathResolver;
+use crate::{input, LintResult};
+
+#[derive(Deserialize)]
+struct Report {
+    #[serde(default)]
+    crates: Vec<Crate>,
+}
+#[derive(Deserialize)]
+struct Crate {
+    name: String,
+    #[serde(default)]
+    lints: Vec<Lint>,
+}
+#[derive(Deserialize)]
+struct Lint {
+    id: String,
+    #[serde(default)]
+    human_readable_name: Option<String>,
+    #[serde(default)]
+    description: Option<String>,
+    /// `major` or `minor`
+    #[serde(default)]
+    required_update: Option<String>,
+    #[serde(default)]
+    reference_link: Option<String>,
+    /// Outputs of the lint query, e.g. `name`, `path`, `span_filename` and `span_begin_line`
+    #[serde(default)]
+    results: Vec<HashMap<String, serde_json::Value>>,
+}
+
+/// Item reported by a finding, e.g. `my_crate::module::function`
+fn item(result: &HashMap<String, serde_json::Value>) -> Option<String> {
+    match result.get("path") {
+        Some(serde_json::Value::Array(path)) => Some(
+            path.iter()
+                .filter_map(|p| p.as_str())
+                .collect::<Vec<_>>()
+                .join("::"),
+        ),
+        _ => result.get("name")?.as_str().map(String::from),
+    }
+}
+
+/// Parse the report, with a lint per finding at the span of the item. Findings requiring a major
+/// version are errors, and the other ones warnings. Findings without a span (e.g. for crate-level
+/// changes) are anchored at the `Cargo.toml` of the workspace.
+pub(crate) fn parse(path: &Path, resolver: &PathResolver) -> anyhow::Result<Vec<LintResult>> {
+    let report: Report = serde_json::from_str(&input::read_to_string(path)?)?;
+    let mut lints = vec![];
+    for krate in report.crates {
+        for lint in krate.lints {
+            let severity = match lint.required_update.as_deref() {
+                Some("minor") => "warning",
+                _ => "error",
+            };
+            let title = lint.human_readable_name.as_deref().unwrap_or(&lint.id);
+            for result in &lint.results {
+                let file = result
+                    .get("span_filename")
+                    .and_then(|f| f.as_str())
+                    .unwrap_or("Cargo.toml");
+                let mut description = format!("{} in {}", title, krate.name);
+                if let Some(item) = item(result) {
+                    description += &format!(": {}", item);
+                }
+                if let Some(text) = &lint.description {
+                    description += &format!("\n\n{}", text);
+                }
+                if let Some(link) = &lint.reference_link {
+                    description += &format!("\n\nSee {}", link);
+                }
+                lints.push(LintResult {
+                    name: "cargo-semver-checks".into(),
+                    code: lint.id.clone(),
+                    severity: severity.into(),
+                    path: resolver
+                        .resolve(Path::new(file), None)
+                        .to_string_lossy()
+                        .to_string(),
+                    line: result
+                        .get("span_begin_line")
+                        .and_then(|l| l.as_u64())
+                        .map(|l| l as usize),
+                    position: None,
+                    description: Some(description),
+                });
+            }
+        }
+    }
+    Ok(lints)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    #[test]
+    fn findings_at_the_items() {
+        let json = r#"{"crates": [{"name": "my-crate", "lints": [
+            {"id": "function_missing", "human_readable_name": "pub fn removed or renamed",
+             "description": "A publicly-visible function cannot be imported anymore.",
+             "required_update": "major", "reference_link": "https://doc.rust-lang.org/cargo/",
+             "results": [{"name": "parse", "path": ["my_crate", "parse"],
+                          "span_filename": "src/lib.rs", "span_begin_line": 42}]},
+            {"id": "trait_method_added", "required_update": "minor",
+             "results": [{"name": "Sink"}]}
+        ]}]}"#;
+        let resolver = PathResolver {
+            workspace: "crates/my-crate".into(),
+            maps: vec![],
+            cwd: "/ws".into(),
+        };
+        let lints = parse(&fixture("semver.json", json), &resolver).unwrap();
+        assert_eq!(lints.len(), 2);
+        assert_eq!(lints[0].code, "function_missing");
+        assert_eq!(lints[0].severity, "error");
+        assert_eq!(lints[0].path, "crates/my-crate/src/lib.rs");
+        assert_eq!(lints[0].line, Some(42));
+        assert_eq!(
+            lints[0].description.as_deref(),
+            Some(
+                "pub fn removed or renamed in my-crate: my_crate::parse\n\n\
+                 A publicly-visible function cannot be imported anymore.\n\n\
+                 See https://doc.rust-lang.org/cargo/"
+            )
+        );
+        assert_eq!(lints[1].code, "trait_method_added");
+        assert_eq!(lints[1].severity, "warning");
+        assert_eq!(lints[1].path, "crates/my-crate/Cargo.toml");
+        assert_eq!(lints[1].line, None);
+        assert_eq!(
+            lints[1].description.as_deref(),
+            Some("trait_method_added in my-crate: Sink")
+        );
+    }
+}
@@ -0,0 +1,610 @@
+//! Server mode: endpoint for Harbormaster "Make HTTP Request" build steps, which checks out the
+//! commit, runs the configured commands and reports the results with harbormaster.sendmessage.
+use std::collections::HashMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use hyper::body::HttpBody;
+use hyper::{Body, Request, Response, StatusCode};
+use log::*;
+use serde::Deserialize;
+
+use crate::{
+    budget, config, remarkup, report, submit, verify, Auth, Flags, Params, Status, UnitResult,
+};
+
+#[derive(clap::Args, Clone)]
+pub(crate) struct ServeArgs {
+    /// Address to listen on
+    #[clap(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+    /// Shared secret authenticating the requests, as the key of an HMAC signature (or as the
+    /// password of the HTTP basic authentication with --allow-basic-auth)
+    #[clap(long, env = "HARBORMASTER_SECRET", hide_env_values = true)]
+    secret: Option<String>,
+    /// Also accept the secret as the password of the HTTP basic authentication, e.g. from a
+    /// Harbormaster credential. Such requests are not protected against replays.
+    #[clap(long, requires = "secret")]
+    allow_basic_auth: bool,
+    /// Maximum age of signed requests, in seconds, to limit replays
+    #[clap(long, default_value_t = 300)]
+    max_skew: u64,
+}
+
+/// `[serve]` section of the configuration file
+#[derive(Debug, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub(crate) struct ServeConfig {
+    /// Git checkout in which the builds run. The inputs are relative to it.
+    repository: PathBuf,
+    /// Remote to fetch the commits from
+    #[serde(default = "default_remote")]
+    remote: String,
+    /// Shell commands producing the inputs, run in order
+    #[serde(default)]
+    commands: Vec<String>,
+    /// Attach the fixes suggested by `cargo clippy --fix`, computed in a scratch worktree, as a
+    /// patch artifact
+    #[serde(default)]
+    clippy_fix: bool,
+}
+fn default_remote() -> String {
+    "origin".into()
+}
+
+/// Build requested by Harbormaster, e.g. with the URI
+/// `http://ci:8080/?target=${target.phid}&commit=${buildable.commit}`
+#[derive(Debug)]
+struct Build {
+    target: String,
+    commit: Option<String>,
+    /// Ref to fetch, e.g. `${repository.staging.ref}` for revisions
+    git_ref: Option<String>,
+}
+impl Build {
+    fn from_query(query: Option<&str>) -> anyhow::Result<Self> {
+        let query: HashMap<String, String> = query
+            .unwrap_or_default()
+            .split('&')
+            .filter_map(|pair| pair.split_once('='))
+            .map(|(k, v)| Ok((decode(k)?, decode(v)?)))
+            .collect::<anyhow::Result<_>>()?;
+        let target = query.get("target").context("Missing target parameter")?;
+        anyhow::ensure!(
+            target.starts_with("PHID-HMBT-"),
+            "{} is not a build target PHID",
+            target
+        );
+        let commit = query.get("commit").filter(|c| !c.is_empty());
+        if let Some(commit) = commit {
+            anyhow::ensure!(is_commit(commit), "{:?} is not a commit hash", commit);
+        }
+        let git_ref = query.get("ref").filter(|r| !r.is_empty());
+        if let Some(git_ref) = git_ref {
+            anyhow::ensure!(is_ref(git_ref), "{:?} is not a valid ref", git_ref);
+        }
+        Ok(Self {
+            target: target.clone(),
+            commit: commit.cloned(),
+            git_ref: git_ref.cloned(),
+        })
+    }
+}
+
+/// Abbreviated or full commit hash
+fn is_commit(commit: &str) -> bool {
+    (4..=64).contains(&commit.len()) && commit.chars().all(|c| c.is_ascii_hexdigit())
+}
+
+/// Ref name (e.g. `refs/tags/phabricator/diff/123`) that git cannot read as an option
+fn is_ref(git_ref: &str) -> bool {
+    !git_ref.starts_with('-')
+        && !git_ref.contains("..")
+        && git_ref
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || matches!(c, '.' | '_' | '/' | '-'))
+}
+
+/// Percent-decoding of a query component
+fn decode(text: &str) -> anyhow::Result<String> {
+    let bytes = text.as_bytes();
+    let mut out = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'%' => {
+                let hex = text.get(i + 1..i + 3).context("Invalid percent-encoding")?;
+                out.push(u8::from_str_radix(hex, 16)?);
+                i += 3;
+            }
+            b'+' => {
+                out.push(b' ');
+                i += 1;
+            }
+            b => {
+                out.push(b);
+                i += 1;
+            }
+        }
+    }
+    Ok(String::from_utf8(out)?)
+}
+
+fn git(repository: &Path, args: &[&str]) -> anyhow::Result<String> {
+    let output = Command::new("git")
+        .arg("-C")
+        .arg(repository)
+        .args(args)
+        .output()
+        .context("Failed to run git")?;
+    anyhow::ensure!(
+        output.status.success(),
+        "git {} failed: {}",
+        args.join(" "),
+        String::from_utf8_lossy(&output.stderr).trim()
+    );
+    Ok(String::from_utf8_lossy(&output.stdout).into())
+}
+
+/// Check out the commit (or ref) to build in the repository. The commit and ref of the request
+/// are passed after `--end-of-options`, in addition to their validation.
+fn checkout(config: &ServeConfig, build: &Build) -> anyhow::Result<()> {
+    if let Some(rev) = build.git_ref.as_ref().or(build.commit.as_ref()) {
+        git(
+            &config.repository,
+            &["fetch", "--end-of-options", &config.remote, rev],
+        )?;
+    }
+    let rev = match (&build.commit, &build.git_ref) {
+        (Some(commit), _) => commit.as_str(),
+        (None, Some(_)) => "FETCH_HEAD",
+        (None, None) => return Ok(()),
+    };
+    // `checkout --detach` does not take `--end-of-options`
+    let commit = git(
+        &config.repository,
+        &[
+            "rev-parse",
+            "--verify",
+            "--end-of-options",
+            &format!("{}^{{commit}}", rev),
+        ],
+    )?;
+    git(
+        &config.repository,
+        &["checkout", "--force", "--detach", commit.trim(), "--"],
+    )?;
+    git(&config.repository, &["clean", "-fd"])?;
+    Ok(())
+}
+
+/// Diff of the fixes applied by `cargo clippy --fix` to the checked out commit, if any. The fixes
+/// are applied in a scratch worktree, sharing the target directory of the repository.
+fn clippy_fix(config: &ServeConfig) -> anyhow::Result<Option<String>> {
+    let scratch = std::env::temp_dir().join(format!("harbormaster-fix-{}", std::process::id()));
+    let scratch_str = scratch.to_string_lossy();
+    git(
+        &config.repository,
+        &[
+            "worktree",
+            "add",
+            "--force",
+            "--detach",
+            &scratch_str,
+            "HEAD",
+        ],
+    )?;
+    let diff = (|| {
+        info!("Running cargo clippy --fix");
+        let mut cmd = Command::new("cargo");
+        cmd.args([
+            "clippy",
+            "--fix",
+            "--allow-dirty",
+            "--workspace",
+            "--all-targets",
+        ])
+        .current_dir(&scratch);
+        if std::env::var_os("CARGO_TARGET_DIR").is_none() {
+            cmd.env("CARGO_TARGET_DIR", config.repository.join("target"));
+        }
+        let output = cmd.output().context("Failed to run cargo clippy --fix")?;
+        anyhow::ensure!(
+            output.status.success(),
+            "cargo clippy --fix failed: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+        git(&scratch, &["diff"])
+    })();
+    if let Err(e) = git(
+        &config.repository,
+        &["worktree", "remove", "--force", &scratch_str],
+    ) {
+        warn!("Failed to remove the scratch worktree: {:#}", e);
+    }
+    Ok(Some(diff?).filter(|d| !d.is_empty()))
+}
+
+/// Run a configured command, as a unit result
+fn run_command(config: &ServeConfig, build: &Build, command: &str) -> anyhow::Result<UnitResult> {
+    info!("Running {}", command);
+    let start = Instant::now();
+    let output = Command::new("sh")
+        .arg("-c")
+        .arg(command)
+        .current_dir(&config.repository)
+        .env("HARBORMASTER_TARGET", &build.target)
+        .env(
+            "HARBORMASTER_COMMIT",
+            build.commit.as_deref().unwrap_or_default(),
+        )
+        .output()
+        .with_context(|| format!("Failed to run {}", command))?;
+    let success = output.status.success();
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    Ok(UnitResult {
+        name: command.into(),
+        result: if success { "pass" } else { "fail" }.into(),
+        namespace: Some("cargo-harbormaster".into()),
+        engine: Some("cargo-harbormaster".into()),
+        duration_s: Some(start.elapsed().as_secs_f32()),
+        details: (!success && !stderr.trim().is_empty())
+            .then(|| remarkup::code_block(stderr.trim())),
+        format: (!success).then(|| "remarkup".into()),
+        ..Default::default()
+    })
+}
+
+/// Run a build and report its results. Failures to check out or run the commands are reported as
+/// a broken result.
+async fn run_build(
+    args: &Flags,
+    config: &config::Config,
+    serve: &ServeConfig,
+    client: &crate::conduit::Client,
+    build: &Build,
+) -> anyhow::Result<()> {
+    info!("Building {:?}", build);
+    let mut units = vec![];
+    let mut omitted = vec![];
+    let mut patch = None;
+    let mut rustfmt_patch = None;
+    let prepared = checkout(serve, build).and_then(|_| {
+        for command in &serve.commands {
+            units.push(run_command(serve, build, command)?);
+        }
+        let mut parsed = crate::parse_all(args, config, &serve.repository)?;
+        // Before --max-lints, which omits lints
+        parsed.lints.extend(budget::check(
+            &serve.repository,
+            &args.max_warnings,
+            &parsed.lints,
+        )?);
+        Ok(parsed)
+    });
+    let mut params = match prepared {
+        Ok(mut parsed) => {
+            if serve.clippy_fix {
+                patch = clippy_fix(serve).unwrap_or_else(|e| {
+                    warn!("Failed to compute the clippy fixes: {:#}", e);
+                    None
+                });
+            }
+            parsed.units.extend(units);
+            omitted = crate::apply_limits(args, &mut parsed.lints, &mut parsed.units);
+            rustfmt_patch = parsed.metadata.rustfmt_patch.take();
+            report::Harbormaster {
+                build: build.target.clone(),
+                status: crate::resolve_status(
+                    args.status.unwrap_or(Status::Auto),
+                    &parsed.lints,
+                    &parsed.units,
+                    parsed.metadata.ice,
+                ),
+                token: args.token.clone().unwrap_or_default(),
+                compact: args.compact,
+                print_secrets: false,
+            }
+            .params(&parsed)
+        }
+        Err(e) => {
+            error!("Build of {} failed: {:#}", build.target, e);
+            units.push(UnitResult {
+                name: "build".into(),
+                result: "broken".into(),
+                namespace: Some("cargo-harbormaster".into()),
+                engine: Some("cargo-harbormaster".into()),
+                details: Some(format!("{:#}", e)),
+                ..Default::default()
+            });
+            Params {
+                build: build.target.clone(),
+                status: Status::Fail,
+                unit: Some(units),
+                lint: None,
+                auth: Auth {
+                    token: args.token.clone().unwrap_or_default(),
+                },
+            }
+        }
+    };
+    crate::schema::validate(&params)?;
+    if let Some(patch) = &rustfmt_patch {
+        submit::attach_rustfmt_patch(client, &mut params, patch).await?;
+    }
+    submit::send(client, &params, args.chunk_size, args.concurrency).await?;
+    info!("Sent {:?} to {}", params.status, params.build);
+    if let Some(patch) = patch {
+        client
+            .upload_artifact(
+                &params.build,
+                "clippy-fix",
+                "clippy-fix.patch",
+                patch.as_bytes(),
+            )
+            .await?;
+    }
+    if !omitted.is_empty() {
+        submit::attach_lints(client, &params, &omitted, "lints").await?;
+    }
+    Ok(())
+}
+
+/// Run the builds one at a time, in a dedicated thread, as they block
+fn worker(
+    args: &Flags,
+    config: &config::Config,
+    uri: &str,
+    jobs: std::sync::mpsc::Receiver<Build>,
+) -> anyhow::Result<()> {
+    let serve = config
+        .serve
+        .as_ref()
+        .context("A [serve] section is required in the configuration file")?;
+    let client = crate::client(args, config, uri, args.token.as_deref().unwrap_or_default())?;
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()?;
+    for build in jobs {
+        if let Err(e) = runtime.block_on(run_build(args, config, serve, &client, &build)) {
+            error!("Failed to report the build of {}: {:#}", build.target, e);
+        }
+    }
+    Ok(())
+}
+
+/// Maximum size of the request bodies, which are read before their authentication
+const MAX_BODY: usize = 1 << 20;
+
+/// Body of a request, or `None` if it is larger than `max` bytes
+async fn read_body(mut body: Body, max: usize) -> Result<Option<Vec<u8>>, hyper::Error> {
+    let mut data = vec![];
+    while let Some(chunk) = body.data().await {
+        let chunk = chunk?;
+        if data.len() + chunk.len() > max {
+            return Ok(None);
+        }
+        data.extend_from_slice(&chunk);
+    }
+    Ok(Some(data))
+}
+
+async fn handle(
+    request: Request<Body>,
+    args: std::sync::Arc<ServeArgs>,
+    jobs: std::sync::mpsc::Sender<Build>,
+) -> Result<Response<Body>, hyper::Error> {
+    let (parts, body) = request.into_parts();
+    let uri = parts
+        .uri
+        .path_and_query()
+        .map_or("/", |p| p.as_str())
+        .to_string();
+    let length = parts
+        .headers
+        .get(hyper::header::CONTENT_LENGTH)
+        .and_then(|l| l.to_str().ok()?.parse::<u64>().ok());
+    let body = match length {
+        Some(length) if length > MAX_BODY as u64 => None,
+        _ => read_body(body, MAX_BODY).await?,
+    };
+    let Some(body) = body else {
+        warn!(
+            "Rejected request {}: body larger than {} bytes",
+            uri, MAX_BODY
+        );
+        return Ok(Response::builder()
+            .status(StatusCode::PAYLOAD_TOO_LARGE)
+            .body(Body::from("Payload Too Large\n"))
+            .unwrap());
+    };
+    if let Some(secret) = &args.secret {
+        let max_skew = Duration::from_secs(args.max_skew);
+        if let Err(e) = verify::verify(
+            secret,
+            max_skew,
+            args.allow_basic_auth,
+            &parts.method,
+            &uri,
+            &parts.headers,
+            &body,
+        ) {
+            warn!("Rejected request {}: {:#}", uri, e);
+            return Ok(Response::builder()
+                .status(StatusCode::UNAUTHORIZED)
+                .body(Body::from("Unauthorized\n"))
+                .unwrap());
+        }
+    }
+    let (status, body) = match Build::from_query(parts.uri.query()) {
+        Ok(build) => {
+            info!("Queueing the build of {}", build.target);
+            match jobs.send(build) {
+                Ok(()) => (StatusCode::ACCEPTED, "Queued\n".to_string()),
+                Err(_) => (StatusCode::SERVICE_UNAVAILABLE, "Worker stopped\n".into()),
+            }
+        }
+        Err(e) => {
+            warn!("Invalid request {}: {:#}", uri, e);
+            (StatusCode::BAD_REQUEST, format!("{:#}\n", e))
+        }
+    };
+    Ok(Response::builder()
+        .status(status)
+        .body(Body::from(body))
+        .unwrap())
+}
+
+pub(crate) fn serve(
+    args: &Flags,
+    serve_args: &ServeArgs,
+    config: &config::Config,
+) -> anyhow::Result<()> {
+    let uri = args
+        .conduit_uri
+        .as_deref()
+        .context("--conduit-uri is required")?;
+    anyhow::ensure!(args.token.is_some(), "--token is required");
+    anyhow::ensure!(
+        config.serve.is_some(),
+        "A [serve] section is required in the configuration file"
+    );
+    let (tx, rx) = std::sync::mpsc::channel();
+    std::thread::scope(|s| {
+        let worker = s.spawn(|| worker(args, config, uri, rx));
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+        runtime.block_on(async {
+            let shared = std::sync::Arc::new(serve_args.clone());
+            let make_service = hyper::service::make_service_fn(move |_| {
+                let tx = tx.clone();
+                let shared = shared.clone();
+                async move {
+                    Ok::<_, hyper::Error>(hyper::service::service_fn(move |request| {
+                        handle(request, shared.clone(), tx.clone())
+                    }))
+                }
+            });
+            let server = hyper::Server::try_bind(&serve_args.listen)?.serve(make_service);
+            info!("Listening on {}", serve_args.listen);
+            if serve_args.secret.is_none() && !serve_args.listen.ip().is_loopback() {
+                warn!("Accepting unauthenticated builds, see --secret");
+            }
+            server
+                .with_graceful_shutdown(async {
+                    let _ = tokio::signal::ctrl_c().await;
+                })
+                .await?;
+            anyhow::Ok(())
+        })?;
+        info!("Waiting for the queued builds");
+        worker.join().unwrap()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args() -> std::sync::Arc<ServeArgs> {
+        std::sync::Arc::new(ServeArgs {
+            listen: "127.0.0.1:0".parse().unwrap(),
+            secret: None,
+            allow_basic_auth: false,
+            max_skew: 300,
+        })
+    }
+
+    async fn request(uri: &str, jobs: std::sync::mpsc::Sender<Build>) -> (StatusCode, String) {
+        let request = Request::post(uri).body(Body::empty()).unwrap();
+        let response = handle(request, args(), jobs).await.unwrap();
+        let status = response.status();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn build_requests() {
+        let build = Build::from_query(Some(
+            "target=PHID-HMBT-abc&commit=1f2e3d4c&ref=refs%2Ftags%2Fphabricator%2Fdiff%2F12&x=y",
+        ))
+        .unwrap();
+        assert_eq!(build.target, "PHID-HMBT-abc");
+        assert_eq!(build.commit.as_deref(), Some("1f2e3d4c"));
+        assert_eq!(
+            build.git_ref.as_deref(),
+            Some("refs/tags/phabricator/diff/12")
+        );
+        let build = Build::from_query(Some("target=PHID-HMBT-abc&commit=&ref=")).unwrap();
+        assert!(build.commit.is_none() && build.git_ref.is_none());
+        assert!(Build::from_query(None).is_err());
+        assert!(Build::from_query(Some("target=PHID-DREV-abc")).is_err());
+        assert!(Build::from_query(Some("target=PHID-HMBT-%zz")).is_err());
+        assert_eq!(decode("a+b%2Fc%C3%A9").unwrap(), "a b/cé");
+    }
+
+    #[test]
+    fn malicious_refs() {
+        for query in [
+            "commit=--upload-pack%3Dtouch%20%2Ftmp%2Fpwned",
+            "commit=HEAD",
+            "commit=1f2e3d4c%20--",
+            "ref=--upload-pack%3Dtouch%20%2Ftmp%2Fpwned",
+            "ref=-c",
+            "ref=refs%2Fheads%2F..%2F..%2Fconfig",
+            "ref=main%3Bid",
+            "ref=main%20--exec%3Did",
+        ] {
+            let query = format!("target=PHID-HMBT-abc&{}", query);
+            assert!(Build::from_query(Some(&query)).is_err(), "{}", query);
+        }
+        assert!(is_ref("feature/my-branch_1.2"));
+        assert!(is_commit(&"a".repeat(40)));
+    }
+
+    #[tokio::test]
+    async fn queue() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (status, _) = request("/?target=PHID-HMBT-1&commit=abcd", tx.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, _) = request("/?target=PHID-HMBT-2", tx.clone()).await;
+        assert_eq!(status, StatusCode::ACCEPTED);
+        let (status, body) = request("/?target=PHID-HMBT-3&ref=--help", tx.clone()).await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.contains("is not a valid ref"));
+        let queued: Vec<_> = rx.try_iter().map(|b| b.target).collect();
+        assert_eq!(queued, ["PHID-HMBT-1", "PHID-HMBT-2"]);
+
+        drop(rx);
+        let (status, _) = request("/?target=PHID-HMBT-4", tx).await;
+        assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    }
+
+    #[tokio::test]
+    async fn large_bodies() {
+        let (tx, rx) = std::sync::mpsc::channel();
+        let uri = "/?target=PHID-HMBT-1";
+        let send = |request: Request<Body>| handle(request, args(), tx.clone());
+        let large = Request::post(uri)
+            .body(Body::from(vec![b'a'; MAX_BODY + 1]))
+            .unwrap();
+        let response = send(large).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        // Rejected from the announced length, without reading the body
+        let announced = Request::post(uri)
+            .header(hyper::header::CONTENT_LENGTH, "1000000000")
+            .body(Body::empty())
+            .unwrap();
+        let response = send(announced).await.unwrap();
+        assert_eq!(response.status(), StatusCode::PAYLOAD_TOO_LARGE);
+        assert!(rx.try_recv().is_err());
+
+        let small = Request::post(uri).body(Body::from("{}")).unwrap();
+        assert_eq!(send(small).await.unwrap().status(), StatusCode::ACCEPTED);
+        assert_eq!(rx.try_recv().unwrap().target, "PHID-HMBT-1");
+    }
+}
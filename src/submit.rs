@@ -0,0 +1,567 @@
+//! Submission of the results to harbormaster.sendmessage, possibly split in several messages
+use std::collections::HashSet;
+use std::fs::File;
+use std::future::Future;
+use std::io::Write;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+use futures::{StreamExt, TryStreamExt};
+use log::*;
+use serde::{Deserialize, Serialize};
+use sha2::Digest;
+use tracing::Instrument;
+
+use crate::conduit::Client;
+use crate::{Auth, LintResult, Params, Status, UnitResult};
+
+#[derive(Serialize)]
+struct Chunk<'a> {
+    #[serde(rename = "buildTargetPHID")]
+    build: &'a str,
+    #[serde(rename = "type")]
+    status: Status,
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    unit: &'a [UnitResult],
+    #[serde(skip_serializing_if = "<[_]>::is_empty")]
+    lint: &'a [LintResult],
+}
+
+/// Send the message. If it has more than `chunk_size` results, these are sent in `work`
+/// messages, with at most `concurrency` simultaneous requests, followed by the final status.
+pub(crate) async fn send(
+    client: &Client,
+    params: &Params,
+    chunk_size: Option<usize>,
+    concurrency: usize,
+) -> anyhow::Result<()> {
+    send_tracked(
+        client,
+        params,
+        chunk_size,
+        concurrency,
+        &Progress::default(),
+    )
+    .await
+}
+
+/// [`send`], recording the results acknowledged by the server in `progress`
+pub(crate) async fn send_tracked(
+    client: &Client,
+    params: &Params,
+    chunk_size: Option<usize>,
+    concurrency: usize,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    let units = params.unit.as_deref().unwrap_or_default();
+    let lints = params.lint.as_deref().unwrap_or_default();
+    send_chunks(client, params, chunk_size, concurrency, progress)
+        .instrument(tracing::info_span!(
+            "submit",
+            build = params.build,
+            units = units.len(),
+            lints = lints.len()
+        ))
+        .await
+}
+
+async fn send_chunks(
+    client: &Client,
+    params: &Params,
+    chunk_size: Option<usize>,
+    concurrency: usize,
+    progress: &Progress,
+) -> anyhow::Result<()> {
+    // Fail early on servers not supporting the requests of the client
+    client.capabilities().await?;
+    *progress.params.lock().unwrap() = Some(params.clone());
+    let units = params.unit.as_deref().unwrap_or_default();
+    let lints = params.lint.as_deref().unwrap_or_default();
+    let Some(chunk_size) = chunk_size.filter(|c| units.len() + lints.len() > *c) else {
+        client
+            .call::<serde_json::Value>("harbormaster.sendmessage", params)
+            .await?;
+        progress.ack(0..units.len(), 0..lints.len());
+        progress.done.store(true, Ordering::SeqCst);
+        return Ok(());
+    };
+    let chunk = |unit, lint| Chunk {
+        build: &params.build,
+        status: Status::Work,
+        unit,
+        lint,
+    };
+    let ranges = |len: usize| {
+        (0..len)
+            .step_by(chunk_size)
+            .map(move |start| start..(start + chunk_size).min(len))
+    };
+    let chunks: Vec<_> = ranges(units.len())
+        .map(|u| (u, 0..0))
+        .chain(ranges(lints.len()).map(|l| (0..0, l)))
+        .collect();
+    let journal = match &progress.journal {
+        Some(dir) => Some(Journal::open(dir, params, chunk_size)?),
+        None => None,
+    };
+    let acked = journal
+        .as_ref()
+        .map(|j| j.acked.clone())
+        .unwrap_or_default();
+    for &i in &acked {
+        if let Some((u, l)) = chunks.get(i) {
+            progress.ack(u.clone(), l.clone());
+        }
+    }
+    info!(
+        "Sending {} results in {} chunks",
+        units.len() + lints.len(),
+        chunks.len()
+    );
+    if !acked.is_empty() {
+        info!(
+            "Resuming the submission, {} chunks were already acknowledged",
+            acked.len()
+        );
+    }
+    let journal = &journal;
+    futures::stream::iter(chunks.iter().enumerate())
+        .filter(|(i, _)| futures::future::ready(!acked.contains(i)))
+        .map(|(i, (u, l))| {
+            async move {
+                client
+                    .call::<serde_json::Value>(
+                        "harbormaster.sendmessage",
+                        &chunk(&units[u.clone()], &lints[l.clone()]),
+                    )
+                    .await
+                    .with_context(|| format!("Failed to send chunk {}", i))?;
+                progress.ack(u.clone(), l.clone());
+                if let Some(journal) = journal {
+                    journal.ack(i)?;
+                }
+                anyhow::Ok(())
+            }
+            .instrument(tracing::info_span!(
+                "submit-chunk",
+                chunk = i,
+                units = u.len(),
+                lints = l.len()
+            ))
+        })
+        .buffer_unordered(concurrency)
+        .try_collect::<Vec<_>>()
+        .await?;
+    // The final status is only sent once all the results have been received
+    client
+        .call::<serde_json::Value>(
+            "harbormaster.sendmessage",
+            &Chunk {
+                status: params.status,
+                ..chunk(&[], &[])
+            },
+        )
+        .await?;
+    progress.done.store(true, Ordering::SeqCst);
+    if let Some(journal) = journal {
+        journal.remove();
+    }
+    Ok(())
+}
+
+/// Indices of the chunks acknowledged during the submission of a message, one per line
+struct Journal {
+    path: PathBuf,
+    file: Mutex<File>,
+    /// Chunks acknowledged by a previous submission, when resuming
+    acked: HashSet<usize>,
+}
+impl Journal {
+    /// Journal of the message, keyed by the build target and a hash of the results and of the
+    /// chunk size, which determine the chunks
+    fn open(dir: &Path, params: &Params, chunk_size: usize) -> anyhow::Result<Self> {
+        let mut hasher = sha2::Sha256::new();
+        hasher.update(serde_json::to_vec(&Chunk {
+            build: &params.build,
+            status: params.status,
+            unit: params.unit.as_deref().unwrap_or_default(),
+            lint: params.lint.as_deref().unwrap_or_default(),
+        })?);
+        hasher.update(chunk_size.to_le_bytes());
+        let hash = format!("{:x}", hasher.finalize());
+        let path = dir.join(format!("{}-{}.journal", params.build, &hash[..16]));
+        let acked = match std::fs::read_to_string(&path) {
+            Ok(data) => data
+                .lines()
+                .map(|l| l.parse())
+                .collect::<Result<HashSet<usize>, _>>()
+                .with_context(|| format!("Invalid journal {:?}", path))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashSet::new(),
+            Err(e) => return Err(e).with_context(|| format!("Failed to read {:?}", path)),
+        };
+        std::fs::create_dir_all(dir).with_context(|| format!("Failed to create {:?}", dir))?;
+        let file = std::fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("Failed to open {:?}", path))?;
+        debug!("Recording the acknowledged chunks in {:?}", path);
+        Ok(Self {
+            path,
+            file: Mutex::new(file),
+            acked,
+        })
+    }
+    fn ack(&self, chunk: usize) -> anyhow::Result<()> {
+        let mut file = self.file.lock().unwrap();
+        writeln!(file, "{}", chunk)
+            .and_then(|_| file.sync_data())
+            .with_context(|| format!("Failed to write {:?}", self.path))
+    }
+    /// Remove the journal once the whole message was sent
+    fn remove(&self) {
+        if let Err(e) = std::fs::remove_file(&self.path) {
+            warn!("Failed to remove {:?}: {}", self.path, e);
+        }
+        if let Some(dir) = self.path.parent() {
+            // Only succeeds if no other journal is left
+            let _ = std::fs::remove_dir(dir);
+        }
+    }
+}
+
+/// Results acknowledged by the server during a submission
+#[derive(Default)]
+pub(crate) struct Progress {
+    /// Message being sent
+    params: Mutex<Option<Params>>,
+    units: Mutex<Vec<Range<usize>>>,
+    lints: Mutex<Vec<Range<usize>>>,
+    /// Whether the final status was sent
+    done: AtomicBool,
+    /// Where the acknowledged chunks are recorded
+    journal: Option<PathBuf>,
+}
+impl Progress {
+    /// Also record the acknowledged chunks in `dir`, and skip the ones recorded by a previous
+    /// interrupted submission of the same message
+    pub fn journaled(dir: PathBuf) -> Self {
+        Self {
+            journal: Some(dir),
+            ..Default::default()
+        }
+    }
+    fn ack(&self, units: Range<usize>, lints: Range<usize>) {
+        self.units.lock().unwrap().push(units);
+        self.lints.lock().unwrap().push(lints);
+    }
+    /// The message without the acknowledged results, or `fallback` if no message was sent yet
+    fn unsent(&self, fallback: &Params) -> Spill {
+        let params = self.params.lock().unwrap();
+        let params = params.as_ref().unwrap_or(fallback);
+        fn keep<T: Clone>(items: Option<&[T]>, acked: &[Range<usize>]) -> Vec<T> {
+            let items = items.unwrap_or_default();
+            (0..items.len())
+                .filter(|i| !acked.iter().any(|r| r.contains(i)))
+                .map(|i| items[i].clone())
+                .collect()
+        }
+        Spill {
+            build: params.build.clone(),
+            status: params.status,
+            unit: keep(params.unit.as_deref(), &self.units.lock().unwrap()),
+            lint: keep(params.lint.as_deref(), &self.lints.lock().unwrap()),
+        }
+    }
+}
+
+/// Results not sent before the deadline, in the format of harbormaster.sendmessage without the
+/// API token
+#[derive(Serialize, Deserialize)]
+pub(crate) struct Spill {
+    #[serde(rename = "buildTargetPHID")]
+    build: String,
+    #[serde(rename = "type")]
+    status: Status,
+    unit: Vec<UnitResult>,
+    lint: Vec<LintResult>,
+}
+impl Spill {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        serde_json::from_slice(&data).with_context(|| format!("Invalid spill file {:?}", path))
+    }
+    pub fn into_params(self, token: &str) -> Params {
+        Params {
+            build: self.build,
+            status: self.status,
+            unit: Some(self.unit),
+            lint: Some(self.lint),
+            auth: Auth {
+                token: token.into(),
+            },
+        }
+    }
+}
+
+/// The deadline of the submission expired, and the unsent results were spilled
+#[derive(Debug)]
+pub(crate) struct DeadlineExceeded {
+    spill: PathBuf,
+}
+impl std::fmt::Display for DeadlineExceeded {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "Deadline exceeded, the unsent results were written to {:?} (send them with --resend)",
+            self.spill
+        )
+    }
+}
+impl std::error::Error for DeadlineExceeded {}
+
+/// Run the submission within the deadline. On expiry, the results not acknowledged in `progress`
+/// (or all of `params` if nothing was sent yet) are written to the spill file.
+pub(crate) async fn with_deadline(
+    deadline: Option<Duration>,
+    progress: &Progress,
+    params: &Params,
+    spill: &Path,
+    submission: impl Future<Output = anyhow::Result<()>>,
+) -> anyhow::Result<()> {
+    let Some(deadline) = deadline else {
+        return submission.await;
+    };
+    match tokio::time::timeout(deadline, submission).await {
+        Ok(result) => result,
+        Err(_) if progress.done.load(Ordering::SeqCst) => {
+            warn!("Deadline exceeded after sending the results");
+            Ok(())
+        }
+        Err(_) => {
+            let unsent = progress.unsent(params);
+            warn!(
+                "Deadline exceeded, {} results and the status were not sent",
+                unsent.unit.len() + unsent.lint.len()
+            );
+            std::fs::write(spill, serde_json::to_vec_pretty(&unsent)?)
+                .with_context(|| format!("Failed to write {:?}", spill))?;
+            Err(DeadlineExceeded {
+                spill: spill.into(),
+            }
+            .into())
+        }
+    }
+}
+
+/// Attach all the lints, including those omitted from the message by `--max-lints`, under the
+/// artifact `key`
+pub(crate) async fn attach_lints(
+    client: &Client,
+    params: &Params,
+    omitted: &[LintResult],
+    key: &str,
+) -> anyhow::Result<()> {
+    let all: Vec<_> = params.lint.iter().flatten().chain(omitted).collect();
+    client
+        .upload_artifact(
+            &params.build,
+            key,
+            "lints.json",
+            serde_json::to_string_pretty(&all)?.as_bytes(),
+        )
+        .await?;
+    Ok(())
+}
+
+/// Attach the patch fixing the formatting violations, and link it from the rustfmt lints
+pub(crate) async fn attach_rustfmt_patch(
+    client: &Client,
+    params: &mut Params,
+    patch: &str,
+) -> anyhow::Result<()> {
+    let file = client
+        .upload_artifact(&params.build, "rustfmt", "rustfmt.patch", patch.as_bytes())
+        .await?;
+    let url = client.file_url(&file).await?;
+    for lint in params.lint.iter_mut().flatten() {
+        if lint.code == crate::rustfmt::CODE {
+            let description = lint.description.get_or_insert_with(String::new);
+            description.push_str(&format!("\n\nFull diff: {}", url));
+        }
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conduit::tests::{client, ok, server};
+    use crate::report::tests::unit;
+    use hyper::StatusCode;
+    use std::sync::Arc;
+
+    fn message(names: &[&str]) -> Params {
+        Params {
+            build: "PHID-HMBT-1".into(),
+            status: Status::Pass,
+            unit: Some(names.iter().map(|name| unit(name, "pass")).collect()),
+            lint: None,
+            auth: Auth {
+                token: "api-token".into(),
+            },
+        }
+    }
+
+    fn journal_dir(name: &str) -> PathBuf {
+        let dir =
+            std::env::temp_dir().join(format!("harbormaster-{}-{}", std::process::id(), name));
+        let _ = std::fs::remove_dir_all(&dir);
+        dir
+    }
+
+    #[test]
+    fn journal() {
+        let dir = journal_dir("journal");
+        let params = message(&["a", "b", "c"]);
+        let journal = Journal::open(&dir, &params, 1).unwrap();
+        assert!(journal.acked.is_empty());
+        journal.ack(0).unwrap();
+        journal.ack(2).unwrap();
+        drop(journal);
+        let journal = Journal::open(&dir, &params, 1).unwrap();
+        assert_eq!(journal.acked, HashSet::from([0, 2]));
+        // Other results or chunks
+        assert!(Journal::open(&dir, &params, 2).unwrap().acked.is_empty());
+        let other = message(&["a", "b"]);
+        assert!(Journal::open(&dir, &other, 1).unwrap().acked.is_empty());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 3);
+        journal.remove();
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 2);
+    }
+
+    #[tokio::test]
+    async fn resume() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let uri = server({
+            let messages = messages.clone();
+            move |method, params| {
+                if method != "harbormaster.sendmessage" {
+                    return ok(serde_json::json!({}));
+                }
+                let mut messages = messages.lock().unwrap();
+                messages.push(params);
+                if messages.len() == 3 {
+                    let error = serde_json::json!({
+                        "result": null,
+                        "error_code": "ERR-CONDUIT-CORE",
+                        "error_info": "Database connection lost",
+                    });
+                    (StatusCode::INTERNAL_SERVER_ERROR, error)
+                } else {
+                    ok(serde_json::Value::Null)
+                }
+            }
+        });
+        let client = client(&uri);
+        let dir = journal_dir("resume");
+        let params = message(&["a", "b", "c", "d"]);
+        let sent = |messages: &[serde_json::Value]| -> Vec<String> {
+            messages
+                .iter()
+                .map(|m| match m["unit"].as_array() {
+                    Some(units) => units[0]["name"].as_str().unwrap().to_string(),
+                    None => m["type"].as_str().unwrap().to_string(),
+                })
+                .collect()
+        };
+
+        let progress = Progress::journaled(dir.clone());
+        assert!(send_tracked(&client, &params, Some(1), 1, &progress)
+            .await
+            .is_err());
+        assert_eq!(sent(&messages.lock().unwrap()), ["a", "b", "c"]);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        // Only the chunks not acknowledged are sent again
+        let progress = Progress::journaled(dir.clone());
+        send_tracked(&client, &params, Some(1), 1, &progress)
+            .await
+            .unwrap();
+        assert_eq!(sent(&messages.lock().unwrap()[3..]), ["c", "d", "pass"]);
+        assert!(progress.done.load(Ordering::SeqCst));
+        assert!(!dir.exists());
+
+        // Without a journal, everything is sent
+        send_tracked(&client, &params, Some(1), 1, &Progress::default())
+            .await
+            .unwrap();
+        assert_eq!(
+            sent(&messages.lock().unwrap()[6..]),
+            ["a", "b", "c", "d", "pass"]
+        );
+        assert!(!dir.exists());
+    }
+
+    #[tokio::test]
+    async fn deadline() {
+        // Conduit server never answering after the first chunk
+        let calls = Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let make_service = hyper::service::make_service_fn({
+            let calls = calls.clone();
+            move |_| {
+                let calls = calls.clone();
+                async move {
+                    Ok::<_, hyper::Error>(hyper::service::service_fn(
+                        move |request: hyper::Request<hyper::Body>| {
+                            let hang = request.uri().path() == "/api/harbormaster.sendmessage"
+                                && calls.fetch_add(1, Ordering::SeqCst) > 0;
+                            async move {
+                                if hang {
+                                    futures::future::pending::<()>().await;
+                                }
+                                let response = serde_json::json!({
+                                    "result": {}, "error_code": null, "error_info": null
+                                });
+                                Ok::<_, hyper::Error>(hyper::Response::new(hyper::Body::from(
+                                    response.to_string(),
+                                )))
+                            }
+                        },
+                    ))
+                }
+            }
+        });
+        let server = hyper::Server::bind(&([127, 0, 0, 1], 0).into()).serve(make_service);
+        let uri = format!("http://{}", server.local_addr());
+        tokio::spawn(server);
+
+        let client = client(&uri);
+        let params = message(&["a", "b", "c"]);
+        let spill = crate::input::tests::fixture("spill.json", "");
+        let progress = Progress::default();
+        let start = std::time::Instant::now();
+        let error = with_deadline(
+            Some(Duration::from_millis(200)),
+            &progress,
+            &params,
+            &spill,
+            send_tracked(&client, &params, Some(1), 2, &progress),
+        )
+        .await
+        .unwrap_err();
+        // Well before the timeout of the requests
+        assert!(start.elapsed() < Duration::from_secs(2));
+        assert_eq!(crate::exit_code(&error), 6);
+        assert_eq!(calls.load(Ordering::SeqCst), 3);
+        let spilled = Spill::load(&spill).unwrap();
+        assert_eq!(spilled.build, "PHID-HMBT-1");
+        assert!(matches!(spilled.status, Status::Pass));
+        let names: Vec<_> = spilled.unit.iter().map(|u| u.name.as_str()).collect();
+        assert_eq!(names, ["b", "c"]);
+    }
+}
@@ -0,0 +1,89 @@
+//! JSON summary of the counts of results, e.g. for dashboards or chat notifications
+use std::collections::BTreeMap;
+use std::io::Write;
+
+use itertools::Itertools;
+use serde::Serialize;
+
+use crate::report::{Report, Sink};
+
+/// Sink writing the number of tests by result and of lints by severity, and the line coverage
+pub(crate) struct Summary;
+
+#[derive(Serialize)]
+struct Counts {
+    total: usize,
+    #[serde(flatten)]
+    by: BTreeMap<String, usize>,
+}
+impl Counts {
+    fn new<T>(items: &[T], key: impl Fn(&T) -> String) -> Self {
+        Self {
+            total: items.len(),
+            by: items.iter().counts_by(key).into_iter().collect(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct Coverage {
+    files: usize,
+    covered: usize,
+    uncovered: usize,
+    percent: f64,
+}
+
+#[derive(Serialize)]
+struct Output {
+    tests: Counts,
+    lints: Counts,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    coverage: Option<Coverage>,
+}
+
+impl Sink for Summary {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        let lines = |c| report.coverage.values().map(|l| l.matches(c).count()).sum();
+        let (covered, uncovered) = (lines('C'), lines('U'));
+        let summary = Output {
+            tests: Counts::new(&report.units, |u| u.result.clone()),
+            lints: Counts::new(&report.lints, |l| l.severity.to_lowercase()),
+            coverage: (!report.coverage.is_empty()).then_some(Coverage {
+                files: report.coverage.len(),
+                covered,
+                uncovered,
+                percent: match covered + uncovered {
+                    0 => 100.0,
+                    total => 100.0 * covered as f64 / total as f64,
+                },
+            }),
+        };
+        serde_json::to_writer_pretty(out, &summary)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{report, written};
+
+    #[test]
+    fn counts() {
+        let mut report = report();
+        let summary: serde_json::Value = serde_json::from_str(&written(&Summary, &report)).unwrap();
+        assert_eq!(
+            summary,
+            serde_json::json!({
+                "tests": {"total": 2, "pass": 1, "fail": 1},
+                "lints": {"total": 2, "error": 1, "advice": 1},
+            })
+        );
+        report.coverage.insert("src/lib.rs".into(), "NCCU".into());
+        let summary: serde_json::Value = serde_json::from_str(&written(&Summary, &report)).unwrap();
+        assert_eq!(
+            summary["coverage"],
+            serde_json::json!({"files": 1, "covered": 2, "uncovered": 1, "percent": 200.0 / 3.0})
+        );
+    }
+}
@@ -0,0 +1,155 @@
+//! TeamCity service messages
+//! See <https://www.jetbrains.com/help/teamcity/service-messages.html>
+use std::collections::HashSet;
+use std::io::Write;
+
+use itertools::Itertools;
+
+use crate::report::{Report, Sink};
+use crate::{remarkup, LintResult, UnitResult};
+
+/// Escape a value according to the service messages format
+fn escape(value: &str) -> String {
+    let mut out = String::with_capacity(value.len());
+    for c in value.chars() {
+        match c {
+            '|' => out.push_str("||"),
+            '\'' => out.push_str("|'"),
+            '\n' => out.push_str("|n"),
+            '\r' => out.push_str("|r"),
+            '[' => out.push_str("|["),
+            ']' => out.push_str("|]"),
+            c => out.push(c),
+        }
+    }
+    out
+}
+
+fn message(
+    mut out: impl Write,
+    name: &str,
+    attributes: &[(&str, Option<&str>)],
+) -> std::io::Result<()> {
+    let attributes = attributes
+        .iter()
+        .filter_map(|(k, v)| Some(format!("{}='{}'", k, escape((*v)?))))
+        .join(" ");
+    writeln!(out, "##teamcity[{} {}]", name, attributes)
+}
+
+pub(crate) fn write(
+    units: &[UnitResult],
+    lints: &[LintResult],
+    mut out: impl Write,
+) -> std::io::Result<()> {
+    let suites = units.iter().into_group_map_by(|u| u.namespace.as_deref());
+    for (namespace, units) in suites.into_iter().sorted_by_key(|(n, _)| *n) {
+        if let Some(namespace) = namespace {
+            message(&mut out, "testSuiteStarted", &[("name", Some(namespace))])?;
+        }
+        for unit in units {
+            let name = Some(unit.name.as_str());
+            message(&mut out, "testStarted", &[("name", name)])?;
+            let details = match unit.format.as_deref() {
+                Some("remarkup") => unit.details.as_deref().map(remarkup::plain),
+                _ => unit.details.clone(),
+            };
+            match unit.result.as_str() {
+                "pass" => {}
+                "skip" => message(&mut out, "testIgnored", &[("name", name)])?,
+                result => message(
+                    &mut out,
+                    "testFailed",
+                    &[
+                        ("name", name),
+                        ("message", Some(result)),
+                        ("details", details.as_deref()),
+                    ],
+                )?,
+            }
+            let duration_ms = unit.duration_s.map(|d| ((d * 1000.0) as u64).to_string());
+            message(
+                &mut out,
+                "testFinished",
+                &[("name", name), ("duration", duration_ms.as_deref())],
+            )?;
+        }
+        if let Some(namespace) = namespace {
+            message(&mut out, "testSuiteFinished", &[("name", Some(namespace))])?;
+        }
+    }
+
+    let mut types = HashSet::new();
+    for lint in lints {
+        if types.insert(&lint.code) {
+            message(
+                &mut out,
+                "inspectionType",
+                &[
+                    ("id", Some(&lint.code)),
+                    ("name", Some(&lint.code)),
+                    ("category", Some(&lint.name)),
+                    ("description", Some(&lint.code)),
+                ],
+            )?;
+        }
+        let severity = match lint.severity.as_str() {
+            "error" => "ERROR",
+            "warning" => "WARNING",
+            _ => "INFO",
+        };
+        let line = lint.line.map(|l| l.to_string());
+        message(
+            &mut out,
+            "inspection",
+            &[
+                ("typeId", Some(&lint.code)),
+                ("message", lint.description.as_deref()),
+                ("file", Some(&lint.path)),
+                ("line", line.as_deref()),
+                ("SEVERITY", Some(severity)),
+            ],
+        )?;
+    }
+    Ok(())
+}
+
+/// Sink printing the results as service messages
+pub(crate) struct Teamcity;
+impl Sink for Teamcity {
+    fn write(&self, report: &Report, out: &mut dyn Write) -> anyhow::Result<()> {
+        Ok(write(&report.units, &report.lints, out)?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::report::tests::{report, unit, written};
+
+    #[test]
+    fn messages() {
+        let out = written(&Teamcity, &report());
+        assert!(out.contains("##teamcity[testSuiteStarted name='my-crate']"));
+        assert!(out.contains("##teamcity[testFailed name='tests::fails' message='fail']"));
+        assert!(!out.contains("testFailed name='tests::passes'"));
+        assert!(out.contains("inspectionType id='clippy::len_zero'"));
+        assert!(out.contains("SEVERITY='ERROR'"));
+    }
+
+    #[test]
+    fn escapes_the_values() {
+        assert_eq!(escape("a|b'c[d]e\nf\rg"), "a||b|'c|[d|]e|nf|rg");
+        let mut out = vec![];
+        let mut unit = unit("tests::it's [1]", "fail");
+        unit.namespace = None;
+        unit.details = Some("left | right\n".into());
+        write(&[unit], &[], &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            "##teamcity[testStarted name='tests::it|'s |[1|]']\n\
+             ##teamcity[testFailed name='tests::it|'s |[1|]' message='fail' details='left || right|n']\n\
+             ##teamcity[testFinished name='tests::it|'s |[1|]' duration='500']\n"
+        );
+    }
+}
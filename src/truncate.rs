@@ -0,0 +1,137 @@
+//! Truncation of the unit details, which Harbormaster rejects beyond its field limits
+use clap::ValueEnum;
+
+use crate::{remarkup, UnitResult};
+
+/// Part of the details to keep when truncating
+#[derive(Debug, Copy, Clone, Default, ValueEnum)]
+pub(crate) enum Keep {
+    Head,
+    /// Where the panic messages and backtraces usually are
+    #[default]
+    Tail,
+}
+
+/// Keep at most `max` bytes of `text`, at a UTF-8 boundary
+fn cut(text: &str, max: usize, keep: Keep) -> &str {
+    match keep {
+        Keep::Head => {
+            let end = (0..=max.min(text.len()))
+                .rev()
+                .find(|i| text.is_char_boundary(*i))
+                .unwrap_or(0);
+            &text[..end]
+        }
+        Keep::Tail => {
+            let start = (text.len().saturating_sub(max)..=text.len())
+                .find(|i| text.is_char_boundary(*i))
+                .unwrap_or(text.len());
+            &text[start..]
+        }
+    }
+}
+
+/// Truncate the details to at most `max` bytes, noting the truncation inline. In Remarkup, only
+/// the first code block is truncated, inside of the block, unless the text around it does not
+/// fit.
+pub(crate) fn details(unit: &mut UnitResult, max: usize, keep: Keep) {
+    let Some(details) = &unit.details else {
+        return;
+    };
+    if details.len() <= max {
+        return;
+    }
+    let remarkup = unit.format.as_deref() == Some("remarkup");
+    let note = |omitted: usize| format!("[{} bytes truncated]", omitted);
+    // Upper bound of the note and code block overhead
+    let overhead = note(details.len()).len() + remarkup::code_block("").len() + 2;
+    let (before, text, after) = match remarkup.then(|| remarkup::split_code_block(details)) {
+        Some(Some((before, code, after))) if before.len() + after.len() + overhead < max => {
+            (before, code, after)
+        }
+        Some(_) => ("", remarkup::plain(details), ""),
+        None => ("", details.clone(), ""),
+    };
+    let build = |kept: &str| {
+        let body = if remarkup {
+            remarkup::code_block(kept)
+        } else {
+            kept.to_string()
+        };
+        let note = note(text.len() - kept.len());
+        match keep {
+            Keep::Head => format!("{}{}\n{}{}", before, body, note, after),
+            Keep::Tail => format!("{}{}\n{}{}", before, note, body, after),
+        }
+    };
+    // Escaping the fences kept in the block lengthens it, so cut further until it fits
+    let mut budget = max.saturating_sub(before.len() + after.len() + overhead);
+    let truncated = loop {
+        let kept = cut(&text, budget, keep);
+        let truncated = build(kept);
+        if truncated.len() <= max {
+            break truncated;
+        }
+        if kept.is_empty() {
+            break cut(&truncated, max, Keep::Head).to_string();
+        }
+        budget = kept.len().saturating_sub(truncated.len() - max);
+    };
+    unit.details = Some(truncated);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn truncated(text: &str, format: Option<&str>, max: usize, keep: Keep) -> String {
+        let mut unit = UnitResult {
+            details: Some(text.into()),
+            format: format.map(String::from),
+            ..Default::default()
+        };
+        details(&mut unit, max, keep);
+        let details = unit.details.unwrap();
+        assert!(details.len() <= max, "{} > {}", details.len(), max);
+        details
+    }
+
+    #[test]
+    fn plain_text() {
+        let text = "é".repeat(50);
+        assert_eq!(truncated(&text, None, 100, Keep::Tail), text);
+        let tail = truncated(&text, None, 60, Keep::Tail);
+        assert!(tail.starts_with('[') && tail.ends_with('é'));
+        let head = truncated(&text, None, 60, Keep::Head);
+        assert!(head.starts_with('é') && head.ends_with("truncated]"));
+    }
+
+    #[test]
+    fn remarkup_keeps_the_text_around_the_block() {
+        let output = format!("{}\npanicked at src/lib.rs:3:5", "line\n".repeat(100));
+        let details = format!(
+            "Doc-test of `parse`:\n\n{}\n\nSee the docs",
+            remarkup::code_block(&output)
+        );
+        let tail = truncated(&details, Some("remarkup"), 200, Keep::Tail);
+        assert!(tail.starts_with("Doc-test of `parse`:\n\n["));
+        assert!(tail.ends_with("panicked at src/lib.rs:3:5\n```\n\nSee the docs"));
+        let (_, code, _) = remarkup::split_code_block(&tail).unwrap();
+        assert!(output.ends_with(&code));
+    }
+
+    #[test]
+    fn remarkup_escaped_fences_fit() {
+        // Each kept fence is escaped again, with 3 more bytes
+        let output = "```\n".repeat(100);
+        let details = remarkup::code_block(&output);
+        for keep in [Keep::Head, Keep::Tail] {
+            let details = truncated(&details, Some("remarkup"), 150, keep);
+            assert_eq!(details.lines().filter(|l| *l == "```").count(), 2);
+        }
+        // Without room for the text around the block, the whole text is truncated
+        let details = format!("{}\n{}", "x".repeat(300), remarkup::code_block(&output));
+        let details = truncated(&details, Some("remarkup"), 100, Keep::Tail);
+        assert!(details.starts_with('['));
+    }
+}
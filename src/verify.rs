@@ -0,0 +1,161 @@
+//! Authentication of the requests to the server mode, with a shared secret
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+use base64::Engine;
+use hmac::Mac;
+use hyper::header::{HeaderMap, AUTHORIZATION};
+use hyper::Method;
+
+/// Unix timestamp of the request, in seconds
+pub(crate) const TIMESTAMP_HEADER: &str = "x-harbormaster-timestamp";
+/// `sha256=<hex>` HMAC of the request
+pub(crate) const SIGNATURE_HEADER: &str = "x-harbormaster-signature";
+
+/// Constant-time comparison
+fn equal(a: &[u8], b: &[u8]) -> bool {
+    a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (x, y)| acc | (x ^ y)) == 0
+}
+
+/// HMAC-SHA256 of `{timestamp}\n{method}\n{path and query}\n{body}`
+fn mac(
+    secret: &str,
+    timestamp: &str,
+    method: &Method,
+    uri: &str,
+    body: &[u8],
+) -> hmac::Hmac<sha2::Sha256> {
+    let mut mac = hmac::Hmac::<sha2::Sha256>::new_from_slice(secret.as_bytes())
+        .expect("HMAC accepts keys of any size");
+    for part in [
+        timestamp.as_bytes(),
+        method.as_str().as_bytes(),
+        uri.as_bytes(),
+    ] {
+        mac.update(part);
+        mac.update(b"\n");
+    }
+    mac.update(body);
+    mac
+}
+
+/// Check that the request is signed with the secret, within `max_skew` of its timestamp, or,
+/// with `allow_basic_auth`, carries the secret as the password of the HTTP basic authentication
+/// (as configured with a Harbormaster credential, which cannot sign requests). The latter is not
+/// protected against replays.
+pub(crate) fn verify(
+    secret: &str,
+    max_skew: Duration,
+    allow_basic_auth: bool,
+    method: &Method,
+    uri: &str,
+    headers: &HeaderMap,
+    body: &[u8],
+) -> anyhow::Result<()> {
+    if let Some(signature) = headers.get(SIGNATURE_HEADER) {
+        let timestamp = headers
+            .get(TIMESTAMP_HEADER)
+            .context("Missing timestamp header")?
+            .to_str()?;
+        let sent =
+            UNIX_EPOCH + Duration::from_secs(timestamp.parse().context("Invalid timestamp")?);
+        let now = SystemTime::now();
+        let skew = now
+            .duration_since(sent)
+            .or_else(|_| sent.duration_since(now))
+            .unwrap_or_default();
+        anyhow::ensure!(
+            skew <= max_skew,
+            "Request timestamp outside of the {:?} window",
+            max_skew
+        );
+        let signature = signature
+            .to_str()?
+            .strip_prefix("sha256=")
+            .context("Expected a sha256= signature")?;
+        let signature = (0..signature.len())
+            .step_by(2)
+            .map(|i| u8::from_str_radix(signature.get(i..i + 2)?, 16).ok())
+            .collect::<Option<Vec<u8>>>()
+            .context("Invalid signature encoding")?;
+        return mac(secret, timestamp, method, uri, body)
+            .verify_slice(&signature)
+            .ok()
+            .context("Invalid signature");
+    }
+    anyhow::ensure!(
+        allow_basic_auth,
+        "Missing signature (basic authentication is not allowed)"
+    );
+    let credentials = headers
+        .get(AUTHORIZATION)
+        .context("Missing signature or authorization")?
+        .to_str()?
+        .strip_prefix("Basic ")
+        .context("Expected basic authentication")?;
+    let credentials = base64::engine::general_purpose::STANDARD.decode(credentials.trim())?;
+    let password = match credentials.iter().position(|b| *b == b':') {
+        Some(i) => &credentials[i + 1..],
+        None => &credentials[..],
+    };
+    anyhow::ensure!(equal(password, secret.as_bytes()), "Invalid credentials");
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn signatures_and_basic_auth() {
+        let verify = |headers: &[(&str, String)], allow_basic_auth| {
+            let headers: HeaderMap = headers
+                .iter()
+                .map(|(name, value)| (name.parse().unwrap(), value.parse().unwrap()))
+                .collect();
+            let skew = Duration::from_secs(300);
+            verify(
+                "s3cret",
+                skew,
+                allow_basic_auth,
+                &Method::POST,
+                "/?a=1",
+                &headers,
+                b"{}",
+            )
+        };
+        let signed = |timestamp: u64| {
+            let timestamp = timestamp.to_string();
+            let mac = mac("s3cret", &timestamp, &Method::POST, "/?a=1", b"{}");
+            let signature: String = mac
+                .finalize()
+                .into_bytes()
+                .iter()
+                .map(|b| format!("{:02x}", b))
+                .collect();
+            vec![
+                (TIMESTAMP_HEADER, timestamp),
+                (SIGNATURE_HEADER, format!("sha256={}", signature)),
+            ]
+        };
+        let now = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        assert!(verify(&signed(now), false).is_ok());
+        // Replayed, or signed with another secret
+        assert!(verify(&signed(now - 600), false).is_err());
+        let mut forged = signed(now);
+        forged[0].1 = (now + 1).to_string();
+        assert!(verify(&forged, false).is_err());
+
+        let basic = |password: &str| {
+            let credentials =
+                base64::engine::general_purpose::STANDARD.encode(format!("user:{}", password));
+            vec![("authorization", format!("Basic {}", credentials))]
+        };
+        assert!(verify(&basic("s3cret"), false).is_err());
+        assert!(verify(&basic("s3cret"), true).is_ok());
+        assert!(verify(&basic("other"), true).is_err());
+    }
+}
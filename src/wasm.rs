@@ -0,0 +1,108 @@
+//! Parsers loaded as WASM plugins, run in a sandboxed interpreter.
+//!
+//! The modules export their `memory`, an `alloc(len: i32) -> i32` function returning a buffer
+//! for the input, and `parse(ptr: i32, len: i32) -> i64`, returning the location of the output
+//! as `ptr << 32 | len`. The output is a JSON report fragment (see [`crate::parser::Fragment`]).
+use std::path::Path;
+
+use anyhow::Context;
+
+use crate::parser::{Fragment, Parser};
+use crate::report::Report;
+
+/// Maximum number of instructions executed by a plugin for an input, to stop runaway plugins
+const FUEL: u64 = 1_000_000_000;
+
+pub(crate) struct Plugin {
+    engine: wasmi::Engine,
+    module: wasmi::Module,
+    /// Instructions allowed for each input
+    fuel: u64,
+}
+impl Plugin {
+    pub fn load(path: &Path) -> anyhow::Result<Self> {
+        let data = std::fs::read(path).with_context(|| format!("Failed to read {:?}", path))?;
+        let mut config = wasmi::Config::default();
+        config.consume_fuel(true);
+        let engine = wasmi::Engine::new(&config);
+        let module = wasmi::Module::new(&engine, data.as_slice())
+            .with_context(|| format!("Invalid WASM module {:?}", path))?;
+        Ok(Self {
+            engine,
+            module,
+            fuel: FUEL,
+        })
+    }
+}
+impl Parser for Plugin {
+    fn parse(&self, input: &[u8]) -> anyhow::Result<Report> {
+        // A fresh instance for each input, so that the inputs are isolated
+        let mut store = wasmi::Store::new(&self.engine, ());
+        store
+            .add_fuel(self.fuel)
+            .map_err(|e| anyhow::anyhow!("{}", e))?;
+        let instance = wasmi::Linker::<()>::new(&self.engine)
+            .instantiate(&mut store, &self.module)?
+            .start(&mut store)?;
+        let memory = instance
+            .get_memory(&store, "memory")
+            .context("The plugin does not export its memory")?;
+        let alloc = instance.get_typed_func::<i32, i32>(&store, "alloc")?;
+        let parse = instance.get_typed_func::<(i32, i32), i64>(&store, "parse")?;
+        let len = i32::try_from(input.len()).context("Input too large")?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory
+            .write(&mut store, ptr as u32 as usize, input)
+            .map_err(|e| anyhow::anyhow!("Failed to write the input: {}", e))?;
+        let output = parse.call(&mut store, (ptr, len))?;
+        let mut data = vec![0; output as u32 as usize];
+        memory
+            .read(&store, (output >> 32) as u32 as usize, &mut data)
+            .map_err(|e| anyhow::anyhow!("Failed to read the output: {}", e))?;
+        let fragment: Fragment =
+            serde_json::from_slice(&data).context("Invalid output of the plugin")?;
+        Ok(fragment.into())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::tests::fixture;
+
+    /// Plugin returning a fixed fragment, after checking that it received the input
+    const PLUGIN: &str = r#"(module
+        (memory (export "memory") 1)
+        (data (i32.const 0) "{\"lint\": [{\"name\": \"sonar\", \"code\": \"S1\", \"severity\": \"warning\", \"path\": \"src/lib.rs\"}]}")
+        (func (export "alloc") (param i32) (result i32) i32.const 1024)
+        (func (export "parse") (param $ptr i32) (param $len i32) (result i64)
+            (if (i32.ne (i32.load8_u (local.get $ptr)) (i32.const 0x7b)) (then unreachable))
+            i64.const 88))
+    "#;
+
+    fn plugin(name: &str, wat: &str) -> Plugin {
+        let path = fixture(name, "");
+        std::fs::write(&path, wat::parse_str(wat).unwrap()).unwrap();
+        Plugin::load(&path).unwrap()
+    }
+
+    #[test]
+    fn runs_the_plugins() {
+        let report = plugin("plugin.wasm", PLUGIN).parse(b"{}").unwrap();
+        assert_eq!(report.lints.len(), 1);
+        assert_eq!(report.lints[0].code, "S1");
+        assert_eq!(report.lints[0].path, "src/lib.rs");
+        assert!(plugin("plugin.wasm", PLUGIN).parse(b"[]").is_err());
+
+        let runaway = r#"(module
+            (memory (export "memory") 1)
+            (func (export "alloc") (param i32) (result i32) i32.const 0)
+            (func (export "parse") (param i32 i32) (result i64) (loop br 0) i64.const 0))
+        "#;
+        let mut runaway = plugin("runaway.wasm", runaway);
+        runaway.fuel = 10_000;
+        assert!(runaway.parse(b"{}").is_err());
+        let invalid = fixture("invalid.wasm", "not wasm");
+        assert!(Plugin::load(&invalid).is_err());
+    }
+}
@@ -0,0 +1,248 @@
+//! Watch mode: send the new results in `work` messages whenever the inputs change, and the final
+//! status on Ctrl-C.
+use std::collections::HashSet;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use log::*;
+use notify::Watcher;
+
+use crate::conduit::Client;
+use crate::{budget, cache, config, schema, submit, Flags, LintResult, Params, Report, Status};
+
+pub(crate) async fn watch(
+    args: &Flags,
+    config: &config::Config,
+    client: &Client,
+    params: Params,
+) -> anyhow::Result<()> {
+    let cwd = std::env::current_dir()?;
+    let inputs: HashSet<PathBuf> = std::iter::once(&args.inputs)
+        .chain(&config.workspaces)
+        .flat_map(|inputs| inputs.paths())
+        .filter(|path| !crate::fetch::is_url(path))
+        .map(|path| cwd.join(path))
+        .collect();
+    anyhow::ensure!(!inputs.is_empty(), "No inputs to watch");
+    let (tx, mut rx) = tokio::sync::mpsc::unbounded_channel();
+    let mut watcher = notify::recommended_watcher(move |event: notify::Result<notify::Event>| {
+        if let Ok(event) = event {
+            let _ = tx.send(event);
+        }
+    })?;
+    // Watch the directories, as the inputs are usually recreated rather than modified
+    let dirs: HashSet<_> = inputs.iter().filter_map(|path| path.parent()).collect();
+    for dir in dirs {
+        watcher.watch(dir, notify::RecursiveMode::NonRecursive)?;
+    }
+    info!(
+        "Watching {} inputs, press Ctrl-C to send the final status",
+        inputs.len()
+    );
+
+    // Listened to from the start, so that a Ctrl-C while parsing or sending is not lost
+    let (stop, mut stopped) = tokio::sync::oneshot::channel();
+    tokio::spawn(async move {
+        if tokio::signal::ctrl_c().await.is_ok() {
+            let _ = stop.send(());
+        }
+    });
+    // Let the task install the handler
+    tokio::task::yield_now().await;
+
+    // Harbormaster appends the results of each message
+    let mut sent = cache::Sent::default();
+    // The artifact keys must be unique within a build target
+    let mut artifacts = 0;
+    let mut attached = cache::Sent::default();
+    let mut last = Report::default();
+    loop {
+        match prepare(args, config) {
+            Ok((update, report, omitted)) => {
+                let update = Params {
+                    unit: update.unit,
+                    lint: update.lint,
+                    ..params.clone()
+                };
+                if let Err(e) = send_update(
+                    client,
+                    &mut sent,
+                    &update,
+                    args.chunk_size,
+                    args.concurrency,
+                )
+                .await
+                {
+                    warn!(
+                        "Failed to send update, retrying on the next change: {:?}",
+                        e
+                    );
+                }
+                if !attached.unsent(&omitted).is_empty() {
+                    artifacts += 1;
+                    let key = match artifacts {
+                        1 => "lints".to_string(),
+                        n => format!("lints-{}", n),
+                    };
+                    match submit::attach_lints(client, &update, &omitted, &key).await {
+                        Ok(()) => attached.mark(&omitted),
+                        Err(e) => warn!("Failed to attach the omitted lints: {:?}", e),
+                    }
+                }
+                last = report;
+            }
+            Err(e) => warn!("Failed to parse the inputs: {:?}", e),
+        }
+        loop {
+            tokio::select! {
+                Ok(()) = &mut stopped => {
+                    // Resolve `--status auto` again, with the last results
+                    let requested = args.status.unwrap_or(params.status);
+                    let status = crate::resolve_status(requested, &last.lints, &last.units, last.metadata.ice);
+                    info!("Sending the final status ({:?})", status);
+                    let done = Params {
+                        status,
+                        unit: None,
+                        lint: None,
+                        ..params
+                    };
+                    return submit::send(client, &done, None, 1).await;
+                }
+                Some(event) = rx.recv() => {
+                    if event.paths.iter().any(|p| inputs.contains(p)) {
+                        break;
+                    }
+                }
+            }
+        }
+        // Debounce, as the inputs are usually written progressively
+        tokio::time::sleep(Duration::from_millis(500)).await;
+        while rx.try_recv().is_ok() {}
+    }
+}
+
+/// Send the results of an update which were not sent yet, in a `work` message. They are only
+/// recorded as sent once acknowledged, to be sent again after a failure.
+async fn send_update(
+    client: &Client,
+    sent: &mut cache::Sent,
+    update: &Params,
+    chunk_size: Option<usize>,
+    concurrency: usize,
+) -> anyhow::Result<()> {
+    let units = sent.unsent(update.unit.as_deref().unwrap_or_default());
+    let lints = sent.unsent(update.lint.as_deref().unwrap_or_default());
+    if units.is_empty() && lints.is_empty() {
+        return Ok(());
+    }
+    info!(
+        "Sending {} new tests and {} new lints",
+        units.len(),
+        lints.len()
+    );
+    let update = Params {
+        status: Status::Work,
+        unit: Some(units),
+        lint: Some(lints),
+        ..update.clone()
+    };
+    submit::send(client, &update, chunk_size, concurrency).await?;
+    sent.mark(update.unit.as_deref().unwrap_or_default());
+    sent.mark(update.lint.as_deref().unwrap_or_default());
+    Ok(())
+}
+
+/// Parse the inputs again, and build the message with the same budgets, limits and validation as
+/// a single run, returning it with the report and the lints omitted by `--max-lints`
+fn prepare(
+    args: &Flags,
+    config: &config::Config,
+) -> anyhow::Result<(Params, Report, Vec<LintResult>)> {
+    let cwd = std::env::current_dir()?;
+    let mut report = crate::parse_all(args, config, &cwd)?;
+    report
+        .lints
+        .extend(budget::check(&cwd, &args.max_warnings, &report.lints)?);
+    let omitted = crate::apply_limits(args, &mut report.lints, &mut report.units);
+    let params = crate::harbormaster_sink(args, &report)?.params(&report);
+    schema::validate(&params)?;
+    params.validate()?;
+    Ok((params, report, omitted))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::conduit::tests::{client, ok, server};
+    use crate::report::tests::{lint, unit};
+    use crate::Auth;
+    use hyper::StatusCode;
+    use std::sync::{Arc, Mutex};
+
+    #[tokio::test]
+    async fn resend() {
+        let messages = Arc::new(Mutex::new(Vec::new()));
+        let uri = server({
+            let messages = messages.clone();
+            move |method, params| {
+                if method != "harbormaster.sendmessage" {
+                    return ok(serde_json::json!({}));
+                }
+                let mut messages = messages.lock().unwrap();
+                messages.push(params);
+                if messages.len() == 1 {
+                    let error = serde_json::json!({
+                        "result": null,
+                        "error_code": "ERR-CONDUIT-CORE",
+                        "error_info": "Database connection lost",
+                    });
+                    (StatusCode::INTERNAL_SERVER_ERROR, error)
+                } else {
+                    ok(serde_json::Value::Null)
+                }
+            }
+        });
+        let client = client(&uri);
+        let update = Params {
+            build: "PHID-HMBT-1".into(),
+            status: Status::Pass,
+            unit: Some(vec![unit("a", "pass")]),
+            lint: Some(vec![lint("error", "src/lib.rs", Some(1))]),
+            auth: Auth {
+                token: "api-token".into(),
+            },
+        };
+        let mut sent = cache::Sent::default();
+        assert!(send_update(&client, &mut sent, &update, None, 1)
+            .await
+            .is_err());
+        // The failed results are sent again
+        send_update(&client, &mut sent, &update, None, 1)
+            .await
+            .unwrap();
+        {
+            let messages = messages.lock().unwrap();
+            assert_eq!(messages.len(), 2);
+            assert_eq!(messages[0]["unit"], messages[1]["unit"]);
+            assert_eq!(messages[0]["lint"], messages[1]["lint"]);
+            assert_eq!(messages[1]["type"], "work");
+            assert_eq!(messages[1]["unit"][0]["name"], "a");
+        }
+        // Nothing new to send
+        send_update(&client, &mut sent, &update, None, 1)
+            .await
+            .unwrap();
+        assert_eq!(messages.lock().unwrap().len(), 2);
+        // Only the new results are sent
+        let mut update = update;
+        update.unit.as_mut().unwrap().push(unit("b", "fail"));
+        send_update(&client, &mut sent, &update, None, 1)
+            .await
+            .unwrap();
+        let messages = messages.lock().unwrap();
+        assert_eq!(messages.len(), 3);
+        assert_eq!(messages[2]["unit"].as_array().unwrap().len(), 1);
+        assert_eq!(messages[2]["unit"][0]["name"], "b");
+        assert!(messages[2]["lint"].as_array().unwrap().is_empty());
+    }
+}